@@ -0,0 +1,600 @@
+use std::{
+    collections::BTreeMap,
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
+};
+
+use anyhow::{bail, Context, Result};
+use dhcproto::{v4, v6, Encodable};
+
+use crate::util::Msg;
+
+/// v4 Authentication option (RFC 3118)
+pub const V4_AUTH: u8 = 90;
+/// v4 Forcerenew Nonce Capable option (RFC 6704)
+pub const FORCERENEW_NONCE_CAPABLE: u8 = 145;
+
+/// delayed authentication, v4 (RFC 3118 section 5)
+const V4_DELAYED: u8 = 1;
+/// delayed authentication, v6 (RFC 3315 section 21.4)
+const V6_DELAYED: u8 = 2;
+/// reconfigure key (v6, RFC 8415 section 20.4) & forcerenew nonce (v4,
+/// RFC 6704) authentication share a protocol number & format
+const KEY_PROTOCOL: u8 = 3;
+/// HMAC-MD5 algorithm
+pub const HMAC_MD5: u8 = 1;
+/// monotonically increasing counter replay detection
+const RDM_COUNTER: u8 = 0;
+/// auth info types: the key sent in a reply & the HMAC of a later message
+const KEY_VALUE: u8 = 1;
+const KEY_HMAC: u8 = 2;
+/// offset of the digest in key auth option data, after the info type
+const KEY_DIGEST: usize = AUTH_HEADER + 1;
+
+/// v4 fixed header fields zeroed for the HMAC (RFC 3118 section 5)
+const V4_HOPS: usize = 3;
+const V4_GIADDR: Range<usize> = 24..28;
+const V4_OPTS_START: usize = 240;
+
+const MD5_BLOCK: usize = 64;
+/// protocol, algorithm, rdm & replay detection
+const AUTH_HEADER: usize = 11;
+
+/// replay detection counter, incremented for each authenticated message sent
+static REPLAY: AtomicU64 = AtomicU64::new(0);
+
+/// the last replay detection value accepted from each server
+pub static REPLIES: Replays = Replays::new();
+
+/// replay detection values of authenticated replies, by server identifier
+#[derive(Debug, Default)]
+pub struct Replays(Mutex<BTreeMap<Vec<u8>, u64>>);
+
+impl Replays {
+    pub const fn new() -> Self {
+        Self(Mutex::new(BTreeMap::new()))
+    }
+
+    /// accept `replay` from `server` if it's greater than the last one
+    fn check(&self, server: Vec<u8>, replay: u64) -> Result<()> {
+        let mut last = self.0.lock().unwrap();
+        match last.get(&server) {
+            Some(&last) if replay <= last => {
+                bail!("reply replay detection {replay} isn't greater than {last}")
+            }
+            _ => {
+                last.insert(server, replay);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// a shared secret for delayed authentication
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Key {
+    pub id: u32,
+    pub secret: Vec<u8>,
+}
+
+/// parse "<key id>:<hex secret>"
+pub fn parse_key(key: &str) -> Result<Key, String> {
+    let (id, secret) = key
+        .split_once(':')
+        .ok_or_else(|| format!("auth key {key:?} must be \"<key id>:<hex secret>\""))?;
+    Ok(Key {
+        id: id
+            .parse()
+            .map_err(|err| format!("invalid key id {id:?}: {err}"))?,
+        secret: hex::decode(secret).map_err(|err| format!("invalid secret: {err}"))?,
+    })
+}
+
+/// next replay detection value, starting from `start` or the current time
+/// in nanoseconds so values keep increasing across runs
+fn next_replay(start: Option<u64>) -> u64 {
+    let _ = REPLAY.compare_exchange(
+        0,
+        start.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(1)
+        }),
+        Ordering::SeqCst,
+        Ordering::SeqCst,
+    );
+    REPLAY.fetch_add(1, Ordering::SeqCst)
+}
+
+/// HMAC-MD5 (RFC 2104)
+pub fn hmac_md5(key: &[u8], data: &[u8]) -> [u8; 16] {
+    let mut block = [0; MD5_BLOCK];
+    if key.len() > MD5_BLOCK {
+        block[..16].copy_from_slice(&md5::compute(key).0);
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = md5::Context::new();
+    inner.consume(block.map(|b| b ^ 0x36));
+    inner.consume(data);
+    let mut outer = md5::Context::new();
+    outer.consume(block.map(|b| b ^ 0x5c));
+    outer.consume(inner.compute().0);
+    outer.compute().0
+}
+
+/// the fields of an Authentication option, v4 opt 90 or v6 opt 11
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Auth {
+    proto: u8,
+    algo: u8,
+    rdm: u8,
+    replay_detection: u64,
+    info: Vec<u8>,
+}
+
+impl Auth {
+    fn v4(msg: &v4::Message) -> Option<Self> {
+        match msg.opts().get(v4::OptionCode::Unknown(V4_AUTH)) {
+            Some(v4::DhcpOption::Unknown(opt)) if opt.data().len() >= 11 => {
+                let data = opt.data();
+                Some(Self {
+                    proto: data[0],
+                    algo: data[1],
+                    rdm: data[2],
+                    replay_detection: u64::from_be_bytes(data[3..11].try_into().unwrap()),
+                    info: data[AUTH_HEADER..].to_vec(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn v6(msg: &v6::Message) -> Option<Self> {
+        match msg.opts().get(v6::OptionCode::Authentication) {
+            Some(v6::DhcpOption::Authentication(auth)) => Some(Self {
+                proto: auth.proto,
+                algo: auth.algo,
+                rdm: auth.rdm,
+                replay_detection: auth.replay_detection,
+                info: auth.info.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// the key & replay detection value sent by the server in a reply
+    fn key(&self) -> Option<(u64, [u8; 16])> {
+        if self.proto == KEY_PROTOCOL && self.info.len() == 17 && self.info[0] == KEY_VALUE {
+            Some((self.replay_detection, self.info[1..].try_into().unwrap()))
+        } else {
+            None
+        }
+    }
+
+    /// check a message authenticated with a key from an earlier reply,
+    /// `zeroed` is the raw message with the digest (& any other fields
+    /// excluded from the HMAC) set to 0
+    fn check_key_hmac(
+        &self,
+        name: &str,
+        zeroed: &[u8],
+        key: &[u8; 16],
+        last_replay: u64,
+    ) -> Result<()> {
+        if self.proto != KEY_PROTOCOL || self.algo != HMAC_MD5 || self.rdm != RDM_COUNTER {
+            bail!(
+                "{name} auth is protocol {} algorithm {} rdm {}, expected HMAC-MD5 key auth (3, 1, 0)",
+                self.proto,
+                self.algo,
+                self.rdm
+            );
+        }
+        if self.info.len() != 17 || self.info[0] != KEY_HMAC {
+            bail!("{name} auth info isn't an HMAC-MD5 digest");
+        }
+        if self.replay_detection <= last_replay {
+            bail!(
+                "{name} replay detection {} isn't greater than {last_replay}",
+                self.replay_detection
+            );
+        }
+        if hmac_md5(key, zeroed)[..] != self.info[1..] {
+            bail!("{name} HMAC-MD5 digest doesn't match the key");
+        }
+        Ok(())
+    }
+}
+
+/// The reconfigure key & replay detection value a server sent in the
+/// Authentication option of a REPLY
+pub fn reconfigure_key(msg: &v6::Message) -> Option<(u64, [u8; 16])> {
+    Auth::v6(msg)?.key()
+}
+
+/// The forcerenew nonce & replay detection value a server sent in opt 90
+/// of an ACK (RFC 6704 section 3.3)
+pub fn forcerenew_nonce(msg: &v4::Message) -> Option<(u64, [u8; 16])> {
+    Auth::v4(msg)?.key()
+}
+
+/// Validate the Authentication option of a RECONFIGURE: the HMAC-MD5 of the
+/// whole message with the digest zeroed must match, using the key from
+/// the REPLY, & the replay detection value must be greater than `last_replay`
+pub fn check_reconfigure(
+    raw: &[u8],
+    msg: &v6::Message,
+    key: &[u8; 16],
+    last_replay: u64,
+) -> Result<()> {
+    let auth = Auth::v6(msg).context("RECONFIGURE has no Authentication option")?;
+    let start = find_v6_opt(raw, v6::OptionCode::Authentication.into())
+        .context("Authentication option not found in the raw message")?;
+    let mut zeroed = raw.to_vec();
+    zero_digest(&mut zeroed, start, KEY_DIGEST)?;
+    auth.check_key_hmac("RECONFIGURE", &zeroed, key, last_replay)
+}
+
+/// Validate the Authentication option of a FORCERENEW against the nonce
+/// from the ACK, hops & giaddr are zeroed along with the digest
+pub fn check_forcerenew(
+    raw: &[u8],
+    msg: &v4::Message,
+    nonce: &[u8; 16],
+    last_replay: u64,
+) -> Result<()> {
+    let auth = Auth::v4(msg).context("FORCERENEW has no Authentication option 90")?;
+    let start = find_v4_opt(raw, V4_AUTH).context("opt 90 not found in the raw message")?;
+    let mut zeroed = raw.to_vec();
+    zero_digest(&mut zeroed, start, KEY_DIGEST)?;
+    zeroed[V4_HOPS] = 0;
+    zeroed[V4_GIADDR].fill(0);
+    auth.check_key_hmac("FORCERENEW", &zeroed, nonce, last_replay)
+}
+
+/// Add delayed authentication to an outgoing v4 message before it's packed
+/// with opt 52. A DISCOVER only asks for authentication, other messages get
+/// a zeroed MAC that [`sign`] fills in (RFC 3118 section 5.3)
+pub fn insert_v4(msg: &mut v4::Message, key: &Key, replay: Option<u64>) {
+    let mut data = vec![V4_DELAYED, HMAC_MD5, RDM_COUNTER];
+    data.extend(next_replay(replay).to_be_bytes());
+    if msg.opts().msg_type() != Some(v4::MessageType::Discover) {
+        data.extend(key.id.to_be_bytes());
+        data.extend([0; 16]);
+    }
+    msg.opts_mut()
+        .insert(v4::DhcpOption::Unknown(v4::UnknownOption::new(
+            V4_AUTH.into(),
+            data,
+        )));
+}
+
+/// Add delayed authentication to an outgoing v6 message, a SOLICIT or
+/// INFORMATION-REQUEST only asks for authentication (RFC 3315 section 21.4.4).
+/// Any Authentication option already in the message (ex. from `-o`) is replaced
+fn insert_v6(msg: &mut v6::Message, key: &Key, realm: &str, replay: Option<u64>) {
+    let mut info = vec![];
+    if !matches!(
+        msg.msg_type(),
+        v6::MessageType::Solicit | v6::MessageType::InformationRequest
+    ) {
+        info.extend(realm.as_bytes());
+        info.extend(key.id.to_be_bytes());
+        info.extend([0; 16]);
+    }
+    // v6 options aren't replaced on insert
+    msg.opts_mut().remove_all(v6::OptionCode::Authentication);
+    msg.opts_mut()
+        .insert(v6::DhcpOption::Authentication(v6::Authentication {
+            proto: V6_DELAYED,
+            algo: HMAC_MD5,
+            rdm: RDM_COUNTER,
+            replay_detection: next_replay(replay),
+            info,
+        }));
+}
+
+/// Compute the HMAC-MD5 over the encoded message & fill it in, v4 messages
+/// must already have the option from [`insert_v4`]
+pub fn sign(msg: &mut Msg, key: &Key, realm: &str, replay: Option<u64>) -> Result<()> {
+    match msg {
+        Msg::V4(msg) => {
+            let mut data = match msg.opts().get(v4::OptionCode::Unknown(V4_AUTH)) {
+                Some(v4::DhcpOption::Unknown(opt)) => opt.data().to_vec(),
+                _ => bail!("opt 90 doesn't fit in the options field, try a larger --max-size"),
+            };
+            // only asking for authentication
+            if data.len() == AUTH_HEADER {
+                return Ok(());
+            }
+            let mut raw = msg.to_vec()?;
+            raw[V4_HOPS] = 0;
+            raw[V4_GIADDR].fill(0);
+            let mac = hmac_md5(&key.secret, &raw);
+            let len = data.len();
+            data[len - 16..].copy_from_slice(&mac);
+            msg.opts_mut()
+                .insert(v4::DhcpOption::Unknown(v4::UnknownOption::new(
+                    V4_AUTH.into(),
+                    data,
+                )));
+        }
+        Msg::V6(msg) => {
+            insert_v6(msg, key, realm, replay);
+            let raw = msg.to_vec()?;
+            // v6 options aren't replaced on insert, fill in the MAC in place
+            if let Some(v6::DhcpOption::Authentication(auth)) =
+                msg.opts_mut().get_mut(v6::OptionCode::Authentication)
+            {
+                // only asking for authentication
+                if auth.info.is_empty() {
+                    return Ok(());
+                }
+                let len = auth.info.len();
+                auth.info[len - 16..].copy_from_slice(&hmac_md5(&key.secret, &raw));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Verify the delayed authentication of a reply: the protocol, key id (&
+/// realm for v6) must match ours, the HMAC-MD5 must validate with the secret
+/// & the replay detection value must be greater than the last one `replays`
+/// accepted from the same server
+pub fn check_reply(raw: &[u8], msg: &Msg, key: &Key, realm: &str, replays: &Replays) -> Result<()> {
+    let (auth, start, proto, prefix, server) = match msg {
+        Msg::V4(msg) => (
+            Auth::v4(msg).context("reply has no authentication opt 90")?,
+            find_v4_opt(raw, V4_AUTH).context("opt 90 not found in the raw reply")?,
+            V4_DELAYED,
+            0,
+            match msg.opts().get(v4::OptionCode::ServerIdentifier) {
+                Some(v4::DhcpOption::ServerIdentifier(ip)) => ip.octets().to_vec(),
+                _ => vec![],
+            },
+        ),
+        Msg::V6(msg) => (
+            Auth::v6(msg).context("reply has no Authentication option")?,
+            find_v6_opt(raw, v6::OptionCode::Authentication.into())
+                .context("Authentication option not found in the raw reply")?,
+            V6_DELAYED,
+            realm.len(),
+            match msg.opts().get(v6::OptionCode::ServerId) {
+                Some(v6::DhcpOption::ServerId(id)) => id.clone(),
+                _ => vec![],
+            },
+        ),
+    };
+    if auth.proto != proto || auth.algo != HMAC_MD5 || auth.rdm != RDM_COUNTER {
+        bail!(
+            "reply auth is protocol {} algorithm {} rdm {}, expected delayed auth HMAC-MD5 ({proto}, 1, 0)",
+            auth.proto,
+            auth.algo,
+            auth.rdm
+        );
+    }
+    if auth.info.len() != prefix + 20 {
+        bail!(
+            "reply auth info is {} bytes, expected {}",
+            auth.info.len(),
+            prefix + 20
+        );
+    }
+    if auth.info[..prefix] != *realm.as_bytes() {
+        bail!(
+            "reply auth realm {:?} doesn't match ours",
+            String::from_utf8_lossy(&auth.info[..prefix])
+        );
+    }
+    let id = u32::from_be_bytes(auth.info[prefix..prefix + 4].try_into().unwrap());
+    if id != key.id {
+        bail!("reply auth key id {id} doesn't match ours ({})", key.id);
+    }
+    let mut zeroed = raw.to_vec();
+    zero_digest(&mut zeroed, start, AUTH_HEADER + prefix + 4)?;
+    if let Msg::V4(_) = msg {
+        zeroed[V4_HOPS] = 0;
+        zeroed[V4_GIADDR].fill(0);
+    }
+    if hmac_md5(&key.secret, &zeroed)[..] != auth.info[prefix + 4..] {
+        bail!("reply HMAC-MD5 doesn't match the shared secret");
+    }
+    replays.check(server, auth.replay_detection)
+}
+
+/// zero the 16 byte digest at `offset` in the auth option data at `start`
+fn zero_digest(raw: &mut [u8], start: usize, offset: usize) -> Result<()> {
+    let digest = start + offset..start + offset + 16;
+    raw.get_mut(digest)
+        .context("Authentication option too short")?
+        .fill(0);
+    Ok(())
+}
+
+/// offset of the data of the first option with `code` in a raw v4 message
+fn find_v4_opt(raw: &[u8], code: u8) -> Option<usize> {
+    let mut i = V4_OPTS_START;
+    while i < raw.len() {
+        match raw[i] {
+            // pad
+            0 => i += 1,
+            // end
+            255 => return None,
+            opt => {
+                let len = *raw.get(i + 1)? as usize;
+                if opt == code {
+                    return Some(i + 2);
+                }
+                i += 2 + len;
+            }
+        }
+    }
+    None
+}
+
+/// offset of the data of the first option with `code` in a raw v6 message
+fn find_v6_opt(raw: &[u8], code: u16) -> Option<usize> {
+    // msg-type & transaction-id
+    let mut i = 4;
+    while i + 4 <= raw.len() {
+        let opt = u16::from_be_bytes([raw[i], raw[i + 1]]);
+        let len = u16::from_be_bytes([raw[i + 2], raw[i + 3]]) as usize;
+        if i + 4 + len > raw.len() {
+            return None;
+        }
+        if opt == code {
+            return Some(i + 4);
+        }
+        i += 4 + len;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 2202 section 2
+    #[test]
+    fn hmac_md5_rfc2202() {
+        let cases: [(Vec<u8>, Vec<u8>, &str); 5] = [
+            (
+                vec![0x0b; 16],
+                b"Hi There".to_vec(),
+                "9294727a3638bb1c13f48ef8158bfc9d",
+            ),
+            (
+                b"Jefe".to_vec(),
+                b"what do ya want for nothing?".to_vec(),
+                "750c783e6ab0b503eaa86e310a5db738",
+            ),
+            (
+                vec![0xaa; 16],
+                vec![0xdd; 50],
+                "56be34521d144c88dbb8c733f0e8b3f6",
+            ),
+            (
+                (1..=25).collect(),
+                vec![0xcd; 50],
+                "697eaf0aca3a3aea3a75164746ffaa79",
+            ),
+            // key longer than the block size is hashed first
+            (
+                vec![0xaa; 80],
+                b"Test Using Larger Than Block-Size Key - Hash Key First".to_vec(),
+                "6b1ab7fe4bd7bf8f0b62e6ce61b9d0cd",
+            ),
+        ];
+        for (key, data, digest) in cases {
+            assert_eq!(hex::encode(hmac_md5(&key, &data)), digest);
+        }
+    }
+
+    fn key() -> Key {
+        parse_key("7:00112233445566778899aabbccddeeff").unwrap()
+    }
+
+    #[test]
+    fn parse_keys() {
+        assert_eq!(
+            key(),
+            Key {
+                id: 7,
+                secret: hex::decode("00112233445566778899aabbccddeeff").unwrap(),
+            }
+        );
+        assert!(parse_key("00112233").is_err());
+        assert!(parse_key("x:0011").is_err());
+        assert!(parse_key("7:zz").is_err());
+    }
+
+    #[test]
+    fn sign_check_v4() {
+        let mut msg = v4::Message::default();
+        msg.opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Request));
+        insert_v4(&mut msg, &key(), None);
+        let mut msg = Msg::V4(msg);
+        sign(&mut msg, &key(), "", None).unwrap();
+        let mut raw = msg.to_vec().unwrap();
+        // hops & giaddr aren't covered so relays can change them
+        raw[V4_HOPS] = 1;
+        raw[V4_GIADDR].copy_from_slice(&[10, 0, 0, 1]);
+        let replays = Replays::new();
+        check_reply(&raw, &msg, &key(), "", &replays).unwrap();
+        // replayed
+        assert!(check_reply(&raw, &msg, &key(), "", &replays).is_err());
+
+        let other = Key {
+            id: 7,
+            secret: vec![0; 16],
+        };
+        assert!(check_reply(&raw, &msg, &other, "", &Replays::new()).is_err());
+        let other = Key { id: 8, ..key() };
+        assert!(check_reply(&raw, &msg, &other, "", &Replays::new()).is_err());
+        // ciaddr
+        raw[12] = 1;
+        assert!(check_reply(&raw, &msg, &key(), "", &Replays::new()).is_err());
+    }
+
+    #[test]
+    fn sign_check_v6() {
+        let mut msg = Msg::V6(v6::Message::new(v6::MessageType::Request));
+        sign(&mut msg, &key(), "realm", None).unwrap();
+        let raw = msg.to_vec().unwrap();
+        check_reply(&raw, &msg, &key(), "realm", &Replays::new()).unwrap();
+        assert!(check_reply(&raw, &msg, &key(), "other", &Replays::new()).is_err());
+        // an Authentication option from -o is replaced
+        let mut msg = v6::Message::new(v6::MessageType::Request);
+        msg.opts_mut()
+            .insert(v6::DhcpOption::Authentication(v6::Authentication {
+                proto: 0,
+                algo: 0,
+                rdm: 0,
+                replay_detection: 0,
+                info: vec![],
+            }));
+        let mut msg = Msg::V6(msg);
+        sign(&mut msg, &key(), "realm", None).unwrap();
+        let raw = msg.to_vec().unwrap();
+        let Msg::V6(v6_msg) = &msg else {
+            unreachable!()
+        };
+        assert_eq!(
+            v6_msg
+                .opts()
+                .iter()
+                .filter(|opt| matches!(opt, v6::DhcpOption::Authentication(_)))
+                .count(),
+            1
+        );
+        check_reply(&raw, &msg, &key(), "realm", &Replays::new()).unwrap();
+    }
+
+    #[test]
+    fn solicit_asks_for_auth() {
+        let mut msg = Msg::V6(v6::Message::new(v6::MessageType::Solicit));
+        sign(&mut msg, &key(), "realm", None).unwrap();
+        let Msg::V6(msg) = msg else { unreachable!() };
+        let auth = Auth::v6(&msg).unwrap();
+        assert_eq!((auth.proto, auth.algo), (V6_DELAYED, HMAC_MD5));
+        assert!(auth.info.is_empty());
+    }
+
+    #[test]
+    fn replays() {
+        let replays = Replays::new();
+        replays.check(vec![1], 5).unwrap();
+        assert!(replays.check(vec![1], 5).is_err());
+        assert!(replays.check(vec![1], 4).is_err());
+        // tracked per server
+        replays.check(vec![2], 1).unwrap();
+        replays.check(vec![1], 6).unwrap();
+    }
+}
@@ -2,22 +2,29 @@ use std::net::Ipv4Addr;
 
 use argh::FromArgs;
 use dhcproto::v4;
-use mac_address::MacAddress;
 
-use crate::opts::{self, parse_mac, parse_opts};
+use crate::{
+    opts::{self, parse_htype, parse_mac, parse_opts, Chaddr},
+    tftp,
+};
 
 #[derive(FromArgs, PartialEq, Eq, Debug, Clone)]
 /// Send a DISCOVER msg
 #[argh(subcommand, name = "bootreq")]
 pub struct BootReqArgs {
-    /// supply a mac address for DHCPv4 (use "random" for a random mac) [default: first interface mac]
+    /// supply a hardware address of up to 16 bytes for DHCPv4 (use "random" for a random mac,
+    /// "none" for hlen 0) [default: first interface mac]
     #[argh(
         option,
         short = 'c',
         from_str_fn(parse_mac),
         default = "opts::get_mac()"
     )]
-    pub chaddr: MacAddress,
+    pub chaddr: Chaddr,
+    /// hardware type, a number or "eth", "ieee802", "ieee1394" or "infiniband", hlen is the
+    /// length of chaddr [default: eth]
+    #[argh(option, from_str_fn(parse_htype), default = "v4::HType::Eth")]
+    pub htype: v4::HType,
     /// address of client [default: None]
     #[argh(option, default = "Ipv4Addr::UNSPECIFIED")]
     pub ciaddr: Ipv4Addr,
@@ -34,17 +41,30 @@ pub struct BootReqArgs {
     /// [ex: "118,hex,C0A80001" or "118,ip,192.168.0.1" or "60,str,foobar"]
     #[argh(option, short = 'o', from_str_fn(parse_opts))]
     pub opt: Vec<v4::DhcpOption>,
+    /// fetch the offered boot file over TFTP and report its size, sha256 & transfer time [default: false]
+    #[argh(switch)]
+    pub tftp: bool,
+    /// TFTP port to use with --tftp [default: 69]
+    #[argh(option, default = "tftp::TFTP_PORT")]
+    pub tftp_port: u16,
+    /// TFTP blksize option to ask for (RFC 2348) [default: 1468]
+    #[argh(option, default = "tftp::DEFAULT_BLKSIZE")]
+    pub tftp_blksize: u16,
 }
 
 impl Default for BootReqArgs {
     fn default() -> Self {
         Self {
             chaddr: opts::get_mac(),
+            htype: v4::HType::Eth,
             ciaddr: Ipv4Addr::UNSPECIFIED,
             giaddr: Ipv4Addr::UNSPECIFIED,
             opt: Vec::new(),
             fname: None,
             sname: None,
+            tftp: false,
+            tftp_port: tftp::TFTP_PORT,
+            tftp_blksize: tftp::DEFAULT_BLKSIZE,
         }
     }
 }
@@ -56,10 +76,11 @@ impl BootReqArgs {
             Ipv4Addr::UNSPECIFIED,
             Ipv4Addr::UNSPECIFIED,
             self.giaddr,
-            &self.chaddr.bytes(),
+            self.chaddr.bytes(),
         );
+        msg.set_htype(self.htype);
 
-        if broadcast {
+        if broadcast || opts::needs_broadcast(self.htype) {
             msg.set_flags(v4::Flags::default().set_broadcast());
         }
 
@@ -124,8 +145,17 @@ pub mod bootreq_mod {
     #[rhai_fn(global, set = "chaddr")]
     pub fn set_chaddr(args: &mut BootReqArgs, chaddr: rhai::Blob) {
         trace!(?chaddr, "setting chaddr");
-        let bytes: [u8; 6] = chaddr.try_into().expect("failed to convert macaddress");
-        args.chaddr = bytes.into();
+        args.chaddr = Chaddr::try_from(&chaddr[..]).expect("failed to convert chaddr");
+    }
+    // htype
+    #[rhai_fn(global, get = "htype", pure)]
+    pub fn get_htype(args: &mut BootReqArgs) -> i64 {
+        u8::from(args.htype) as i64
+    }
+    #[rhai_fn(global, set = "htype")]
+    pub fn set_htype(args: &mut BootReqArgs, htype: i64) {
+        trace!(?htype, "setting htype");
+        args.htype = u8::try_from(htype).expect("htype must fit in a u8").into();
     }
     // fname
     #[rhai_fn(global, get = "fname", pure)]
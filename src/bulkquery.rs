@@ -0,0 +1,307 @@
+use std::{
+    net::Ipv4Addr,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use argh::FromArgs;
+use dhcproto::{
+    v4::{self, bulk_query, relay},
+    Encodable,
+};
+use serde::Serialize;
+use tracing::{debug, info, warn};
+
+use crate::{
+    opts::{parse_mac, parse_opts, Chaddr, ParamList},
+    overload, tcp,
+    util::{Msg, PrettyPrint, PrettyTime},
+    Args,
+};
+
+/// opt 82 sub-opt for the relay identifier (RFC 6925)
+const RELAY_ID: u8 = 12;
+
+#[derive(FromArgs, PartialEq, Eq, Debug, Clone)]
+/// Send a BULKLEASEQUERY over TCP (RFC 6926), printing leases as JSON lines
+#[argh(subcommand, name = "bulk-leasequery")]
+pub struct BulkLeaseQueryArgs {
+    /// query by relay id, opt 82 sub-opt 12 as hex
+    #[argh(option, from_str_fn(parse_hex))]
+    pub relay_id: Option<Vec<u8>>,
+    /// query by remote id, opt 82 sub-opt 2 as hex
+    #[argh(option, from_str_fn(parse_hex))]
+    pub remote_id: Option<Vec<u8>>,
+    /// query all leases on a link, opt 82 sub-opt 5 link selection
+    #[argh(option)]
+    pub link: Option<Ipv4Addr>,
+    /// query by IP address (set in ciaddr)
+    #[argh(option)]
+    pub ip: Option<Ipv4Addr>,
+    /// query by MAC address (set in chaddr)
+    #[argh(option, from_str_fn(parse_mac))]
+    pub mac: Option<Chaddr>,
+    /// query by client identifier opt 61, as hex
+    #[argh(option, from_str_fn(parse_hex))]
+    pub client_id: Option<Vec<u8>>,
+    /// only leases that changed state at or after this time (opt 154, seconds since epoch)
+    #[argh(option)]
+    pub start_time: Option<u32>,
+    /// only leases that changed state at or before this time (opt 155, seconds since epoch)
+    #[argh(option)]
+    pub end_time: Option<u32>,
+    /// add opts to the message
+    /// [ex: these are equivalent- "118,hex,C0A80001" or "118,ip,192.168.0.1"]
+    #[argh(option, short = 'o', from_str_fn(parse_opts))]
+    pub opt: Vec<v4::DhcpOption>,
+    /// params to include: [default: 51,61,82,91,92,152,153,156,157]
+    #[argh(option, default = "default_params()")]
+    pub params: ParamList,
+}
+
+fn default_params() -> ParamList {
+    "51,61,82,91,92,152,153,156,157"
+        .parse()
+        .expect("valid params")
+}
+
+fn parse_hex(id: &str) -> Result<Vec<u8>, String> {
+    hex::decode(id.replace(':', "")).map_err(|err| err.to_string())
+}
+
+/// a lease from a LEASEACTIVE/LEASEUNASSIGNED reply, printed as a JSON line
+#[derive(Serialize)]
+struct Lease<'a> {
+    state: String,
+    ip: Ipv4Addr,
+    chaddr: String,
+    client_id: Option<String>,
+    lease_time: Option<u32>,
+    base_time: Option<u32>,
+    start_time_of_state: Option<u32>,
+    dhcp_state: Option<bulk_query::QueryState>,
+    opts: &'a v4::DhcpOptions,
+}
+
+impl<'a> Lease<'a> {
+    fn new(msg: &'a v4::Message) -> Self {
+        use v4::DhcpOption as O;
+        let opts = msg.opts();
+        let u32_opt = |code| match opts.get(code) {
+            Some(O::AddressLeaseTime(t))
+            | Some(O::BulkLeaseQueryBaseTime(t))
+            | Some(O::BulkLeasQueryStartTimeOfState(t)) => Some(*t),
+            _ => None,
+        };
+        Self {
+            state: opts
+                .msg_type()
+                .map(|ty| format!("{ty:?}"))
+                .unwrap_or_default(),
+            ip: msg.ciaddr(),
+            chaddr: hex::encode(&msg.chaddr()[..(msg.hlen() as usize).min(16)]),
+            client_id: match opts.get(v4::OptionCode::ClientIdentifier) {
+                Some(O::ClientIdentifier(id)) => Some(hex::encode(id)),
+                _ => None,
+            },
+            lease_time: u32_opt(v4::OptionCode::AddressLeaseTime),
+            base_time: u32_opt(v4::OptionCode::BulkLeaseQueryBaseTime),
+            start_time_of_state: u32_opt(v4::OptionCode::BulkLeasQueryStartTimeOfState),
+            dhcp_state: match opts.get(v4::OptionCode::BulkLeaseQueryDhcpState) {
+                Some(O::BulkLeaseQueryDhcpState(state)) => Some(*state),
+                _ => None,
+            },
+            opts,
+        }
+    }
+}
+
+impl BulkLeaseQueryArgs {
+    pub fn build(&self) -> Result<v4::Message> {
+        let queries = [
+            self.relay_id.is_some(),
+            self.remote_id.is_some(),
+            self.link.is_some(),
+            self.ip.is_some(),
+            self.mac.is_some(),
+            self.client_id.is_some(),
+        ];
+        if queries.iter().filter(|q| **q).count() != 1 {
+            bail!("bulk-leasequery needs exactly one of --relay-id, --remote-id, --link, --ip, --mac or --client-id");
+        }
+        let mut msg = v4::Message::new(
+            self.ip.unwrap_or(Ipv4Addr::UNSPECIFIED),
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            // htype, hlen & chaddr must be 0 unless querying by MAC
+            &self.mac.map(|mac| mac.bytes().to_vec()).unwrap_or_default(),
+        );
+        if self.mac.is_none() {
+            msg.set_htype(0.into());
+        }
+        msg.opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::BulkLeaseQuery));
+        if let Some(id) = &self.client_id {
+            msg.opts_mut()
+                .insert(v4::DhcpOption::ClientIdentifier(id.clone()));
+        }
+        let mut info = relay::RelayAgentInformation::default();
+        if let Some(id) = &self.relay_id {
+            info.insert(relay::RelayInfo::Unknown(relay::UnknownInfo::new(
+                RELAY_ID.into(),
+                id.clone(),
+            )));
+        }
+        if let Some(id) = &self.remote_id {
+            info.insert(relay::RelayInfo::AgentRemoteId(id.clone()));
+        }
+        if let Some(link) = self.link {
+            info.insert(relay::RelayInfo::LinkSelection(link));
+        }
+        if info.iter().next().is_some() {
+            msg.opts_mut()
+                .insert(v4::DhcpOption::RelayAgentInformation(info));
+        }
+        if let Some(t) = self.start_time {
+            msg.opts_mut()
+                .insert(v4::DhcpOption::BulkLeaseQueryQueryStartTime(t));
+        }
+        if let Some(t) = self.end_time {
+            msg.opts_mut()
+                .insert(v4::DhcpOption::BulkLeaseQueryQueryEndTime(t));
+        }
+        msg.opts_mut()
+            .insert(v4::DhcpOption::ParameterRequestList(self.params.0.clone()));
+        // insert manually entered opts
+        for opt in &self.opt {
+            msg.opts_mut().insert(opt.clone());
+        }
+        Ok(msg)
+    }
+
+    /// Connect to the server, send the query & print each lease as a JSON
+    /// line until DHCPLEASEQUERYDONE or the server closes the connection
+    pub fn run(&self, args: &Args) -> Result<()> {
+        let start = Instant::now();
+        let (target, _) = args.get_target();
+        if target.is_ipv6() {
+            bail!("bulk-leasequery is dhcpv4 only");
+        }
+        let timeout = Duration::from_secs(args.timeout);
+        let mut stream = tcp::connect(target, args.bind, timeout)?;
+
+        let query = self.build()?;
+        tcp::send(&mut stream, &query.to_vec()?)?;
+        let sent = Msg::V4(query.clone());
+        info!(msg_type = %sent.get_type(), ?target, msg = %PrettyPrint(&sent, args.output), "SENT");
+
+        let mut count = 0;
+        while let Some(buf) = tcp::recv(&mut stream)? {
+            let msg = overload::decode(&buf)?;
+            if msg.xid() != query.xid() {
+                warn!(xid = msg.xid(), "reply xid doesn't match the query");
+            }
+            debug!(msg = %PrettyPrint(&Msg::V4(msg.clone()), args.output), "RECEIVED");
+            match msg.opts().msg_type() {
+                Some(v4::MessageType::LeaseActive)
+                | Some(v4::MessageType::LeaseUnassigned)
+                | Some(v4::MessageType::LeaseUnknown) => {
+                    count += 1;
+                    println!("{}", serde_json::to_string(&Lease::new(&msg))?);
+                }
+                Some(v4::MessageType::LeaseQueryDone) => {
+                    let status = msg.opts().get(v4::OptionCode::BulkLeaseQueryStatusCode);
+                    info!(leases = count, ?status, elapsed = %PrettyTime(start.elapsed()), "LEASEQUERYDONE");
+                    return check_status(status);
+                }
+                other => warn!(msg_type = ?other, "unexpected reply to bulk leasequery"),
+            }
+        }
+        warn!(leases = count, elapsed = %PrettyTime(start.elapsed()), "server closed the connection without LEASEQUERYDONE");
+        Ok(())
+    }
+}
+
+/// a DHCPLEASEQUERYDONE status other than success is an error
+fn check_status(status: Option<&v4::DhcpOption>) -> Result<()> {
+    match status {
+        Some(v4::DhcpOption::BulkLeaseQueryStatusCode(code, msg))
+            if *code != bulk_query::Code::Success =>
+        {
+            bail!("bulk leasequery failed: {code:?} {msg}")
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> BulkLeaseQueryArgs {
+        BulkLeaseQueryArgs::from_args(&["bulk-leasequery"], args).unwrap()
+    }
+
+    #[test]
+    fn by_relay_info() {
+        let msg = args(&["--relay-id", "01:02", "--start-time", "100"])
+            .build()
+            .unwrap();
+        assert_eq!(msg.opts().msg_type(), Some(v4::MessageType::BulkLeaseQuery));
+        assert_eq!(msg.hlen(), 0);
+        let Some(v4::DhcpOption::RelayAgentInformation(info)) =
+            msg.opts().get(v4::OptionCode::RelayAgentInformation)
+        else {
+            panic!("no opt 82")
+        };
+        assert_eq!(
+            info.get(RELAY_ID.into()),
+            Some(&relay::RelayInfo::Unknown(relay::UnknownInfo::new(
+                RELAY_ID.into(),
+                vec![1, 2]
+            )))
+        );
+        assert_eq!(
+            msg.opts().get(v4::OptionCode::BulkLeaseQueryQueryStartTime),
+            Some(&v4::DhcpOption::BulkLeaseQueryQueryStartTime(100))
+        );
+
+        let msg = args(&["--link", "10.0.0.0"]).build().unwrap();
+        let Some(v4::DhcpOption::RelayAgentInformation(info)) =
+            msg.opts().get(v4::OptionCode::RelayAgentInformation)
+        else {
+            panic!("no opt 82")
+        };
+        assert_eq!(
+            info.get(relay::RelayCode::LinkSelection),
+            Some(&relay::RelayInfo::LinkSelection(Ipv4Addr::new(10, 0, 0, 0)))
+        );
+    }
+
+    #[test]
+    fn one_query() {
+        assert!(args(&[]).build().is_err());
+        assert!(args(&["--ip", "10.0.0.5", "--remote-id", "01"])
+            .build()
+            .is_err());
+        let msg = args(&["--ip", "10.0.0.5"]).build().unwrap();
+        assert_eq!(msg.ciaddr(), Ipv4Addr::new(10, 0, 0, 5));
+        assert_eq!(msg.opts().get(v4::OptionCode::RelayAgentInformation), None);
+    }
+
+    #[test]
+    fn status() {
+        check_status(None).unwrap();
+        check_status(Some(&v4::DhcpOption::BulkLeaseQueryStatusCode(
+            bulk_query::Code::Success,
+            String::new(),
+        )))
+        .unwrap();
+        assert!(check_status(Some(&v4::DhcpOption::BulkLeaseQueryStatusCode(
+            bulk_query::Code::NotAllowed,
+            "no".into(),
+        )))
+        .is_err());
+    }
+}
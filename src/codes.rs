@@ -0,0 +1,313 @@
+use std::io::{self, Write};
+
+use anyhow::{Context, Result};
+use argh::FromArgs;
+use dhcproto::{v4, v6};
+
+/// short names accepted in addition to the `OptionCode` names
+const V4_ALIASES: &[(&str, u8)] = &[
+    ("subnet", 1),
+    ("netmask", 1),
+    ("router", 3),
+    ("dns", 6),
+    ("hostname", 12),
+    ("domain", 15),
+    ("mtu", 26),
+    ("broadcast", 28),
+    ("ntp", 42),
+    ("vendor", 43),
+    ("requested-ip", 50),
+    ("lease-time", 51),
+    ("overload", 52),
+    ("msg-type", 53),
+    ("server-id", 54),
+    ("params", 55),
+    ("t1", 58),
+    ("t2", 59),
+    ("class-id", 60),
+    ("client-id", 61),
+    ("tftp-server", 66),
+    ("bootfile", 67),
+    ("fqdn", 81),
+    ("relay", 82),
+    ("arch", 93),
+    ("nii", 94),
+    ("uuid", 97),
+    ("subnet-select", 118),
+    ("search", 119),
+    ("routes", 121),
+];
+
+const V6_ALIASES: &[(&str, u16)] = &[
+    ("client-id", 1),
+    ("server-id", 2),
+    ("ia-na", 3),
+    ("ia-ta", 4),
+    ("ia-addr", 5),
+    ("oro", 6),
+    ("auth", 11),
+    ("dns", 23),
+    ("search", 24),
+    ("ia-pd", 25),
+    ("ia-prefix", 26),
+    ("sntp", 31),
+    ("fqdn", 39),
+    ("ntp", 56),
+    ("bootfile-url", 59),
+    ("bootfile-param", 60),
+    ("arch", 61),
+];
+
+/// lowercase & drop separators so "Subnet-Mask", "subnet_mask" and "SubnetMask" all match
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| *c != '-' && *c != '_')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// iterate the v4 option codes dhcproto knows about
+pub fn v4_codes() -> impl Iterator<Item = v4::OptionCode> {
+    (1..u8::MAX)
+        .map(v4::OptionCode::from)
+        .filter(|code| !matches!(code, v4::OptionCode::Unknown(_)))
+}
+
+/// iterate the v6 option codes dhcproto knows about
+pub fn v6_codes() -> impl Iterator<Item = v6::OptionCode> {
+    (1..=u8::MAX as u16)
+        .map(v6::OptionCode::from)
+        .filter(|code| !matches!(code, v6::OptionCode::Unknown(_)))
+}
+
+/// parse a v4 option code from a number or a name ("1", "subnet", "SubnetMask", "subnet-mask")
+pub fn parse_v4_code(code: &str) -> Result<v4::OptionCode, String> {
+    if let Ok(n) = code.parse::<u8>() {
+        return Ok(n.into());
+    }
+    let name = normalize(code);
+    V4_ALIASES
+        .iter()
+        .find(|(alias, _)| normalize(alias) == name)
+        .map(|(_, n)| v4::OptionCode::from(*n))
+        .or_else(|| v4_codes().find(|c| normalize(&format!("{c:?}")) == name))
+        .ok_or_else(|| {
+            format!("unknown OptionCode {code:?}, run `dhcpm options list` for known codes")
+        })
+}
+
+/// parse a v6 option code from a number or a name ("23", "dns", "DomainNameServers")
+pub fn parse_v6_code(code: &str) -> Result<v6::OptionCode, String> {
+    if let Ok(n) = code.parse::<u16>() {
+        return Ok(n.into());
+    }
+    let name = normalize(code);
+    V6_ALIASES
+        .iter()
+        .find(|(alias, _)| normalize(alias) == name)
+        .map(|(_, n)| v6::OptionCode::from(*n))
+        .or_else(|| v6_codes().find(|c| normalize(&format!("{c:?}")) == name))
+        .ok_or_else(|| {
+            format!("unknown OptionCode {code:?}, run `dhcpm options list` for known codes")
+        })
+}
+
+/// the `-o` type to use for a v4 option's value
+pub fn v4_value_type(code: v4::OptionCode) -> &'static str {
+    match u8::from(code) {
+        1 | 16 | 28 | 32 | 50 | 54 | 118 => "ip",
+        3..=11 | 21 | 33 | 41 | 42 | 44 | 45 | 48 | 49 | 65 | 68..=76 | 89 | 92 | 150 => "ips",
+        12 | 14 | 15 | 17 | 18 | 40 | 47 | 56 | 60 | 62 | 64 | 66 | 67 | 100 | 101 | 114 => "str",
+        19 | 20 | 27 | 29 | 30 | 31 | 34 | 36 | 39 => "bool",
+        23 | 37 | 46 | 52 | 53 | 116 | 156 | 157 => "u8",
+        13 | 22 | 26 | 57 | 93 => "u16",
+        24 | 35 | 38 | 51 | 58 | 59 | 91 | 152..=155 => "u32",
+        2 => "i32",
+        88 | 119 => "domains",
+        121 => "cidr-routes",
+        _ => "hex",
+    }
+}
+
+/// the `-o` type to use for a v6 option's value. IA_NA, IA_TA & IA_PD are
+/// "hex" (IAID, T1, T2), options inside them are nested with "container/code"
+pub fn v6_value_type(code: v6::OptionCode) -> &'static str {
+    match u16::from(code) {
+        1 | 2 => "duid",
+        5 => "ia-addr",
+        26 => "ia-prefix",
+        7 => "u8",
+        8 => "u16",
+        32 | 46 | 82 | 83 => "u32",
+        12 => "ip6",
+        22 | 23 | 27 | 28 | 31 | 34 | 40 | 48 => "ips6",
+        21 | 24 | 29 | 30 | 33 | 64 | 65 => "domains",
+        41 | 42 | 59 | 103 => "str",
+        _ => "hex",
+    }
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug, Clone)]
+/// Print information about DHCP options
+#[argh(subcommand, name = "options")]
+pub struct OptionsArgs {
+    #[argh(subcommand)]
+    pub cmd: OptionsCmd,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, FromArgs)]
+#[argh(subcommand)]
+pub enum OptionsCmd {
+    List(ListArgs),
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug, Clone)]
+/// List known option codes, names & the value type to use with `-o`
+#[argh(subcommand, name = "list")]
+pub struct ListArgs {
+    /// only list dhcpv4 options
+    #[argh(switch)]
+    pub v4: bool,
+    /// only list dhcpv6 options
+    #[argh(switch)]
+    pub v6: bool,
+}
+
+impl OptionsArgs {
+    pub fn run(&self) -> Result<()> {
+        match self.list(&mut io::stdout().lock()) {
+            // ex. piped to `head`
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            res => res.context("failed printing to stdout"),
+        }
+    }
+
+    fn list(&self, out: &mut impl Write) -> io::Result<()> {
+        let OptionsCmd::List(args) = &self.cmd;
+        let both = args.v4 == args.v6;
+        if args.v4 || both {
+            writeln!(out, "dhcpv4 options:")?;
+            writeln!(out, "{:>6}  {:<40} {:<12} aliases", "code", "name", "type")?;
+            for code in v4_codes() {
+                let n = u8::from(code);
+                let aliases = V4_ALIASES
+                    .iter()
+                    .filter(|(_, c)| *c == n)
+                    .map(|(a, _)| *a)
+                    .collect::<Vec<_>>()
+                    .join(",");
+                writeln!(
+                    out,
+                    "{n:>6}  {:<40} {:<12} {aliases}",
+                    format!("{code:?}"),
+                    v4_value_type(code)
+                )?;
+            }
+        }
+        if both {
+            writeln!(out)?;
+        }
+        if args.v6 || both {
+            writeln!(out, "dhcpv6 options:")?;
+            writeln!(out, "{:>6}  {:<40} {:<12} aliases", "code", "name", "type")?;
+            for code in v6_codes() {
+                let n = u16::from(code);
+                let aliases = V6_ALIASES
+                    .iter()
+                    .filter(|(_, c)| *c == n)
+                    .map(|(a, _)| *a)
+                    .collect::<Vec<_>>()
+                    .join(",");
+                writeln!(
+                    out,
+                    "{n:>6}  {:<40} {:<12} {aliases}",
+                    format!("{code:?}"),
+                    v6_value_type(code)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opts;
+
+    #[test]
+    fn v4_names() {
+        for name in ["1", "subnet", "SubnetMask", "subnet-mask", "subnet_mask"] {
+            assert_eq!(parse_v4_code(name).unwrap(), v4::OptionCode::SubnetMask);
+        }
+        assert_eq!(
+            parse_v4_code("routes").unwrap(),
+            v4::OptionCode::ClasslessStaticRoute
+        );
+        assert_eq!(parse_v4_code("224").unwrap(), v4::OptionCode::Unknown(224));
+        assert!(parse_v4_code("256").is_err());
+        assert!(parse_v4_code("nope").is_err());
+    }
+
+    #[test]
+    fn v6_names() {
+        for name in ["23", "dns", "DomainNameServers"] {
+            assert_eq!(
+                parse_v6_code(name).unwrap(),
+                v6::OptionCode::DomainNameServers
+            );
+        }
+        assert_eq!(parse_v6_code("ia-na").unwrap(), v6::OptionCode::IANA);
+        assert!(parse_v6_code("nope").is_err());
+    }
+
+    #[test]
+    fn aliases_unique() {
+        for aliases in [
+            V4_ALIASES.iter().map(|(a, _)| *a).collect::<Vec<_>>(),
+            V6_ALIASES.iter().map(|(a, _)| *a).collect(),
+        ] {
+            for (i, alias) in aliases.iter().enumerate() {
+                assert!(!aliases[i + 1..].contains(alias), "{alias}");
+            }
+        }
+    }
+
+    // every listed type must be one `-o` accepts
+    #[test]
+    fn value_types() {
+        for code in v4_codes() {
+            let ty = v4_value_type(code);
+            if let Err(err) = opts::encode_value(ty, "") {
+                assert!(!err.starts_with("unknown type"), "{code:?}: {err}");
+            }
+        }
+        for code in v6_codes() {
+            let ty = v6_value_type(code);
+            if let Err(err) = opts::v6::encode_value(ty, "") {
+                assert!(!err.starts_with("unknown type"), "{code:?}: {err}");
+            }
+        }
+    }
+
+    #[test]
+    fn listed_types() {
+        // TFTP server addresses, RFC 5859
+        assert_eq!(v4_value_type(v4::OptionCode::Unknown(150)), "ips");
+        // unassigned
+        assert_eq!(v4_value_type(v4::OptionCode::Unknown(106)), "hex");
+
+        let args = OptionsArgs {
+            cmd: OptionsCmd::List(ListArgs {
+                v4: true,
+                v6: false,
+            }),
+        };
+        let mut out = vec![];
+        args.list(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("dhcpv4 options:\n"));
+        assert!(out.contains("SubnetMask"));
+        assert!(!out.contains("dhcpv6 options:"));
+    }
+}
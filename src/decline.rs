@@ -2,22 +2,33 @@ use std::net::Ipv4Addr;
 
 use argh::FromArgs;
 use dhcproto::v4;
-use mac_address::MacAddress;
 
-use crate::opts::{self, parse_mac, parse_opts, ParamList};
+use crate::opts::{
+    self, parse_client_id, parse_htype, parse_mac, parse_opts, Chaddr, ClientId, ParamList,
+};
 
 #[derive(FromArgs, PartialEq, Eq, Debug, Clone)]
 /// Send a DECLINE msg
 #[argh(subcommand, name = "decline")]
 pub struct DeclineArgs {
-    /// supply a mac address for DHCPv4 (use "random" for a random mac) [default: first interface mac]
+    /// supply a hardware address of up to 16 bytes for DHCPv4 (use "random" for a random mac,
+    /// "none" for hlen 0) [default: first interface mac]
     #[argh(
         option,
         short = 'c',
         from_str_fn(parse_mac),
         default = "opts::get_mac()"
     )]
-    pub chaddr: MacAddress,
+    pub chaddr: Chaddr,
+    /// hardware type, a number or "eth", "ieee802", "ieee1394" or "infiniband", hlen is the
+    /// length of chaddr [default: eth]
+    #[argh(option, from_str_fn(parse_htype), default = "v4::HType::Eth")]
+    pub htype: v4::HType,
+    /// client id opt 61: "hwtype+mac", "mac" (no hardware type), "none", "hex:<hex>" or
+    /// "duid[:<iaid>[:<duid>]]" for an RFC 4361 IAID & DUID, defaulting to IAID 1 &
+    /// DUID-LL of chaddr [default: hwtype+mac, duid if chaddr is "none"]
+    #[argh(option, from_str_fn(parse_client_id), default = "ClientId::default()")]
+    pub client_id: ClientId,
     /// giaddr [default: 0.0.0.0]
     #[argh(option, short = 'g', default = "Ipv4Addr::UNSPECIFIED")]
     pub giaddr: Ipv4Addr,
@@ -52,6 +63,8 @@ impl Default for DeclineArgs {
     fn default() -> Self {
         Self {
             chaddr: opts::get_mac(),
+            htype: v4::HType::Eth,
+            client_id: ClientId::default(),
             ciaddr: Ipv4Addr::UNSPECIFIED,
             giaddr: Ipv4Addr::UNSPECIFIED,
             yiaddr: Ipv4Addr::UNSPECIFIED,
@@ -72,16 +85,20 @@ impl DeclineArgs {
             self.yiaddr,
             Ipv4Addr::UNSPECIFIED,
             self.giaddr,
-            &self.chaddr.bytes(),
+            self.chaddr.bytes(),
         );
+        msg.set_htype(self.htype);
 
         msg.opts_mut()
             .insert(v4::DhcpOption::MessageType(v4::MessageType::Decline));
-        msg.opts_mut().insert(v4::DhcpOption::ClientIdentifier(
-            self.chaddr.bytes().to_vec(),
-        ));
-        msg.opts_mut()
-            .insert(v4::DhcpOption::ParameterRequestList(self.params.0.clone()));
+        if let Some(id) = self.client_id.opt(self.htype, self.chaddr) {
+            msg.opts_mut().insert(id);
+        }
+        // a DECLINE MUST NOT have opt 55 (RFC 2131 table 5), only add it if asked
+        if !self.params.0.is_empty() {
+            msg.opts_mut()
+                .insert(v4::DhcpOption::ParameterRequestList(self.params.0.clone()));
+        }
         // insert manually entered opts
         for opt in &self.opt {
             msg.opts_mut().insert(opt.clone());
@@ -191,8 +208,17 @@ pub mod decline_mod {
     #[rhai_fn(global, set = "chaddr")]
     pub fn set_chaddr(args: &mut DeclineArgs, chaddr: rhai::Blob) {
         trace!(?chaddr, "setting chaddr");
-        let bytes: [u8; 6] = chaddr.try_into().expect("failed to convert macaddress");
-        args.chaddr = bytes.into();
+        args.chaddr = Chaddr::try_from(&chaddr[..]).expect("failed to convert chaddr");
+    }
+    // htype
+    #[rhai_fn(global, get = "htype", pure)]
+    pub fn get_htype(args: &mut DeclineArgs) -> i64 {
+        u8::from(args.htype) as i64
+    }
+    #[rhai_fn(global, set = "htype")]
+    pub fn set_htype(args: &mut DeclineArgs, htype: i64) {
+        trace!(?htype, "setting htype");
+        args.htype = u8::try_from(htype).expect("htype must fit in a u8").into();
     }
     #[rhai_fn(global, name = "rand_chaddr")]
     pub fn rand_chaddr(args: &mut DeclineArgs) {
@@ -210,6 +236,13 @@ pub mod decline_mod {
         trace!(?sident, "setting req_addr");
         args.sident = Some(sident.parse::<Ipv4Addr>().expect("failed to parse sident"));
     }
+    // client_id
+    #[rhai_fn(global, set = "client_id")]
+    pub fn set_client_id(args: &mut DeclineArgs, client_id: &str) {
+        trace!(?client_id, "setting client_id");
+        args.client_id =
+            crate::opts::parse_client_id(client_id).expect("failed to parse client_id");
+    }
     // opt
     #[rhai_fn(global, set = "opt")]
     pub fn set_opt(args: &mut DeclineArgs, opt: String) {
@@ -230,3 +263,58 @@ pub mod decline_mod {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use argh::FromArgs;
+
+    use super::*;
+    use crate::DoraArgs;
+
+    #[test]
+    fn no_params() {
+        let args = DeclineArgs {
+            chaddr: [2, 0, 0, 0, 0, 1].into(),
+            ..Default::default()
+        };
+        let msg = args.build();
+        assert_eq!(msg.opts().msg_type(), Some(v4::MessageType::Decline));
+        assert_eq!(msg.opts().get(v4::OptionCode::ParameterRequestList), None);
+        let args = DeclineArgs {
+            params: "1,3".parse().unwrap(),
+            ..args
+        };
+        assert!(args
+            .build()
+            .opts()
+            .get(v4::OptionCode::ParameterRequestList)
+            .is_some());
+    }
+
+    // RFC 2131 table 5
+    #[test]
+    fn from_ack() {
+        let dora = DoraArgs::from_args(&["dora"], &["-c", "02:00:00:00:00:01"]).unwrap();
+        let mut ack = v4::Message::default();
+        ack.set_yiaddr(Ipv4Addr::new(10, 0, 0, 5));
+        ack.opts_mut()
+            .insert(v4::DhcpOption::ServerIdentifier(Ipv4Addr::new(10, 0, 0, 1)));
+        let msg = dora.decline(&ack).build();
+        assert_eq!(msg.ciaddr(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(msg.yiaddr(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(
+            msg.opts().get(v4::OptionCode::RequestedIpAddress),
+            Some(&v4::DhcpOption::RequestedIpAddress(Ipv4Addr::new(
+                10, 0, 0, 5
+            )))
+        );
+        assert_eq!(
+            msg.opts().get(v4::OptionCode::ServerIdentifier),
+            Some(&v4::DhcpOption::ServerIdentifier(Ipv4Addr::new(
+                10, 0, 0, 1
+            )))
+        );
+        assert_eq!(msg.opts().get(v4::OptionCode::ParameterRequestList), None);
+        assert!(msg.opts().get(v4::OptionCode::ClientIdentifier).is_some());
+    }
+}
@@ -1,23 +1,40 @@
 use std::net::Ipv4Addr;
 
+use anyhow::{bail, Result};
 use argh::FromArgs;
 use dhcproto::v4;
-use mac_address::MacAddress;
+use tracing::info;
 
-use crate::opts::{self, parse_mac, parse_opts, ParamList};
+use crate::{
+    fqdn::{parse_fqdn, Fqdn},
+    opts::{
+        self, parse_client_id, parse_htype, parse_mac, parse_opts, Chaddr, ClientId, ParamList,
+    },
+    vendor::{self, parse_vi_class, parse_vi_opt, ViClass, ViOpt},
+};
 
 #[derive(FromArgs, PartialEq, Eq, Debug, Clone)]
 /// Send a DISCOVER msg
 #[argh(subcommand, name = "discover")]
 pub struct DiscoverArgs {
-    /// supply a mac address for DHCPv4 (use "random" for a random mac) [default: first interface mac]
+    /// supply a hardware address of up to 16 bytes for DHCPv4 (use "random" for a random mac,
+    /// "none" for hlen 0) [default: first interface mac]
     #[argh(
         option,
         short = 'c',
         from_str_fn(parse_mac),
         default = "opts::get_mac()"
     )]
-    pub chaddr: MacAddress,
+    pub chaddr: Chaddr,
+    /// hardware type, a number or "eth", "ieee802", "ieee1394" or "infiniband", hlen is the
+    /// length of chaddr [default: eth]
+    #[argh(option, from_str_fn(parse_htype), default = "v4::HType::Eth")]
+    pub htype: v4::HType,
+    /// client id opt 61: "hwtype+mac", "mac" (no hardware type), "none", "hex:<hex>" or
+    /// "duid[:<iaid>[:<duid>]]" for an RFC 4361 IAID & DUID, defaulting to IAID 1 &
+    /// DUID-LL of chaddr [default: hwtype+mac, duid if chaddr is "none"]
+    #[argh(option, from_str_fn(parse_client_id), default = "ClientId::default()")]
+    pub client_id: ClientId,
     /// address of client [default: None]
     #[argh(option, default = "Ipv4Addr::UNSPECIFIED")]
     pub ciaddr: Ipv4Addr,
@@ -40,12 +57,33 @@ pub struct DiscoverArgs {
     /// params to include: [default: 1,3,6,15 (Subnet, Router, DnsServer, DomainName]
     #[argh(option, default = "opts::default_params()")]
     pub params: ParamList,
+    /// include rapid commit opt 80, the server may reply with an ACK (RFC 4039) [default: false]
+    #[argh(switch)]
+    pub rapid_commit: bool,
+    /// client FQDN opt 81 as "<name>[,<flags>]", flags any of S, O, E & N [default: SE], without E
+    /// the name is ASCII encoded [ex: "host.example.com,SE"] [default: None]
+    #[argh(option, from_str_fn(parse_fqdn))]
+    pub fqdn: Option<Fqdn>,
+    /// vendor class identifier opt 60 [default: None]
+    #[argh(option)]
+    pub vendor_class: Option<String>,
+    /// vendor-identifying vendor class opt 124 as "<enterprise>:<data>[,<data>...]",
+    /// can be repeated [ex: "4491:docsis3.0"]
+    #[argh(option, from_str_fn(parse_vi_class))]
+    pub vi_class: Vec<ViClass>,
+    /// vendor-identifying vendor-specific info opt 125 sub-option as
+    /// "<enterprise>:<code>,<type>,<value>" with the same types as --opt, can be repeated
+    /// [ex: "3561:1,str,ABC123"]
+    #[argh(option, from_str_fn(parse_vi_opt))]
+    pub vi_opt: Vec<ViOpt>,
 }
 
 impl Default for DiscoverArgs {
     fn default() -> Self {
         Self {
             chaddr: opts::get_mac(),
+            htype: v4::HType::Eth,
+            client_id: ClientId::default(),
             ciaddr: Ipv4Addr::UNSPECIFIED,
             giaddr: Ipv4Addr::UNSPECIFIED,
             req_addr: None,
@@ -53,6 +91,11 @@ impl Default for DiscoverArgs {
             relay_link: None,
             opt: Vec::new(),
             params: opts::default_params(),
+            rapid_commit: false,
+            fqdn: None,
+            vendor_class: None,
+            vi_class: Vec::new(),
+            vi_opt: Vec::new(),
         }
     }
 }
@@ -64,20 +107,25 @@ impl DiscoverArgs {
             Ipv4Addr::UNSPECIFIED,
             Ipv4Addr::UNSPECIFIED,
             self.giaddr,
-            &self.chaddr.bytes(),
+            self.chaddr.bytes(),
         );
+        msg.set_htype(self.htype);
 
-        if broadcast {
+        if broadcast || opts::needs_broadcast(self.htype) {
             msg.set_flags(v4::Flags::default().set_broadcast());
         }
         msg.opts_mut()
             .insert(v4::DhcpOption::MessageType(v4::MessageType::Discover));
-        msg.opts_mut().insert(v4::DhcpOption::ClientIdentifier(
-            self.chaddr.bytes().to_vec(),
-        ));
+        if let Some(id) = self.client_id.opt(self.htype, self.chaddr) {
+            msg.opts_mut().insert(id);
+        }
         // insert parse params
         msg.opts_mut()
             .insert(v4::DhcpOption::ParameterRequestList(self.params.0.clone()));
+        // insert vendor class & vendor-specific info
+        for opt in vendor::v4_opts(self.vendor_class.as_deref(), &self.vi_class, &self.vi_opt) {
+            msg.opts_mut().insert(opt);
+        }
         // insert manually entered opts
         for opt in &self.opt {
             msg.opts_mut().insert(opt.clone());
@@ -97,10 +145,37 @@ impl DiscoverArgs {
         if let Some(ip) = self.subnet_select {
             msg.opts_mut().insert(v4::DhcpOption::SubnetSelection(ip));
         }
+        if self.rapid_commit {
+            msg.opts_mut().insert(v4::DhcpOption::RapidCommit);
+        }
+        if let Some(fqdn) = &self.fqdn {
+            msg.opts_mut().insert(fqdn.v4());
+        }
         msg
     }
 }
 
+/// Check the reply to a rapid commit DISCOVER, returns `true` if the server
+/// committed the lease with an ACK or `false` for an OFFER, meaning the
+/// exchange falls back to DORA (RFC 4039 section 4)
+pub fn rapid_commit_done(reply: &v4::Message) -> Result<bool> {
+    match reply.opts().msg_type() {
+        Some(v4::MessageType::Ack) => {
+            if reply.opts().get(v4::OptionCode::RapidCommit).is_none() {
+                bail!("ACK in response to DISCOVER is missing rapid commit opt 80");
+            }
+            info!(yiaddr = %reply.yiaddr(), "rapid commit ACK, lease committed");
+            Ok(true)
+        }
+        Some(v4::MessageType::Offer) => {
+            info!("server didn't rapid commit");
+            Ok(false)
+        }
+        Some(v4::MessageType::Nak) => bail!("server NAKed the rapid commit DISCOVER"),
+        other => bail!("expected an OFFER or ACK in response to DISCOVER, got {other:?}"),
+    }
+}
+
 #[cfg(feature = "script")]
 use rhai::{plugin::*, EvalAltResult};
 
@@ -159,8 +234,17 @@ pub mod discover_mod {
     #[rhai_fn(global, set = "chaddr")]
     pub fn set_chaddr(args: &mut DiscoverArgs, chaddr: rhai::Blob) {
         trace!(?chaddr, "setting chaddr");
-        let bytes: [u8; 6] = chaddr.try_into().expect("failed to convert macaddress");
-        args.chaddr = bytes.into();
+        args.chaddr = Chaddr::try_from(&chaddr[..]).expect("failed to convert chaddr");
+    }
+    // htype
+    #[rhai_fn(global, get = "htype", pure)]
+    pub fn get_htype(args: &mut DiscoverArgs) -> i64 {
+        u8::from(args.htype) as i64
+    }
+    #[rhai_fn(global, set = "htype")]
+    pub fn set_htype(args: &mut DiscoverArgs, htype: i64) {
+        trace!(?htype, "setting htype");
+        args.htype = u8::try_from(htype).expect("htype must fit in a u8").into();
     }
     #[rhai_fn(global, name = "rand_chaddr")]
     pub fn rand_chaddr(args: &mut DiscoverArgs) {
@@ -182,6 +266,35 @@ pub mod discover_mod {
                 .expect("failed to parse req_addr"),
         );
     }
+    // rapid_commit
+    #[rhai_fn(global, get = "rapid_commit", pure)]
+    pub fn get_rapid_commit(args: &mut DiscoverArgs) -> bool {
+        args.rapid_commit
+    }
+    #[rhai_fn(global, set = "rapid_commit")]
+    pub fn set_rapid_commit(args: &mut DiscoverArgs, rapid_commit: bool) {
+        trace!(?rapid_commit, "setting rapid_commit");
+        args.rapid_commit = rapid_commit;
+    }
+    // fqdn
+    #[rhai_fn(global, set = "fqdn")]
+    pub fn set_fqdn(args: &mut DiscoverArgs, fqdn: &str) {
+        trace!(?fqdn, "setting fqdn");
+        args.fqdn = Some(crate::fqdn::parse_fqdn(fqdn).expect("failed to parse fqdn"));
+    }
+    // vendor_class
+    #[rhai_fn(global, set = "vendor_class")]
+    pub fn set_vendor_class(args: &mut DiscoverArgs, vendor_class: &str) {
+        trace!(?vendor_class, "setting vendor_class");
+        args.vendor_class = Some(vendor_class.to_owned());
+    }
+    // client_id
+    #[rhai_fn(global, set = "client_id")]
+    pub fn set_client_id(args: &mut DiscoverArgs, client_id: &str) {
+        trace!(?client_id, "setting client_id");
+        args.client_id =
+            crate::opts::parse_client_id(client_id).expect("failed to parse client_id");
+    }
     // opt
     #[rhai_fn(global, set = "opt")]
     pub fn set_opt(args: &mut DiscoverArgs, opt: String) {
@@ -202,3 +315,45 @@ pub mod discover_mod {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(ty: v4::MessageType, rapid_commit: bool) -> v4::Message {
+        let mut msg = v4::Message::default();
+        msg.opts_mut().insert(v4::DhcpOption::MessageType(ty));
+        if rapid_commit {
+            msg.opts_mut().insert(v4::DhcpOption::RapidCommit);
+        }
+        msg
+    }
+
+    #[test]
+    fn rapid_commit() {
+        let args = DiscoverArgs {
+            rapid_commit: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            args.build(false).opts().get(v4::OptionCode::RapidCommit),
+            Some(&v4::DhcpOption::RapidCommit)
+        );
+        let args = DiscoverArgs::default();
+        assert_eq!(
+            args.build(false).opts().get(v4::OptionCode::RapidCommit),
+            None
+        );
+    }
+
+    #[test]
+    fn rapid_commit_reply() {
+        assert!(rapid_commit_done(&reply(v4::MessageType::Ack, true)).unwrap());
+        assert!(!rapid_commit_done(&reply(v4::MessageType::Offer, false)).unwrap());
+        // an ACK must carry opt 80
+        assert!(rapid_commit_done(&reply(v4::MessageType::Ack, false)).is_err());
+        assert!(rapid_commit_done(&reply(v4::MessageType::Nak, false)).is_err());
+        assert!(rapid_commit_done(&reply(v4::MessageType::Decline, false)).is_err());
+        assert!(rapid_commit_done(&v4::Message::default()).is_err());
+    }
+}
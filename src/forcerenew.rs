@@ -0,0 +1,196 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use crossbeam_channel::{after, select, Receiver};
+use dhcproto::v4;
+use tracing::{debug, info, warn};
+
+use crate::{
+    auth,
+    opts::LogStructure,
+    util::{Msg, PrettyPrint, PrettyTime},
+};
+
+/// opt 145 telling the server we can authenticate a FORCERENEW with a
+/// nonce using HMAC-MD5 (RFC 6704 section 3.1)
+pub fn nonce_capable() -> v4::DhcpOption {
+    v4::DhcpOption::Unknown(v4::UnknownOption::new(
+        auth::FORCERENEW_NONCE_CAPABLE.into(),
+        vec![auth::HMAC_MD5],
+    ))
+}
+
+/// Wait for a FORCERENEW (RFC 3203) from the server that sent `ack`, it must
+/// be for our chaddr & authenticate with the nonce from the ACK, or with
+/// delayed auth (RFC 3118) using `key` if the ACK has no nonce
+pub fn wait(
+    ack: &v4::Message,
+    key: Option<&auth::Key>,
+    recv_rx: &Receiver<(Msg, SocketAddr, Vec<u8>)>,
+    shutdown_rx: &Receiver<()>,
+    wait: Duration,
+    output: LogStructure,
+) -> Result<()> {
+    let nonce = auth::forcerenew_nonce(ack);
+    if nonce.is_none() && key.is_none() {
+        bail!(
+            "the ACK has no forcerenew nonce (opt 90, protocol 3) & no --auth-key is set, a FORCERENEW can't be authenticated"
+        );
+    }
+    let start = Instant::now();
+    let timeout = after(wait);
+    info!(wait = %PrettyTime(wait), "waiting for FORCERENEW");
+    loop {
+        select! {
+            recv(recv_rx) -> res => {
+                let (msg, addr, raw) = res?;
+                match msg {
+                    Msg::V4(msg) if msg.opts().msg_type() == Some(v4::MessageType::ForceRenew) => {
+                        info!(?addr, elapsed = %PrettyTime(start.elapsed()), msg = %PrettyPrint(&Msg::V4(msg.clone()), output), "RECEIVED FORCERENEW");
+                        return check(ack, &msg, &raw, nonce, key);
+                    }
+                    msg => debug!(msg_type = %msg.get_type(), ?addr, "ignoring message while waiting for FORCERENEW"),
+                }
+            }
+            recv(shutdown_rx) -> _ => bail!("shutdown while waiting for FORCERENEW"),
+            recv(timeout) -> _ => bail!("no FORCERENEW received after {}", PrettyTime(wait)),
+        }
+    }
+}
+
+fn check(
+    ack: &v4::Message,
+    msg: &v4::Message,
+    raw: &[u8],
+    nonce: Option<(u64, [u8; 16])>,
+    key: Option<&auth::Key>,
+) -> Result<()> {
+    if msg.chaddr() != ack.chaddr() {
+        bail!("FORCERENEW chaddr doesn't match ours");
+    }
+    let sident = msg.opts().get(v4::OptionCode::ServerIdentifier);
+    if sident.is_none() {
+        warn!("FORCERENEW has no server identifier");
+    } else if sident != ack.opts().get(v4::OptionCode::ServerIdentifier) {
+        bail!("FORCERENEW server identifier doesn't match the server that sent the ACK");
+    }
+    match (nonce, key) {
+        (Some((last_replay, nonce)), _) => auth::check_forcerenew(raw, msg, &nonce, last_replay)?,
+        (None, Some(key)) => auth::check_reply(raw, &Msg::V4(msg.clone()), key, "", &auth::REPLIES)
+            .context("FORCERENEW delayed auth")?,
+        (None, None) => bail!("FORCERENEW can't be authenticated"),
+    }
+    info!("FORCERENEW is valid");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crossbeam_channel::unbounded;
+    use dhcproto::Encodable;
+
+    use super::*;
+
+    const NONCE: [u8; 16] = [3; 16];
+    const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+    fn opt90(replay: u64, ty: u8, value: [u8; 16]) -> v4::DhcpOption {
+        // reconfigure key protocol, HMAC-MD5, monotonic counter
+        let mut data = vec![3, 1, 0];
+        data.extend(replay.to_be_bytes());
+        data.push(ty);
+        data.extend(value);
+        v4::DhcpOption::Unknown(v4::UnknownOption::new(90.into(), data))
+    }
+
+    fn msg(ty: v4::MessageType) -> v4::Message {
+        let mut msg = v4::Message::default();
+        msg.set_chaddr(&[2, 0, 0, 0, 0, 1]);
+        msg.opts_mut().insert(v4::DhcpOption::MessageType(ty));
+        msg.opts_mut()
+            .insert(v4::DhcpOption::ServerIdentifier(SERVER));
+        msg
+    }
+
+    /// an ACK with a nonce & a FORCERENEW authenticated with it
+    fn forcerenew() -> (v4::Message, v4::Message, Vec<u8>) {
+        let mut ack = msg(v4::MessageType::Ack);
+        ack.opts_mut().insert(opt90(5, 1, NONCE));
+        let mut msg = msg(v4::MessageType::ForceRenew);
+        msg.opts_mut().insert(opt90(6, 2, [0; 16]));
+        let mac = auth::hmac_md5(&NONCE, &msg.to_vec().unwrap());
+        msg.opts_mut().insert(opt90(6, 2, mac));
+        let raw = msg.to_vec().unwrap();
+        (ack, msg, raw)
+    }
+
+    #[test]
+    fn nonce() {
+        let (ack, msg, raw) = forcerenew();
+        let nonce = auth::forcerenew_nonce(&ack);
+        assert_eq!(nonce, Some((5, NONCE)));
+        check(&ack, &msg, &raw, nonce, None).unwrap();
+        // replayed
+        assert!(check(&ack, &msg, &raw, Some((6, NONCE)), None).is_err());
+        assert!(check(&ack, &msg, &raw, Some((5, [0; 16])), None).is_err());
+
+        let mut other = msg.clone();
+        other.set_chaddr(&[2, 0, 0, 0, 0, 2]);
+        assert!(check(&ack, &other, &raw, nonce, None).is_err());
+        let mut other = msg.clone();
+        other
+            .opts_mut()
+            .insert(v4::DhcpOption::ServerIdentifier(Ipv4Addr::new(10, 0, 0, 2)));
+        assert!(check(&ack, &other, &raw, nonce, None).is_err());
+    }
+
+    #[test]
+    fn delayed_auth() {
+        let key = auth::parse_key("7:00112233445566778899aabbccddeeff").unwrap();
+        let ack = msg(v4::MessageType::Ack);
+        let mut fr = msg(v4::MessageType::ForceRenew);
+        auth::insert_v4(&mut fr, &key, None);
+        let mut fr = Msg::V4(fr);
+        auth::sign(&mut fr, &key, "", None).unwrap();
+        let raw = fr.to_vec().unwrap();
+        let Msg::V4(fr) = fr else { unreachable!() };
+        check(&ack, &fr, &raw, None, Some(&key)).unwrap();
+        let other = auth::Key {
+            secret: vec![0; 16],
+            ..key
+        };
+        assert!(check(&ack, &fr, &raw, None, Some(&other)).is_err());
+        assert!(check(&ack, &fr, &raw, None, None).is_err());
+    }
+
+    #[test]
+    fn waits() {
+        let (ack, fr, raw) = forcerenew();
+        let (recv_tx, recv_rx) = unbounded();
+        let (_shutdown_tx, shutdown_rx) = unbounded();
+        let from = SocketAddr::from((SERVER, 67));
+        let wait_for = |ack: &v4::Message| {
+            wait(
+                ack,
+                None,
+                &recv_rx,
+                &shutdown_rx,
+                Duration::from_millis(100),
+                LogStructure::Pretty,
+            )
+        };
+        // other messages are skipped
+        recv_tx.send((Msg::V4(ack.clone()), from, vec![])).unwrap();
+        recv_tx.send((Msg::V4(fr), from, raw)).unwrap();
+        wait_for(&ack).unwrap();
+        // nothing received
+        assert!(wait_for(&ack).is_err());
+        // no nonce or key
+        assert!(wait_for(&msg(v4::MessageType::Ack)).is_err());
+    }
+}
@@ -0,0 +1,464 @@
+use std::{
+    net::{IpAddr, SocketAddr, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use dhcproto::{v4, v6};
+use hickory_proto::{
+    op::{Message, Query, ResponseCode},
+    rr::{Name, RData, RecordType},
+    serialize::binary::BinEncodable,
+};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+
+/// client FQDN opt 39 (RFC 4704)
+const V6_CLIENT_FQDN: u16 = 39;
+/// DHCID resource record (RFC 4701)
+const DHCID: u16 = 49;
+
+// flag bits, the same for v4 & v6 except v6 has no E
+const S: u8 = 0x01;
+const O: u8 = 0x02;
+const E: u8 = 0x04;
+const N: u8 = 0x08;
+
+/// DHCID identifier types (RFC 4701 section 3.3)
+const ID_CHADDR: u16 = 0;
+const ID_CLIENT_ID: u16 = 1;
+const ID_DUID: u16 = 2;
+/// DHCID digest type SHA-256
+const SHA_256: u8 = 1;
+
+/// A client FQDN for opt 81 (RFC 4702) or v6 opt 39 (RFC 4704)
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Fqdn {
+    labels: Vec<String>,
+    flags: u8,
+}
+
+/// parse "<name>[,<flags>]" where flags are any of S, O, E & N [default: SE].
+/// A single label is sent as a partial name for the server to complete
+pub fn parse_fqdn(fqdn: &str) -> Result<Fqdn, String> {
+    let (name, flags) = match fqdn.split_once(',') {
+        Some((name, flags)) => (name, flags),
+        None => (fqdn, "SE"),
+    };
+    let name = name.strip_suffix('.').unwrap_or(name);
+    let labels = name.split('.').map(str::to_owned).collect::<Vec<_>>();
+    if labels
+        .iter()
+        .any(|label| label.is_empty() || label.len() > 63)
+    {
+        return Err(format!(
+            "invalid fqdn {name:?}, labels must be 1 to 63 characters"
+        ));
+    }
+    if name.len() > 253 {
+        return Err(format!("invalid fqdn {name:?}, longer than 253 characters"));
+    }
+    let flags = flags.chars().try_fold(0, |acc, flag| {
+        Ok(acc
+            | match flag.to_ascii_uppercase() {
+                'S' => S,
+                'O' => O,
+                'E' => E,
+                'N' => N,
+                c => return Err(format!("unknown fqdn flag {c:?}, expected S, O, E or N")),
+            })
+    })?;
+    Ok(Fqdn { labels, flags })
+}
+
+impl Fqdn {
+    fn partial(&self) -> bool {
+        self.labels.len() == 1
+    }
+
+    /// opt 81, encoded by hand as dhcproto's ClientFQDN can't send partial
+    /// names & the ASCII encoding is deprecated (RFC 4702 section 2.3.1)
+    pub fn v4(&self) -> v4::DhcpOption {
+        let mut buf = vec![self.flags, 0, 0];
+        if self.flags & E != 0 {
+            buf.extend(wire(&self.labels, !self.partial()));
+        } else {
+            buf.extend(self.labels.join(".").as_bytes());
+        }
+        v4::DhcpOption::Unknown(v4::UnknownOption::new(v4::OptionCode::ClientFQDN, buf))
+    }
+
+    /// v6 opt 39 is always in wire format & has no E flag
+    pub fn v6(&self) -> v6::DhcpOption {
+        let mut buf = vec![self.flags & (N | O | S)];
+        buf.extend(wire(&self.labels, !self.partial()));
+        v6::DhcpOption::Unknown(v6::UnknownOption::new(
+            v6::OptionCode::Unknown(V6_CLIENT_FQDN),
+            buf,
+        ))
+    }
+}
+
+/// encode labels in DNS wire format, a partial name has no root label
+fn wire(labels: &[String], root: bool) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for label in labels {
+        buf.push(label.len() as u8);
+        buf.extend(label.as_bytes());
+    }
+    if root {
+        buf.push(0);
+    }
+    buf
+}
+
+/// The FQDN option a server returned
+#[derive(Debug)]
+pub struct Reply {
+    pub flags: u8,
+    pub rcode1: u8,
+    pub rcode2: u8,
+    pub name: String,
+}
+
+impl Reply {
+    pub fn v4(msg: &v4::Message) -> Option<Self> {
+        match msg.opts().get(v4::OptionCode::ClientFQDN)? {
+            v4::DhcpOption::ClientFQDN(fqdn) => Some(Self {
+                flags: fqdn.flags().into(),
+                rcode1: fqdn.r1(),
+                rcode2: fqdn.r2(),
+                name: fqdn.domain().to_ascii(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn v6(msg: &v6::Message) -> Option<Self> {
+        match msg.opts().get(v6::OptionCode::Unknown(V6_CLIENT_FQDN))? {
+            v6::DhcpOption::Unknown(opt) => {
+                let (flags, name) = opt.data().split_first()?;
+                Some(Self {
+                    flags: *flags,
+                    rcode1: 0,
+                    rcode2: 0,
+                    name: from_wire(name)?,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// log the flags the server returned, S set means the server updates
+    /// the forward record, O that it overrode the client's S, N that it does
+    /// no updates
+    pub fn report(&self) {
+        info!(
+            name = %self.name,
+            s = self.flags & S != 0,
+            o = self.flags & O != 0,
+            e = self.flags & E != 0,
+            n = self.flags & N != 0,
+            rcode1 = self.rcode1,
+            rcode2 = self.rcode2,
+            "server FQDN"
+        );
+    }
+}
+
+/// decode a wire format name, adding the root if it's fully qualified
+fn from_wire(mut buf: &[u8]) -> Option<String> {
+    let mut labels = Vec::new();
+    while let Some((&len, rest)) = buf.split_first() {
+        if len == 0 {
+            labels.push(String::new());
+            break;
+        }
+        let label = rest.get(..len as usize)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        buf = &rest[len as usize..];
+    }
+    Some(labels.join("."))
+}
+
+/// log the FQDN option in a reply, if any
+pub fn report(msg: &crate::util::Msg) {
+    let reply = match msg {
+        crate::util::Msg::V4(msg) => Reply::v4(msg),
+        crate::util::Msg::V6(msg) => Reply::v6(msg),
+    };
+    if let Some(reply) = reply {
+        reply.report();
+    }
+}
+
+/// parse a resolver address, the port defaults to 53
+pub fn parse_resolver(addr: &str) -> Result<SocketAddr, String> {
+    addr.parse::<SocketAddr>()
+        .or_else(|_| addr.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+        .map_err(|err| format!("invalid resolver {addr:?}: {err}"))
+}
+
+/// What a server that did the DNS updates should have created
+#[derive(Debug)]
+pub struct Expected {
+    /// the FQDN returned by the server, or the one we sent
+    pub name: String,
+    /// the server's flags
+    pub flags: u8,
+    pub addr: IpAddr,
+    /// DHCID identifier type & identifier
+    pub id_type: u16,
+    pub id: Vec<u8>,
+}
+
+impl Expected {
+    /// from the REQUEST we sent & the ACK. The DHCID identifier is the DUID
+    /// of an RFC 4361 client id (type 255, RFC 4701 section 3.3), any other
+    /// client id if we sent one, else htype & chaddr
+    pub fn v4(sent: &Fqdn, req: &v4::Message, ack: &v4::Message) -> Self {
+        let (id_type, id) = match req.opts().get(v4::OptionCode::ClientIdentifier) {
+            // type 255, 4 byte IAID then the DUID
+            Some(v4::DhcpOption::ClientIdentifier(id)) if id.len() > 5 && id[0] == 255 => {
+                (ID_DUID, id[5..].to_vec())
+            }
+            Some(v4::DhcpOption::ClientIdentifier(id)) => (ID_CLIENT_ID, id.clone()),
+            _ => {
+                let mut id = vec![u8::from(req.htype())];
+                id.extend(req.chaddr());
+                (ID_CHADDR, id)
+            }
+        };
+        let (name, flags) = match Reply::v4(ack) {
+            Some(reply) => (reply.name, reply.flags),
+            None => (sent.labels.join("."), sent.flags),
+        };
+        Self {
+            name,
+            flags,
+            addr: IpAddr::V4(ack.yiaddr()),
+            id_type,
+            id,
+        }
+    }
+
+    /// from the client's DUID & a REPLY with an address in an IA_NA
+    pub fn v6(sent: &Fqdn, duid: Vec<u8>, reply: &v6::Message) -> Result<Self> {
+        let addr = match reply.opts().get(v6::OptionCode::IANA) {
+            Some(v6::DhcpOption::IANA(ia)) => match ia.opts.get(v6::OptionCode::IAAddr) {
+                Some(v6::DhcpOption::IAAddr(ia_addr)) => ia_addr.addr,
+                _ => bail!("REPLY IA_NA has no address"),
+            },
+            _ => bail!("REPLY has no IA_NA"),
+        };
+        let (name, flags) = match Reply::v6(reply) {
+            Some(reply) => (reply.name, reply.flags),
+            None => (sent.labels.join("."), sent.flags),
+        };
+        Ok(Self {
+            name,
+            flags,
+            addr: IpAddr::V6(addr),
+            id_type: ID_DUID,
+            id: duid,
+        })
+    }
+
+    /// DHCID RDATA, SHA-256 over the identifier & the FQDN in canonical
+    /// wire format (RFC 4701 section 3.5)
+    fn dhcid(&self, name: &Name) -> Result<Vec<u8>> {
+        let mut digest = Sha256::new();
+        digest.update(&self.id);
+        digest.update(name.to_lowercase().to_bytes()?);
+        let mut rdata = self.id_type.to_be_bytes().to_vec();
+        rdata.push(SHA_256);
+        rdata.extend(digest.finalize());
+        Ok(rdata)
+    }
+
+    /// check the forward, PTR & DHCID records for the binding were created,
+    /// retrying until `wait` elapses as servers may update DNS after replying
+    pub fn verify(&self, resolver: SocketAddr, wait: Duration) -> Result<()> {
+        if self.flags & N != 0 {
+            info!("server returned N, it does no DNS updates, skipping verification");
+            return Ok(());
+        }
+        let start = Instant::now();
+        loop {
+            match self.check(resolver) {
+                Ok(()) => {
+                    info!(name = %self.name, addr = %self.addr, "DNS records verified");
+                    return Ok(());
+                }
+                Err(err) if start.elapsed() < wait => {
+                    debug!(%err, "DNS records not found yet, retrying");
+                    thread::sleep(Duration::from_secs(1));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn check(&self, resolver: SocketAddr) -> Result<()> {
+        let name = Name::from_ascii(&self.name)
+            .with_context(|| format!("invalid FQDN {:?}", self.name))?
+            .append_domain(&Name::root())?;
+        let reverse = Name::from(self.addr);
+
+        let ptrs = lookup(resolver, &reverse, RecordType::PTR)?;
+        if !ptrs
+            .iter()
+            .any(|rdata| matches!(rdata, RData::PTR(ptr) if ptr.0 == name))
+        {
+            bail!("no PTR record {reverse} -> {name}");
+        }
+        debug!(%reverse, %name, "PTR record found");
+
+        // the server only updates the forward name if it returned S
+        if self.flags & S == 0 {
+            warn!("server returned S=0, the forward records are the client's to update, only checked PTR");
+            return Ok(());
+        }
+        let ty = match self.addr {
+            IpAddr::V4(_) => RecordType::A,
+            IpAddr::V6(_) => RecordType::AAAA,
+        };
+        let addrs = lookup(resolver, &name, ty)?;
+        if !addrs.iter().any(|rdata| match (rdata, self.addr) {
+            (RData::A(a), IpAddr::V4(ip)) => a.0 == ip,
+            (RData::AAAA(aaaa), IpAddr::V6(ip)) => aaaa.0 == ip,
+            _ => false,
+        }) {
+            bail!("no {ty} record {name} -> {}", self.addr);
+        }
+        debug!(%name, addr = %self.addr, "{ty} record found");
+
+        let expected = self.dhcid(&name)?;
+        let dhcids = lookup(resolver, &name, RecordType::Unknown(DHCID))?;
+        let found = dhcids.iter().find_map(|rdata| match rdata {
+            RData::Unknown { rdata, .. } => Some(&rdata.anything),
+            _ => None,
+        });
+        match found {
+            Some(dhcid) if *dhcid == expected => {
+                debug!(%name, dhcid = %hex::encode(dhcid), "DHCID record matches");
+                Ok(())
+            }
+            Some(dhcid) => bail!(
+                "DHCID record for {name} is {}, expected {}",
+                hex::encode(dhcid),
+                hex::encode(&expected)
+            ),
+            None => bail!("no DHCID record for {name}"),
+        }
+    }
+}
+
+/// query `resolver` over UDP, returning the answers of type `ty`
+fn lookup(resolver: SocketAddr, name: &Name, ty: RecordType) -> Result<Vec<RData>> {
+    let mut query = Message::query();
+    query.metadata.recursion_desired = true;
+    query.add_query(Query::query(name.clone(), ty));
+
+    let bind: SocketAddr = match resolver {
+        SocketAddr::V4(_) => "0.0.0.0:0".parse()?,
+        SocketAddr::V6(_) => "[::]:0".parse()?,
+    };
+    let soc = UdpSocket::bind(bind).context("failed to bind DNS socket")?;
+    soc.set_read_timeout(Some(Duration::from_secs(2)))?;
+    soc.send_to(&query.to_vec()?, resolver)
+        .context("failed to send DNS query")?;
+    let mut buf = [0; 4096];
+    let reply = loop {
+        let (len, _) = soc
+            .recv_from(&mut buf)
+            .with_context(|| format!("no DNS reply from {resolver} for {name} {ty}"))?;
+        let reply = Message::from_vec(&buf[..len]).context("failed to decode DNS reply")?;
+        if reply.metadata.id == query.metadata.id {
+            break reply;
+        }
+    };
+    match reply.metadata.response_code {
+        ResponseCode::NoError | ResponseCode::NXDomain => {}
+        rcode => bail!("DNS query for {name} {ty} failed: {rcode}"),
+    }
+    Ok(reply
+        .answers
+        .into_iter()
+        .filter(|record| record.record_type() == ty)
+        .map(|record| record.data)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let fqdn = parse_fqdn("host.example.com.").unwrap();
+        assert_eq!(fqdn.labels, ["host", "example", "com"]);
+        assert_eq!(fqdn.flags, S | E);
+        assert_eq!(parse_fqdn("host,on").unwrap().flags, O | N);
+        assert!(parse_fqdn("host..com").is_err());
+        assert!(parse_fqdn(&"a".repeat(64)).is_err());
+        assert!(parse_fqdn("host,X").is_err());
+    }
+
+    #[test]
+    fn encode() {
+        let opt = |fqdn| match parse_fqdn(fqdn).unwrap().v4() {
+            v4::DhcpOption::Unknown(opt) => opt.data().to_vec(),
+            opt => panic!("{opt:?}"),
+        };
+        assert_eq!(opt("a.b"), b"\x05\0\0\x01a\x01b\0");
+        // partial name has no root label
+        assert_eq!(opt("a"), b"\x05\0\0\x01a");
+        assert_eq!(opt("a.b,S"), b"\x01\0\0a.b");
+    }
+
+    // RFC 4701 section 3.6.1
+    #[test]
+    fn dhcid_duid() {
+        let expected = Expected {
+            name: "chi6.example.com".into(),
+            flags: S,
+            addr: IpAddr::V6("2001:db8::1234:5678".parse().unwrap()),
+            id_type: ID_DUID,
+            id: hex::decode("00010006412df166010203040506").unwrap(),
+        };
+        let name = Name::from_ascii("chi6.example.com.").unwrap();
+        assert_eq!(
+            expected.dhcid(&name).unwrap(),
+            base64::engine::general_purpose::STANDARD
+                .decode("AAIBY2/AuCccgoJbsaxcQc9TUapptP69lOjxfNuVAA2kjEA=")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn dhcid_id_type() {
+        let fqdn = parse_fqdn("host").unwrap();
+        let mut req = v4::Message::default();
+        req.set_chaddr(&[1, 2, 3, 4, 5, 6]);
+        let ack = v4::Message::default();
+        let id = |req: &v4::Message| {
+            let expected = Expected::v4(&fqdn, req, &ack);
+            (expected.id_type, expected.id)
+        };
+        assert_eq!(id(&req), (ID_CHADDR, vec![1, 1, 2, 3, 4, 5, 6]));
+
+        req.opts_mut()
+            .insert(v4::DhcpOption::ClientIdentifier(vec![1, 2, 3]));
+        assert_eq!(id(&req), (ID_CLIENT_ID, vec![1, 2, 3]));
+
+        // the IAID isn't part of the DHCID (RFC 4361 section 6.1)
+        req.opts_mut().insert(v4::DhcpOption::ClientIdentifier(vec![
+            255, 0, 0, 0, 1, 0, 3, 0, 1, 9,
+        ]));
+        assert_eq!(id(&req), (ID_DUID, vec![0, 3, 0, 1, 9]));
+    }
+}
@@ -1,8 +1,14 @@
 use argh::FromArgs;
 use dhcproto::v6;
-use mac_address::MacAddress;
 
-use crate::opts::{self, parse_mac, v6::ParamList as V6ParamList};
+use crate::{
+    opts::{
+        self, parse_mac,
+        v6::{parse_opts as parse_v6_opts, Opt as V6Opt, ParamList as V6ParamList},
+        Chaddr,
+    },
+    vendor::{self, parse_vi_class, parse_vi_opt6, ViClass, ViOpt},
+};
 
 #[derive(FromArgs, PartialEq, Eq, Debug, Clone)]
 /// Send a INFORMATION-REQUEST msg (dhcpv6)
@@ -15,10 +21,22 @@ pub struct InformationReqArgs {
         from_str_fn(parse_mac),
         default = "opts::get_mac()"
     )]
-    pub chaddr: MacAddress,
+    pub chaddr: Chaddr,
     /// params to include: [default: 23,24,39,59]
     #[argh(option, default = "default_opts()")]
     pub params: V6ParamList,
+    /// add opts to the message ("code,type,value", nest in an IA with "container/code")
+    /// [ex: "16,hex,0000000900..." or "ia-na/ia-addr,ia-addr,2001:db8::1"]
+    #[argh(option, short = 'o', from_str_fn(parse_v6_opts))]
+    pub opt: Vec<V6Opt>,
+    /// vendor class opt 16 as "<enterprise>:<data>[,<data>...]", can be repeated
+    /// [ex: "4491:docsis3.0"]
+    #[argh(option, from_str_fn(parse_vi_class))]
+    pub vi_class: Vec<ViClass>,
+    /// vendor-specific info opt 17 sub-option as "<enterprise>:<code>,<type>,<value>"
+    /// with the same types as --opt, can be repeated [ex: "4491:1,str,ABC123"]
+    #[argh(option, from_str_fn(parse_vi_opt6))]
+    pub vi_opt: Vec<ViOpt>,
 }
 
 pub fn default_opts() -> V6ParamList {
@@ -35,6 +53,9 @@ impl Default for InformationReqArgs {
         Self {
             chaddr: opts::get_mac(),
             params: default_opts(),
+            opt: Vec::new(),
+            vi_class: Vec::new(),
+            vi_opt: Vec::new(),
         }
     }
 }
@@ -46,6 +67,12 @@ impl InformationReqArgs {
         msg.opts_mut().insert(v6::DhcpOption::ORO(v6::ORO {
             opts: self.params.0.clone(),
         }));
+        // insert vendor class & vendor-specific info
+        for opt in vendor::v6_opts(&self.vi_class, &self.vi_opt) {
+            msg.opts_mut().insert(opt);
+        }
+        // insert manually entered opts
+        opts::v6::insert_opts(&mut msg, &self.opt);
 
         msg
     }
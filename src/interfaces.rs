@@ -0,0 +1,273 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use argh::FromArgs;
+use pnet_datalink::NetworkInterface;
+use tracing::info;
+
+use crate::{
+    opts::Chaddr,
+    probe,
+    util::{Msg, PrettyTime},
+};
+
+#[derive(FromArgs, PartialEq, Eq, Debug, Clone)]
+/// List network interfaces with their index, MAC, addresses & state
+#[argh(subcommand, name = "interfaces")]
+pub struct InterfacesArgs {
+    /// only list interfaces that are up
+    #[argh(switch)]
+    pub up: bool,
+}
+
+impl InterfacesArgs {
+    pub fn run(&self) {
+        row("index", "name", "state", "mac", "addresses");
+        for int in pnet_datalink::interfaces() {
+            if self.up && !int.is_up() {
+                continue;
+            }
+            let mac = int.mac.map(|mac| mac.to_string()).unwrap_or_default();
+            let mut addrs = int.ips.iter().map(|net| match net.ip() {
+                IpAddr::V4(_) => format!("{net} brd {}", net.broadcast()),
+                IpAddr::V6(ip) if is_link_local(ip) => format!("{net} link-local"),
+                IpAddr::V6(_) => net.to_string(),
+            });
+            row(
+                &int.index.to_string(),
+                &int.name,
+                &state(&int),
+                &mac,
+                &addrs.next().unwrap_or_default(),
+            );
+            for addr in addrs {
+                row("", "", "", "", &addr);
+            }
+        }
+    }
+}
+
+fn row(index: &str, name: &str, state: &str, mac: &str, addr: &str) {
+    let row = format!("{index:>6}  {name:<16} {state:<40} {mac:<18} {addr}");
+    println!("{}", row.trim_end());
+}
+
+fn state(int: &NetworkInterface) -> String {
+    [
+        (int.is_up(), "UP"),
+        (int.is_running(), "RUNNING"),
+        (int.is_lower_up(), "LOWER_UP"),
+        (int.is_loopback(), "LOOPBACK"),
+        (int.is_broadcast(), "BROADCAST"),
+        (int.is_multicast(), "MULTICAST"),
+        (int.is_point_to_point(), "POINTOPOINT"),
+    ]
+    .iter()
+    .filter(|(set, _)| *set)
+    .map(|(_, name)| *name)
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+pub fn is_link_local(ip: Ipv6Addr) -> bool {
+    (ip.segments()[0] & 0xffc0) == 0xfe80
+}
+
+/// the interface the OS routes `target` through, for `--interface auto`, &
+/// the broadcast address of its network for v4. Connecting a UDP socket
+/// sends nothing but picks the source address. Broadcast & multicast
+/// targets use the default route
+pub fn route_to(target: IpAddr) -> Result<(NetworkInterface, Option<Ipv4Addr>)> {
+    let dst = match target {
+        IpAddr::V4(ip) if ip.is_broadcast() || ip.is_multicast() => {
+            IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))
+        }
+        IpAddr::V6(ip) if ip.is_multicast() => {
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))
+        }
+        ip => ip,
+    };
+    let bind: SocketAddr = match dst {
+        IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        IpAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let soc = UdpSocket::bind(bind)?;
+    soc.connect((dst, 9))
+        .with_context(|| format!("no route to {target}, name the interface instead of auto"))?;
+    let src = soc.local_addr()?.ip();
+    let int = pnet_datalink::interfaces()
+        .into_iter()
+        .find(|int| int.ips.iter().any(|net| net.ip() == src))
+        .with_context(|| format!("no interface has the source address {src} for {target}"))?;
+    let broadcast =
+        int.ips
+            .iter()
+            .find(|net| net.ip() == src)
+            .and_then(|net| match net.broadcast() {
+                IpAddr::V4(broadcast) => Some(broadcast),
+                IpAddr::V6(_) => None,
+            });
+    match broadcast {
+        Some(broadcast) => {
+            info!(interface = %int.name, %src, %broadcast, "selected interface from the route to {target}")
+        }
+        None => info!(interface = %int.name, %src, "selected interface from the route to {target}"),
+    }
+    Ok((int, broadcast))
+}
+
+/// the interface names for the `-i` options, in the order given. "auto" is the
+/// interface the route to `target` uses & names with `*` or `?` are globs
+/// matched against the interfaces that are up
+pub fn select(patterns: &[String], target: IpAddr) -> Result<Vec<String>> {
+    let ints = pnet_datalink::interfaces()
+        .into_iter()
+        .filter(|int| int.is_up())
+        .collect::<Vec<_>>();
+    let mut names = Vec::new();
+    for pattern in patterns {
+        let found = if pattern == "auto" {
+            vec![route_to(target)?.0.name]
+        } else if pattern.contains(['*', '?']) {
+            ints.iter()
+                .filter(|int| glob(pattern.as_bytes(), int.name.as_bytes()))
+                .map(|int| int.name.clone())
+                .collect()
+        } else {
+            vec![pattern.clone()]
+        };
+        if found.is_empty() {
+            bail!("no interface that is up matches {pattern}");
+        }
+        for name in found {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// shell style glob, `*` matches any run of characters & `?` any one
+fn glob(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => {
+            glob(rest, name) || (!name.is_empty() && glob(pattern, &name[1..]))
+        }
+        (Some((b'?', rest)), Some((_, name))) => glob(rest, name),
+        (Some((p, rest)), Some((c, name))) if p == c => glob(rest, name),
+        _ => false,
+    }
+}
+
+/// how the message or flow went on one interface
+#[derive(Debug)]
+pub struct Outcome {
+    pub interface: String,
+    pub chaddr: Option<Chaddr>,
+    pub elapsed: Duration,
+    /// the last reply received
+    pub result: Result<Option<Msg>>,
+}
+
+/// print a table of the result on each interface
+pub fn report(outcomes: &[Outcome]) {
+    println!(
+        "{:<16} {:<18} {:<12} {:<8} {:<24} error",
+        "interface", "chaddr", "result", "elapsed", "addresses"
+    );
+    for outcome in outcomes {
+        let chaddr = outcome.chaddr.map(|c| c.to_string()).unwrap_or_default();
+        let elapsed = PrettyTime(outcome.elapsed).to_string();
+        let (result, addrs, err) = match &outcome.result {
+            Ok(Some(msg)) => {
+                let addrs = probe::offered(msg)
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",");
+                (msg.get_type(), addrs, String::new())
+            }
+            Ok(None) => ("OK".to_owned(), String::new(), String::new()),
+            Err(err) => ("FAILED".to_owned(), String::new(), format!("{err:#}")),
+        };
+        let row = format!(
+            "{:<16} {chaddr:<18} {result:<12} {elapsed:<8} {addrs:<24} {err}",
+            outcome.interface
+        );
+        println!("{}", row.trim_end());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        let glob = |pattern: &str, name: &str| glob(pattern.as_bytes(), name.as_bytes());
+        assert!(glob("eth*", "eth0"));
+        assert!(glob("eth*", "eth"));
+        assert!(glob("*", "enp3s0"));
+        assert!(glob("en*s?", "enp3s0"));
+        assert!(glob("*.10", "eth0.10"));
+        assert!(glob("e*h*0", "eth0"));
+        assert!(!glob("eth?", "eth"));
+        assert!(!glob("eth?", "eth10"));
+        assert!(!glob("wl*", "eth0"));
+        assert!(!glob("eth", "eth0"));
+    }
+
+    #[test]
+    fn select_names() {
+        let target = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let names = |patterns: &[&str]| {
+            select(
+                &patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+                target,
+            )
+        };
+        // plain names are kept as is for find_interface to check
+        assert_eq!(names(&["foo", "lo", "foo"]).unwrap(), ["foo", "lo"]);
+        assert!(names(&["nomatch*"]).is_err());
+    }
+
+    #[test]
+    fn link_local() {
+        assert!(is_link_local("fe80::1".parse().unwrap()));
+        assert!(is_link_local("febf::1".parse().unwrap()));
+        assert!(!is_link_local("fec0::1".parse().unwrap()));
+        assert!(!is_link_local("2001:db8::1".parse().unwrap()));
+    }
+
+    #[test]
+    #[ignore = "needs lo with 127.0.0.1/8 in the routing table"]
+    fn route() {
+        let (int, broadcast) = route_to(IpAddr::V4(Ipv4Addr::LOCALHOST)).unwrap();
+        assert!(int.is_loopback());
+        assert!(state(&int).contains("LOOPBACK"));
+        // 127.0.0.1/8
+        assert_eq!(broadcast, Some(Ipv4Addr::new(127, 255, 255, 255)));
+    }
+
+    #[test]
+    fn find() {
+        assert_eq!(crate::find_interface(None).unwrap(), None);
+        let err = crate::find_interface(Some("nope0")).unwrap_err();
+        assert!(err.to_string().contains("unable to find interface nope0"));
+    }
+
+    #[test]
+    fn link_local_addr() {
+        for int in pnet_datalink::interfaces() {
+            if let Some(ip) = crate::find_link_local(&int) {
+                assert!(is_link_local(ip));
+                assert!(int.ips.iter().any(|net| net.ip() == ip));
+            }
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::net::Ipv4Addr;
+
+use anyhow::{bail, Result};
+use argh::FromArgs;
+use dhcproto::v4;
+use tracing::{info, warn};
+
+use crate::opts::{parse_mac, parse_opts, Chaddr, ParamList};
+
+#[derive(FromArgs, PartialEq, Eq, Debug, Clone)]
+/// Send a LEASEQUERY msg (RFC 4388)
+#[argh(subcommand, name = "leasequery")]
+pub struct LeaseQueryArgs {
+    /// query by IP address (set in ciaddr)
+    #[argh(option)]
+    pub ip: Option<Ipv4Addr>,
+    /// query by MAC address (set in chaddr)
+    #[argh(option, from_str_fn(parse_mac))]
+    pub mac: Option<Chaddr>,
+    /// query by client identifier opt 61, as hex
+    #[argh(option, from_str_fn(parse_client_id))]
+    pub client_id: Option<Vec<u8>>,
+    /// address of the requester, the server replies to <giaddr>:67 so dhcpm binds there
+    /// unless --bind is given
+    #[argh(option, short = 'g')]
+    pub giaddr: Ipv4Addr,
+    /// add opts to the message
+    /// [ex: these are equivalent- "118,hex,C0A80001" or "118,ip,192.168.0.1"]
+    #[argh(option, short = 'o', from_str_fn(parse_opts))]
+    pub opt: Vec<v4::DhcpOption>,
+    /// params to include: [default: 51,61,82,91,92 (LeaseTime, ClientId, RelayInfo, LastTransaction, AssociatedIp)]
+    #[argh(option, default = "default_params()")]
+    pub params: ParamList,
+}
+
+fn default_params() -> ParamList {
+    "51,61,82,91,92".parse().expect("valid params")
+}
+
+fn parse_client_id(id: &str) -> Result<Vec<u8>, String> {
+    hex::decode(id.replace(':', "")).map_err(|err| err.to_string())
+}
+
+impl Default for LeaseQueryArgs {
+    fn default() -> Self {
+        Self {
+            ip: None,
+            mac: None,
+            client_id: None,
+            giaddr: Ipv4Addr::UNSPECIFIED,
+            opt: Vec::new(),
+            params: default_params(),
+        }
+    }
+}
+
+impl LeaseQueryArgs {
+    pub fn build(&self) -> Result<v4::Message> {
+        let queries = [
+            self.ip.is_some(),
+            self.mac.is_some(),
+            self.client_id.is_some(),
+        ];
+        if queries.iter().filter(|q| **q).count() != 1 {
+            bail!("leasequery needs exactly one of --ip, --mac or --client-id");
+        }
+        if self.giaddr.is_unspecified() {
+            bail!("leasequery needs --giaddr, the server replies to giaddr");
+        }
+        let mut msg = v4::Message::new(
+            self.ip.unwrap_or(Ipv4Addr::UNSPECIFIED),
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            self.giaddr,
+            // htype, hlen & chaddr must be 0 unless querying by MAC
+            &self.mac.map(|mac| mac.bytes().to_vec()).unwrap_or_default(),
+        );
+        if self.mac.is_none() {
+            msg.set_htype(0.into());
+        }
+        msg.opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::LeaseQuery));
+        if let Some(id) = &self.client_id {
+            msg.opts_mut()
+                .insert(v4::DhcpOption::ClientIdentifier(id.clone()));
+        }
+        msg.opts_mut()
+            .insert(v4::DhcpOption::ParameterRequestList(self.params.0.clone()));
+        // insert manually entered opts
+        for opt in &self.opt {
+            msg.opts_mut().insert(opt.clone());
+        }
+        Ok(msg)
+    }
+}
+
+/// Log the lease state from a LEASEACTIVE/LEASEUNASSIGNED/LEASEUNKNOWN reply
+pub fn report(msg: &v4::Message) {
+    let opts = msg.opts();
+    let state = match opts.msg_type() {
+        Some(state @ v4::MessageType::LeaseActive)
+        | Some(state @ v4::MessageType::LeaseUnassigned)
+        | Some(state @ v4::MessageType::LeaseUnknown) => state,
+        other => {
+            warn!(msg_type = ?other, "expected a LEASEACTIVE, LEASEUNASSIGNED or LEASEUNKNOWN reply");
+            return;
+        }
+    };
+    let lease_time = match opts.get(v4::OptionCode::AddressLeaseTime) {
+        Some(v4::DhcpOption::AddressLeaseTime(t)) => Some(*t),
+        _ => None,
+    };
+    let last_transaction = match opts.get(v4::OptionCode::ClientLastTransactionTime) {
+        Some(v4::DhcpOption::ClientLastTransactionTime(t)) => Some(*t),
+        _ => None,
+    };
+    let associated_ips = match opts.get(v4::OptionCode::AssociatedIp) {
+        Some(v4::DhcpOption::AssociatedIp(ips)) => ips.clone(),
+        _ => vec![],
+    };
+    let client_id = match opts.get(v4::OptionCode::ClientIdentifier) {
+        Some(v4::DhcpOption::ClientIdentifier(id)) => Some(hex::encode(id)),
+        _ => None,
+    };
+    let chaddr = &msg.chaddr()[..(msg.hlen() as usize).min(16)];
+    info!(
+        ?state,
+        ip = %msg.ciaddr(),
+        chaddr = hex::encode(chaddr),
+        ?client_id,
+        ?lease_time,
+        ?last_transaction,
+        ?associated_ips,
+        relay_info = ?opts.get(v4::OptionCode::RelayAgentInformation),
+        "lease state"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<LeaseQueryArgs, argh::EarlyExit> {
+        LeaseQueryArgs::from_args(&["leasequery"], args)
+    }
+
+    #[test]
+    fn by_ip() {
+        let msg = args(&["--ip", "10.0.0.5", "-g", "10.0.0.1"])
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(msg.ciaddr(), Ipv4Addr::new(10, 0, 0, 5));
+        assert_eq!(msg.giaddr(), Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(u8::from(msg.htype()), 0);
+        assert_eq!(msg.hlen(), 0);
+        assert_eq!(msg.opts().msg_type(), Some(v4::MessageType::LeaseQuery));
+    }
+
+    #[test]
+    fn by_mac_or_client_id() {
+        let msg = args(&["--mac", "aa:bb:cc:dd:ee:ff", "-g", "10.0.0.1"])
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(msg.htype(), v4::HType::Eth);
+        assert_eq!(msg.chaddr(), [0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]);
+
+        let msg = args(&["--client-id", "01:aa:bb", "-g", "10.0.0.1"])
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(msg.hlen(), 0);
+        assert_eq!(
+            msg.opts().get(v4::OptionCode::ClientIdentifier),
+            Some(&v4::DhcpOption::ClientIdentifier(vec![1, 0xaa, 0xbb]))
+        );
+    }
+
+    #[test]
+    fn one_query() {
+        assert!(args(&["-g", "10.0.0.1"]).unwrap().build().is_err());
+        assert!(args(&[
+            "--ip",
+            "10.0.0.5",
+            "--mac",
+            "aa:bb:cc:dd:ee:ff",
+            "-g",
+            "10.0.0.1"
+        ])
+        .unwrap()
+        .build()
+        .is_err());
+        assert!(args(&["--ip", "10.0.0.5", "-g", "0.0.0.0"])
+            .unwrap()
+            .build()
+            .is_err());
+        // --giaddr is required
+        assert!(args(&["--ip", "10.0.0.5"]).is_err());
+    }
+}
@@ -0,0 +1,494 @@
+use std::{
+    net::Ipv6Addr,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use argh::FromArgs;
+use dhcproto::{v6, Decodable, Decoder, Encodable};
+use serde::Serialize;
+use tracing::{debug, info, warn};
+
+use crate::{
+    opts::{
+        self, parse_mac,
+        v6::{parse_duid, parse_opts as parse_v6_opts, Opt as V6Opt, ParamList as V6ParamList},
+        Chaddr,
+    },
+    tcp,
+    util::{Msg, PrettyPrint, PrettyTime},
+    Args,
+};
+
+// query types (RFC 5007 section 4.1.2.1, RFC 5460 section 5.2.1)
+const QUERY_BY_ADDRESS: u8 = 1;
+const QUERY_BY_CLIENTID: u8 = 2;
+const QUERY_BY_RELAY_ID: u8 = 3;
+const QUERY_BY_LINK_ADDRESS: u8 = 4;
+const QUERY_BY_REMOTE_ID: u8 = 5;
+
+#[derive(FromArgs, PartialEq, Eq, Debug, Clone)]
+/// Send a LEASEQUERY msg (dhcpv6, RFC 5007)
+#[argh(subcommand, name = "leasequery6")]
+pub struct LeaseQuery6Args {
+    /// query by IPv6 address
+    #[argh(option)]
+    pub ip: Option<Ipv6Addr>,
+    /// query by client DUID (ex: "ll:<mac>", "en:<enterprise>:<hex>" or hex)
+    #[argh(option, from_str_fn(parse_duid))]
+    pub client_id: Option<Vec<u8>>,
+    /// link address the binding is on, "::" for any link [default: ::]
+    #[argh(option, default = "Ipv6Addr::UNSPECIFIED")]
+    pub link: Ipv6Addr,
+    /// mac address for the requestor's DUID-LL (use "random" for a random mac) [default: first interface mac]
+    #[argh(
+        option,
+        short = 'c',
+        from_str_fn(parse_mac),
+        default = "opts::get_mac()"
+    )]
+    pub chaddr: Chaddr,
+    /// params to request in the client data: [default: 47 (LqRelayData)]
+    #[argh(option, default = "default_params()")]
+    pub params: V6ParamList,
+    /// add opts to the message ("code,type,value")
+    #[argh(option, short = 'o', from_str_fn(parse_v6_opts))]
+    pub opt: Vec<V6Opt>,
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug, Clone)]
+/// Send a LEASEQUERY over TCP (dhcpv6, RFC 5460), printing bindings as JSON lines
+#[argh(subcommand, name = "bulk-leasequery6")]
+pub struct BulkLeaseQuery6Args {
+    /// query by IPv6 address
+    #[argh(option)]
+    pub ip: Option<Ipv6Addr>,
+    /// query by client DUID (ex: "ll:<mac>", "en:<enterprise>:<hex>" or hex)
+    #[argh(option, from_str_fn(parse_duid))]
+    pub client_id: Option<Vec<u8>>,
+    /// query by relay agent DUID, opt 53
+    #[argh(option, from_str_fn(parse_duid))]
+    pub relay_id: Option<Vec<u8>>,
+    /// query by remote id opt 37, as "<enterprise>:<hex>"
+    #[argh(option, from_str_fn(parse_remote_id))]
+    pub remote_id: Option<Vec<u8>>,
+    /// link address to limit the query to, without another query this
+    /// queries all bindings on the link [default: ::]
+    #[argh(option, default = "Ipv6Addr::UNSPECIFIED")]
+    pub link: Ipv6Addr,
+    /// mac address for the requestor's DUID-LL (use "random" for a random mac) [default: first interface mac]
+    #[argh(
+        option,
+        short = 'c',
+        from_str_fn(parse_mac),
+        default = "opts::get_mac()"
+    )]
+    pub chaddr: Chaddr,
+    /// params to request in the client data: [default: 47 (LqRelayData)]
+    #[argh(option, default = "default_params()")]
+    pub params: V6ParamList,
+    /// add opts to the message ("code,type,value")
+    #[argh(option, short = 'o', from_str_fn(parse_v6_opts))]
+    pub opt: Vec<V6Opt>,
+}
+
+fn default_params() -> V6ParamList {
+    V6ParamList(vec![v6::OptionCode::LqRelayData])
+}
+
+/// remote id is the enterprise number followed by the id (RFC 4649)
+fn parse_remote_id(id: &str) -> Result<Vec<u8>, String> {
+    let (num, id) = id
+        .split_once(':')
+        .ok_or_else(|| format!("remote id {id:?} must be \"<enterprise>:<hex>\""))?;
+    let num = num
+        .parse::<u32>()
+        .map_err(|err| format!("invalid enterprise number {num:?}: {err}"))?;
+    let mut buf = num.to_be_bytes().to_vec();
+    buf.extend(hex::decode(id.replace(':', "")).map_err(|err| err.to_string())?);
+    Ok(buf)
+}
+
+fn unknown(code: v6::OptionCode, data: Vec<u8>) -> v6::DhcpOption {
+    v6::DhcpOption::Unknown(v6::UnknownOption::new(code, data))
+}
+
+/// the query carried in OPTION_LQ_QUERY (44)
+struct Query<'a> {
+    ip: Option<Ipv6Addr>,
+    client_id: Option<&'a [u8]>,
+    relay_id: Option<&'a [u8]>,
+    remote_id: Option<&'a [u8]>,
+    link: Ipv6Addr,
+    chaddr: Chaddr,
+    params: &'a V6ParamList,
+    opt: &'a [V6Opt],
+}
+
+impl Query<'_> {
+    fn build(&self) -> Result<v6::Message> {
+        let mut query_opts = v6::DhcpOptions::new();
+        let queries = [
+            self.ip.is_some(),
+            self.client_id.is_some(),
+            self.relay_id.is_some(),
+            self.remote_id.is_some(),
+        ];
+        let query_type = match queries {
+            [true, false, false, false] => {
+                query_opts.insert(v6::DhcpOption::IAAddr(v6::IAAddr {
+                    addr: self.ip.unwrap(),
+                    preferred_life: 0,
+                    valid_life: 0,
+                    opts: v6::DhcpOptions::new(),
+                }));
+                QUERY_BY_ADDRESS
+            }
+            [false, true, false, false] => {
+                query_opts.insert(v6::DhcpOption::ClientId(self.client_id.unwrap().to_vec()));
+                QUERY_BY_CLIENTID
+            }
+            [false, false, true, false] => {
+                query_opts.insert(unknown(
+                    v6::OptionCode::RelayId,
+                    self.relay_id.unwrap().to_vec(),
+                ));
+                QUERY_BY_RELAY_ID
+            }
+            [false, false, false, true] => {
+                query_opts.insert(unknown(
+                    v6::OptionCode::RemoteId,
+                    self.remote_id.unwrap().to_vec(),
+                ));
+                QUERY_BY_REMOTE_ID
+            }
+            [false, false, false, false] if !self.link.is_unspecified() => QUERY_BY_LINK_ADDRESS,
+            _ => bail!(
+                "leasequery needs exactly one of --ip, --client-id, --relay-id, --remote-id or only --link"
+            ),
+        };
+        if !self.params.is_empty() {
+            query_opts.insert(v6::DhcpOption::ORO(v6::ORO {
+                opts: self.params.0.clone(),
+            }));
+        }
+        let mut data = vec![query_type];
+        data.extend(self.link.octets());
+        data.extend(query_opts.to_vec()?);
+
+        let mut msg = v6::Message::new(v6::MessageType::LeaseQuery);
+        // the requestor must identify itself (RFC 5007 section 4.1.2)
+        msg.opts_mut()
+            .insert(v6::DhcpOption::ClientId(opts::v6::duid_ll(self.chaddr)));
+        msg.opts_mut()
+            .insert(unknown(v6::OptionCode::LqQuery, data));
+        // insert manually entered opts
+        opts::v6::insert_opts(&mut msg, self.opt);
+        Ok(msg)
+    }
+}
+
+impl LeaseQuery6Args {
+    pub fn build(&self) -> Result<v6::Message> {
+        if self.ip.is_some() == self.client_id.is_some() {
+            bail!("leasequery6 needs exactly one of --ip or --client-id");
+        }
+        Query {
+            ip: self.ip,
+            client_id: self.client_id.as_deref(),
+            relay_id: None,
+            remote_id: None,
+            link: self.link,
+            chaddr: self.chaddr,
+            params: &self.params,
+            opt: &self.opt,
+        }
+        .build()
+    }
+}
+
+/// a binding from OPTION_CLIENT_DATA (45), printed as a JSON line
+#[derive(Debug, Default, Serialize)]
+pub struct Binding {
+    pub client_id: Option<String>,
+    pub addrs: Vec<v6::IAAddr>,
+    pub prefixes: Vec<v6::IAPrefix>,
+    /// seconds since the server last communicated with the client, opt 46
+    pub clt_time: Option<u32>,
+    /// address of the relay closest to the client & its RELAY-FORW, opt 47
+    pub peer_addr: Option<Ipv6Addr>,
+    pub relay_msg: Option<v6::RelayMessage>,
+    pub opts: Vec<v6::DhcpOption>,
+}
+
+impl Binding {
+    fn decode(data: &[u8]) -> Result<Self> {
+        let opts = v6::DhcpOptions::decode(&mut Decoder::new(data))?;
+        let mut binding = Binding::default();
+        for opt in opts.iter() {
+            match opt {
+                v6::DhcpOption::ClientId(id) => binding.client_id = Some(hex::encode(id)),
+                v6::DhcpOption::IAAddr(addr) => binding.addrs.push(addr.clone()),
+                v6::DhcpOption::IAPrefix(prefix) => binding.prefixes.push(prefix.clone()),
+                v6::DhcpOption::Unknown(opt) if opt.code() == v6::OptionCode::CltTime => {
+                    binding.clt_time = opt.data().try_into().ok().map(u32::from_be_bytes);
+                }
+                v6::DhcpOption::Unknown(opt) if opt.code() == v6::OptionCode::LqRelayData => {
+                    if opt.data().len() < 16 {
+                        bail!("lq-relay-data too short ({} bytes)", opt.data().len());
+                    }
+                    let (peer, relay) = opt.data().split_at(16);
+                    binding.peer_addr = Some(<[u8; 16]>::try_from(peer)?.into());
+                    binding.relay_msg = Some(v6::RelayMessage::decode(&mut Decoder::new(relay))?);
+                }
+                opt => binding.opts.push(opt.clone()),
+            }
+        }
+        Ok(binding)
+    }
+}
+
+/// link addresses from OPTION_LQ_CLIENT_LINK (48), sent instead of client
+/// data when the client has bindings on more than one link
+fn client_links(msg: &v6::Message) -> Vec<Ipv6Addr> {
+    match msg.opts().get(v6::OptionCode::LqClientLink) {
+        Some(v6::DhcpOption::Unknown(opt)) => opt
+            .data()
+            .chunks_exact(16)
+            .map(|ip| Ipv6Addr::from(<[u8; 16]>::try_from(ip).unwrap()))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn client_data(msg: &v6::Message) -> Result<Option<Binding>> {
+    match msg.opts().get(v6::OptionCode::ClientData) {
+        Some(v6::DhcpOption::Unknown(opt)) => Binding::decode(opt.data()).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// a status code other than success is an error
+fn check_status(msg: &v6::Message) -> Result<()> {
+    match msg.opts().get(v6::OptionCode::StatusCode) {
+        Some(v6::DhcpOption::StatusCode(code)) if code.status != v6::Status::Success => {
+            bail!("leasequery failed: {:?} {}", code.status, code.msg)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Log the binding from a LEASEQUERY-REPLY
+pub fn report(msg: &v6::Message) -> Result<()> {
+    if msg.msg_type() != v6::MessageType::LeaseQueryReply {
+        warn!(msg_type = ?msg.msg_type(), "expected a LEASEQUERY-REPLY");
+        return Ok(());
+    }
+    check_status(msg)?;
+    let links = client_links(msg);
+    match client_data(msg)? {
+        Some(binding) => info!(
+            client_id = ?binding.client_id,
+            addrs = ?binding.addrs.iter().map(|a| a.addr).collect::<Vec<_>>(),
+            prefixes = ?binding.prefixes.iter().map(|p| format!("{}/{}", p.prefix_ip, p.prefix_len)).collect::<Vec<_>>(),
+            valid = ?binding.addrs.iter().map(|a| a.valid_life).collect::<Vec<_>>(),
+            clt_time = ?binding.clt_time,
+            peer_addr = ?binding.peer_addr,
+            relay_link = ?binding.relay_msg.as_ref().map(|r| r.link_addr()),
+            "binding"
+        ),
+        None if !links.is_empty() => {
+            info!(
+                ?links,
+                "client has bindings on multiple links, query again with --link"
+            )
+        }
+        None => info!("no binding found"),
+    }
+    Ok(())
+}
+
+impl BulkLeaseQuery6Args {
+    pub fn build(&self) -> Result<v6::Message> {
+        Query {
+            ip: self.ip,
+            client_id: self.client_id.as_deref(),
+            relay_id: self.relay_id.as_deref(),
+            remote_id: self.remote_id.as_deref(),
+            link: self.link,
+            chaddr: self.chaddr,
+            params: &self.params,
+            opt: &self.opt,
+        }
+        .build()
+    }
+
+    /// Connect to the server, send the query & print each binding as a JSON
+    /// line until LEASEQUERY-DONE or the server closes the connection
+    pub fn run(&self, args: &Args) -> Result<()> {
+        let start = Instant::now();
+        let (target, _) = args.get_target();
+        if !target.is_ipv6() {
+            bail!("bulk-leasequery6 is dhcpv6 only");
+        }
+        let timeout = Duration::from_secs(args.timeout);
+        let mut stream = tcp::connect(target, args.bind, timeout)?;
+
+        let query = self.build()?;
+        tcp::send(&mut stream, &query.to_vec()?)?;
+        let sent = Msg::V6(query.clone());
+        info!(msg_type = %sent.get_type(), ?target, msg = %PrettyPrint(&sent, args.output), "SENT");
+
+        let mut count = 0;
+        while let Some(buf) = tcp::recv(&mut stream)? {
+            let msg = v6::Message::decode(&mut Decoder::new(&buf))?;
+            if msg.xid() != query.xid() {
+                warn!(xid = msg.xid_num(), "reply xid doesn't match the query");
+            }
+            debug!(msg = %PrettyPrint(&Msg::V6(msg.clone()), args.output), "RECEIVED");
+            match msg.msg_type() {
+                v6::MessageType::LeaseQueryReply => {
+                    check_status(&msg)?;
+                    let links = client_links(&msg);
+                    if !links.is_empty() {
+                        info!(?links, "client has bindings on multiple links");
+                    }
+                }
+                v6::MessageType::LeaseQueryData => {}
+                v6::MessageType::LeaseQueryDone => {
+                    info!(bindings = count, elapsed = %PrettyTime(start.elapsed()), "LEASEQUERY-DONE");
+                    return check_status(&msg);
+                }
+                other => {
+                    warn!(msg_type = ?other, "unexpected reply to bulk leasequery");
+                    continue;
+                }
+            }
+            if let Some(binding) = client_data(&msg)? {
+                count += 1;
+                println!("{}", serde_json::to_string(&binding)?);
+            }
+        }
+        warn!(bindings = count, elapsed = %PrettyTime(start.elapsed()), "server closed the connection without LEASEQUERY-DONE");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the query type, link address & options of OPTION_LQ_QUERY
+    fn query(msg: &v6::Message) -> (u8, Ipv6Addr, v6::DhcpOptions) {
+        let Some(v6::DhcpOption::Unknown(opt)) = msg.opts().get(v6::OptionCode::LqQuery) else {
+            panic!("no lq-query")
+        };
+        let data = opt.data();
+        (
+            data[0],
+            <[u8; 16]>::try_from(&data[1..17]).unwrap().into(),
+            v6::DhcpOptions::decode(&mut Decoder::new(&data[17..])).unwrap(),
+        )
+    }
+
+    #[test]
+    fn by_address() {
+        let args = LeaseQuery6Args::from_args(
+            &["leasequery6"],
+            &["--ip", "2001:db8::5", "-c", "aa:bb:cc:dd:ee:ff"],
+        )
+        .unwrap();
+        let msg = args.build().unwrap();
+        assert_eq!(msg.msg_type(), v6::MessageType::LeaseQuery);
+        assert_eq!(
+            msg.opts().get(v6::OptionCode::ClientId),
+            Some(&v6::DhcpOption::ClientId(
+                hex::decode("00030001aabbccddeeff").unwrap()
+            ))
+        );
+        let (ty, link, opts) = query(&msg);
+        assert_eq!((ty, link), (QUERY_BY_ADDRESS, Ipv6Addr::UNSPECIFIED));
+        match opts.get(v6::OptionCode::IAAddr) {
+            Some(v6::DhcpOption::IAAddr(addr)) => {
+                assert_eq!(addr.addr, "2001:db8::5".parse::<Ipv6Addr>().unwrap())
+            }
+            opt => panic!("{opt:?}"),
+        }
+        assert_eq!(
+            opts.get(v6::OptionCode::ORO),
+            Some(&v6::DhcpOption::ORO(v6::ORO {
+                opts: vec![v6::OptionCode::LqRelayData]
+            }))
+        );
+
+        let args = LeaseQuery6Args::from_args(&["leasequery6"], &[]).unwrap();
+        assert!(args.build().is_err());
+    }
+
+    #[test]
+    fn bulk() {
+        let bulk = |args: &[&str]| {
+            BulkLeaseQuery6Args::from_args(&["bulk-leasequery6"], args)
+                .unwrap()
+                .build()
+        };
+        let (ty, link, _) = query(&bulk(&["--link", "2001:db8::"]).unwrap());
+        assert_eq!(ty, QUERY_BY_LINK_ADDRESS);
+        assert_eq!(link, "2001:db8::".parse::<Ipv6Addr>().unwrap());
+
+        let (ty, _, opts) = query(&bulk(&["--remote-id", "9:0a0b"]).unwrap());
+        assert_eq!(ty, QUERY_BY_REMOTE_ID);
+        assert_eq!(
+            opts.get(v6::OptionCode::RemoteId),
+            Some(&unknown(v6::OptionCode::RemoteId, vec![0, 0, 0, 9, 10, 11]))
+        );
+        let (ty, _, _) = query(&bulk(&["--relay-id", "000100"]).unwrap());
+        assert_eq!(ty, QUERY_BY_RELAY_ID);
+
+        assert!(bulk(&[]).is_err());
+        assert!(bulk(&["--relay-id", "00", "--remote-id", "9:0a"]).is_err());
+        assert!(parse_remote_id("0a0b").is_err());
+    }
+
+    #[test]
+    fn binding() {
+        let mut opts = v6::DhcpOptions::new();
+        opts.insert(v6::DhcpOption::ClientId(vec![0, 1]));
+        opts.insert(v6::DhcpOption::IAAddr(v6::IAAddr {
+            addr: "2001:db8::5".parse().unwrap(),
+            preferred_life: 100,
+            valid_life: 200,
+            opts: v6::DhcpOptions::new(),
+        }));
+        opts.insert(unknown(
+            v6::OptionCode::CltTime,
+            30_u32.to_be_bytes().to_vec(),
+        ));
+        let binding = Binding::decode(&opts.to_vec().unwrap()).unwrap();
+        assert_eq!(binding.client_id.as_deref(), Some("0001"));
+        assert_eq!(binding.addrs[0].valid_life, 200);
+        assert_eq!(binding.clt_time, Some(30));
+
+        let mut short = v6::DhcpOptions::new();
+        short.insert(unknown(v6::OptionCode::LqRelayData, vec![0; 4]));
+        assert!(Binding::decode(&short.to_vec().unwrap()).is_err());
+    }
+
+    #[test]
+    fn reply() {
+        let mut msg = v6::Message::new(v6::MessageType::LeaseQueryReply);
+        let links = ["2001:db8:1::", "2001:db8:2::"].map(|ip| ip.parse::<Ipv6Addr>().unwrap());
+        msg.opts_mut().insert(unknown(
+            v6::OptionCode::LqClientLink,
+            links.iter().flat_map(|ip| ip.octets()).collect(),
+        ));
+        assert_eq!(client_links(&msg), links);
+        check_status(&msg).unwrap();
+        msg.opts_mut()
+            .insert(v6::DhcpOption::StatusCode(v6::StatusCode {
+                status: v6::Status::NotConfigured,
+                msg: String::new(),
+            }));
+        assert!(check_status(&msg).is_err());
+    }
+}
@@ -9,9 +9,9 @@
 #![allow(clippy::cognitive_complexity)]
 
 use std::{
-    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket},
     sync::Arc,
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 #[cfg(unix)]
@@ -26,29 +26,56 @@ use anyhow::{bail, Context, Result};
 use argh::FromArgs;
 use crossbeam_channel::{Receiver, Sender};
 use dhcproto::{v4, v6};
-use mac_address::MacAddress;
 use opts::LogStructure;
 use pnet_datalink::NetworkInterface;
-use tracing::{error, info, trace};
+use tracing::{debug, error, info, info_span, trace, warn};
 
+mod auth;
 mod bootreq;
+mod bulkquery;
+mod codes;
 mod decline;
 mod discover;
+mod forcerenew;
+mod fqdn;
 mod inforeq;
 mod inform;
+mod interfaces;
+mod leasequery;
+mod leasequery6;
 mod opts;
+mod overload;
+mod probe;
+mod pxe;
+mod relay;
 mod release;
 mod request;
 mod runner;
 #[cfg(feature = "script")]
 mod script;
+mod solicit;
+mod tcp;
+mod tftp;
+mod vendor;
 
-use opts::{parse_mac, parse_opts, ParamList};
+use opts::{parse_mac, parse_opts, Chaddr, ParamList};
 use runner::TimeoutRunner;
 
 use crate::{
-    bootreq::BootReqArgs, decline::DeclineArgs, discover::DiscoverArgs,
-    inforeq::InformationReqArgs, inform::InformArgs, release::ReleaseArgs, request::RequestArgs,
+    bootreq::BootReqArgs,
+    bulkquery::BulkLeaseQueryArgs,
+    codes::OptionsArgs,
+    decline::DeclineArgs,
+    discover::DiscoverArgs,
+    inforeq::InformationReqArgs,
+    inform::InformArgs,
+    interfaces::InterfacesArgs,
+    leasequery::LeaseQueryArgs,
+    leasequery6::{BulkLeaseQuery6Args, LeaseQuery6Args},
+    pxe::PxeArgs,
+    release::ReleaseArgs,
+    request::RequestArgs,
+    solicit::SolicitArgs,
     util::Msg,
 };
 
@@ -56,7 +83,14 @@ const V6_MULTICAST: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 1, 2);
 
 #[allow(clippy::collapsible_else_if)]
 fn main() -> Result<()> {
+    // doesn't send anything, no need for a target or a socket
+    if run_without_target()? {
+        return Ok(());
+    }
     let mut args: Args = argh::from_env();
+    if let Some(MsgType::Solicit(solicit)) = &args.msg {
+        solicit.validate()?;
+    }
 
     let mut default_port = false;
     // set default port to send if none provided
@@ -69,6 +103,34 @@ fn main() -> Result<()> {
         }
     }
 
+    // bulk leasequery runs over TCP, it doesn't need the UDP socket
+    if let Some(MsgType::BulkLeaseQuery(bulk)) = &args.msg {
+        opts::init_tracing(&args);
+        return bulk.run(&args);
+    }
+    if let Some(MsgType::BulkLeaseQuery6(bulk)) = &args.msg {
+        opts::init_tracing(&args);
+        return bulk.run(&args);
+    }
+
+    if let Some(relay) = args.relay {
+        if args.target.is_ipv6() {
+            bail!("--relay is only supported for dhcpv4");
+        }
+        // a relay agent listens on the server port at giaddr
+        if args.bind.is_none() {
+            args.bind = Some(SocketAddr::new(IpAddr::V4(relay), v4::SERVER_PORT));
+        }
+    }
+
+    // leasequery replies are sent to the server port at giaddr, like a relay
+    if let (Some(MsgType::LeaseQuery(lq)), None) = (&args.msg, args.bind) {
+        if !lq.giaddr.is_unspecified() {
+            args.bind = Some(SocketAddr::new(IpAddr::V4(lq.giaddr), v4::SERVER_PORT));
+        }
+    }
+
+    let bind_given = args.bind.is_some();
     if args.bind.is_none() {
         if args.target.is_ipv6() {
             if default_port {
@@ -92,11 +154,150 @@ fn main() -> Result<()> {
     }
 
     opts::init_tracing(&args);
+    if args.interface == ["auto"] {
+        let (int, broadcast) = interfaces::route_to(args.target)?;
+        // a limited broadcast goes to the broadcast address of the route's network
+        if let (IpAddr::V4(target), Some(broadcast)) = (args.target, broadcast) {
+            if target.is_broadcast() {
+                info!(%broadcast, "sending to the broadcast address of {}", int.name);
+                args.target = IpAddr::V4(broadcast);
+            }
+        }
+        args.interface = vec![int.name];
+    } else if !args.interface.is_empty() {
+        args.interface = interfaces::select(&args.interface, args.target)?;
+    }
+    // dora probes the ACK itself, don't probe the same address twice
+    if let (true, Some(MsgType::Dora(dora))) = (args.probe, &args.msg) {
+        if dora.arp_probe {
+            debug!("--arp-probe probes the dora lease, skipping --probe");
+            args.probe = false;
+        }
+    }
+    // the REQUEST state decides where it's sent (RFC 2131 section 4.3.2)
+    if let Some(MsgType::Request(req)) = &args.msg {
+        args.target =
+            req.state_target(args.target, args.relay.is_some(), &request::v4_networks())?;
+    }
     trace!(?args);
-    let interface = find_interface(&args.interface)?;
+    if args.interface.len() > 1 {
+        return run_each(args, bind_given);
+    }
+    let shutdown_rx = ctrl_channel(1)?.remove(0);
+    run(args, bind_given, shutdown_rx)?;
+    Ok(())
+}
+
+/// run `options` or `interfaces` if it's the first argument, they don't send
+/// anything so `Args` shouldn't ask for a target. Returns false for other commands
+fn run_without_target() -> Result<bool> {
+    let argv = std::env::args().collect::<Vec<_>>();
+    let argv = argv.iter().map(String::as_str).collect::<Vec<_>>();
+    let (Some(bin), Some(&name)) = (argv.first(), argv.get(1)) else {
+        return Ok(false);
+    };
+    let bin = std::path::Path::new(bin)
+        .file_name()
+        .and_then(|bin| bin.to_str())
+        .unwrap_or(bin);
+    match name {
+        "options" => parse_cmd::<OptionsArgs>(&[bin, name], &argv[2..]).run()?,
+        "interfaces" => parse_cmd::<InterfacesArgs>(&[bin, name], &argv[2..]).run(),
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// parse a subcommand on its own, exiting like `argh::from_env` on help or errors
+fn parse_cmd<T: FromArgs>(cmd: &[&str], args: &[&str]) -> T {
+    T::from_args(cmd, args).unwrap_or_else(|exit| match exit.status {
+        Ok(()) => {
+            println!("{}", exit.output);
+            std::process::exit(0)
+        }
+        Err(()) => {
+            eprintln!(
+                "{}\nRun {} --help for more information.",
+                exit.output,
+                cmd.join(" ")
+            );
+            std::process::exit(1)
+        }
+    })
+}
+
+/// run the message or flow on each interface concurrently, each with its own
+/// socket & chaddr (the interface's MAC unless -c was given), then print a
+/// table of the results
+fn run_each(args: Args, bind_given: bool) -> Result<()> {
+    let default_mac = opts::get_mac();
+    let shutdown = ctrl_channel(args.interface.len())?;
+    let outcomes = std::thread::scope(|scope| {
+        let handles = args
+            .interface
+            .iter()
+            .zip(shutdown)
+            .map(|(name, shutdown_rx)| {
+                let mut args = args.clone();
+                args.interface = vec![name.clone()];
+                let chaddr = args.msg.as_mut().and_then(MsgType::chaddr_mut);
+                if let Some(chaddr) = chaddr.filter(|chaddr| **chaddr == default_mac) {
+                    let int = find_interface(Some(name)).ok().flatten();
+                    if let Some(mac) = int.and_then(|int| int.mac) {
+                        *chaddr = mac.octets().into();
+                    }
+                }
+                let chaddr = args.msg.as_mut().and_then(MsgType::chaddr_mut).map(|c| *c);
+                scope.spawn(move || {
+                    let _span = info_span!("interface", name = %name).entered();
+                    let start = Instant::now();
+                    let result = run(args, bind_given, shutdown_rx);
+                    if let Err(err) = &result {
+                        error!(%err, "failed on interface");
+                    }
+                    interfaces::Outcome {
+                        interface: name.clone(),
+                        chaddr,
+                        elapsed: start.elapsed(),
+                        result,
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("interface thread panicked"))
+            .collect::<Vec<_>>()
+    });
+    interfaces::report(&outcomes);
+    let failed = outcomes.iter().filter(|o| o.result.is_err()).count();
+    if failed > 0 {
+        bail!("failed on {failed} of {} interfaces", outcomes.len());
+    }
+    Ok(())
+}
+
+/// set up the socket on `args.interface` & run the message or flow, returning
+/// the last reply
+fn run(mut args: Args, bind_given: bool, shutdown_rx: Receiver<()>) -> Result<Option<Msg>> {
+    let interface = find_interface(args.interface())?;
     trace!(?interface);
 
-    let bind_addr: SocketAddr = args.bind.context("bind address must be specified")?;
+    // a v6 client sends to multicast & link-local targets from its link-local
+    // address (RFC 8415 section 13.1), bind to it unless --bind was given
+    if let (false, Some(int), IpAddr::V6(target)) = (bind_given, &interface, args.target) {
+        if target.is_multicast() || interfaces::is_link_local(target) {
+            match find_link_local(int) {
+                Some(ip) => {
+                    let port = args.bind.map(|bind| bind.port()).unwrap_or_default();
+                    args.bind = Some(SocketAddrV6::new(ip, port, 0, int.index).into());
+                }
+                None => warn!(interface = %int.name, "no link-local address, binding to ::"),
+            }
+        }
+    }
+
+    let mut bind_addr: SocketAddr = args.bind.context("bind address must be specified")?;
     let socket = socket2::Socket::new(
         if args.target.is_ipv6() {
             socket2::Domain::IPV6
@@ -119,24 +320,45 @@ fn main() -> Result<()> {
         socket.set_broadcast(true)?;
     }
 
-    socket
-        .bind(&bind_addr.into())
-        .context("failed to bind addr")?;
+    // bind to the device first, runs on other interfaces may share the port
+    if let Some(int) = &interface {
+        #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+        socket
+            .bind_device(Some(int.name.as_bytes()))
+            .context("SO_BINDTODEVICE failed")?;
+        #[cfg(target_vendor = "apple")]
+        socket
+            .bind_device_by_index_v4(std::num::NonZeroU32::new(int.index))
+            .context("IP_BOUND_IF")?;
+    }
+    if let Err(err) = socket.bind(&bind_addr.into()) {
+        match bind_addr {
+            // the link-local address may still be tentative, fall back to ::
+            SocketAddr::V6(addr) if !bind_given && addr.scope_id() != 0 => {
+                warn!(%err, %bind_addr, "binding to link-local address failed, binding to ::");
+                bind_addr = (Ipv6Addr::UNSPECIFIED, addr.port()).into();
+                args.bind = Some(bind_addr);
+                socket
+                    .bind(&bind_addr.into())
+                    .context("failed to bind addr")?;
+            }
+            _ => return Err(err).context("failed to bind addr"),
+        }
+    }
 
     match interface {
         Some(int) => {
-            #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
-            socket
-                .bind_device(Some(int.name.as_bytes()))
-                .context("SO_BINDTODEVICE failed")?;
-            #[cfg(target_vendor = "apple")]
-            socket
-                .bind_device_by_index_v4(std::num::NonZeroU32::new(int.index))
-                .context("IP_BOUND_IF")?;
-            if bind_addr.is_ipv6() && bind_addr.ip() == V6_MULTICAST {
+            let multicast = matches!(args.target, IpAddr::V6(ip) if ip.is_multicast());
+            if bind_addr.ip() == V6_MULTICAST {
                 socket
                     .join_multicast_v6(&V6_MULTICAST, int.index)
                     .context("join v6 multicast")?;
+            } else if multicast {
+                if let Err(err) = socket.join_multicast_v6(&V6_MULTICAST, int.index) {
+                    warn!(%err, interface = %int.name, "failed to join {V6_MULTICAST}");
+                }
+            }
+            if bind_addr.ip() == V6_MULTICAST || multicast {
                 socket
                     .set_multicast_if_v6(int.index)
                     .context("set multicast interface")?;
@@ -144,7 +366,8 @@ fn main() -> Result<()> {
             }
         }
         None => {
-            if bind_addr.is_ipv6() {
+            // leasequery6 is unicast to the server, it doesn't need an interface
+            if bind_addr.is_ipv6() && !matches!(args.msg, Some(MsgType::LeaseQuery6(_))) {
                 bail!("an interface must be specified for ipv6");
             }
         }
@@ -161,7 +384,6 @@ fn main() -> Result<()> {
     };
     let soc = Arc::new(socket);
 
-    let shutdown_rx = ctrl_channel()?;
     // messages put on `send_tx` will go out on the socket
     let (send_tx, send_rx) = crossbeam_channel::bounded(1);
     // messages coming from `recv_rx` were received from the socket
@@ -191,7 +413,7 @@ fn main() -> Result<()> {
             error!(?err, "error running rhai script");
         }
         info!(elapsed = %util::PrettyTime(start.elapsed()), "script completed");
-        return Ok(());
+        return Ok(None);
     }
 
     // clone new args so we still have the original in case we need to
@@ -204,6 +426,10 @@ fn main() -> Result<()> {
                 new_args.msg = Some(MsgType::Discover(dora.discover()));
                 new_args
             }
+            Some(MsgType::Pxe(pxe)) => {
+                new_args.msg = Some(MsgType::Discover(pxe.discover()));
+                new_args
+            }
             _ => new_args,
         },
         shutdown_rx.clone(),
@@ -212,31 +438,110 @@ fn main() -> Result<()> {
     )?;
 
     // then to request for the next run
+    let mut offer = None;
     let new_args = match (&args.msg, msg) {
+        (Some(MsgType::Discover(discover)), Msg::V4(msg)) if discover.rapid_commit => {
+            discover::rapid_commit_done(&msg)?;
+            return Ok(Some(Msg::V4(msg)));
+        }
+        (Some(MsgType::Dora(dora)), Msg::V4(msg))
+            if dora.rapid_commit && discover::rapid_commit_done(&msg)? =>
+        {
+            let msg = dora_bound(&args, dora, msg, &shutdown_rx, &send_tx, &recv_rx)?;
+            info!(elapsed = %util::PrettyTime(start.elapsed()), "total time");
+            return Ok(Some(msg));
+        }
         (Some(MsgType::Dora(dora)), Msg::V4(msg)) => {
             let mut new_args = args.clone();
             new_args.msg = Some(MsgType::Request(dora.request(msg.yiaddr())));
             new_args
         }
+        (Some(MsgType::Pxe(pxe_args)), Msg::V4(msg)) => {
+            pxe::report(&msg);
+            let mut new_args = args.clone();
+            new_args.msg = Some(MsgType::Request(pxe_args.request(&msg)));
+            offer = Some(msg);
+            new_args
+        }
+        (Some(MsgType::BootReq(bootreq)), Msg::V4(msg)) if bootreq.tftp => {
+            tftp::fetch_boot_file(
+                &msg,
+                bootreq.tftp_port,
+                bootreq.tftp_blksize,
+                Duration::from_secs(args.timeout),
+            )?;
+            return Ok(Some(Msg::V4(msg)));
+        }
+        (Some(MsgType::LeaseQuery(_)), Msg::V4(msg)) => {
+            leasequery::report(&msg);
+            return Ok(Some(Msg::V4(msg)));
+        }
+        (Some(MsgType::LeaseQuery6(_)), Msg::V6(msg)) => {
+            leasequery6::report(&msg)?;
+            return Ok(Some(Msg::V6(msg)));
+        }
+        (Some(MsgType::Solicit(solicit)), Msg::V6(msg)) => {
+            solicit.check_reply(&msg)?;
+            solicit.verify_dns(&msg, Duration::from_secs(args.timeout))?;
+            if let Some(wait) = solicit.wait_reconfigure {
+                solicit.wait_reconfigure(
+                    &msg,
+                    &recv_rx,
+                    &shutdown_rx,
+                    Duration::from_secs(wait),
+                    args.output,
+                )?;
+            }
+            return Ok(Some(Msg::V6(msg)));
+        }
         // exit if we were just meant to send 1 message
-        _ => {
+        (_, msg) => {
             drop(send_tx);
             drop(recv_rx);
-            return Ok(());
+            return Ok(Some(msg));
         }
     };
-    run_it(move || new_args, shutdown_rx, send_tx, recv_rx)?;
+    let mut msg = run_it(
+        move || new_args,
+        shutdown_rx.clone(),
+        send_tx.clone(),
+        recv_rx.clone(),
+    )?;
+    if let (Some(MsgType::Dora(dora)), Msg::V4(ack)) = (&args.msg, &msg) {
+        msg = dora_bound(&args, dora, ack.clone(), &shutdown_rx, &send_tx, &recv_rx)?;
+    }
+    if let (Some(MsgType::Pxe(pxe_args)), Msg::V4(ack), Some(offer)) = (&args.msg, &msg, &offer) {
+        pxe::report(ack);
+        let mut boot_msg = ack.clone();
+        // continue on to the boot server on port 4011
+        if pxe_args.boot {
+            let boot_args = pxe_args.boot_args(&args, offer, ack)?;
+            if let Msg::V4(reply) = run_it(move || boot_args, shutdown_rx, send_tx, recv_rx)? {
+                pxe::report(&reply);
+                boot_msg = reply;
+            }
+        }
+        if pxe_args.tftp {
+            tftp::fetch_boot_file(
+                &boot_msg,
+                pxe_args.tftp_port,
+                pxe_args.tftp_blksize,
+                Duration::from_secs(args.timeout),
+            )?;
+        }
+        msg = Msg::V4(boot_msg);
+    }
 
     info!(elapsed = %util::PrettyTime(start.elapsed()), "total time");
 
-    Ok(())
+    Ok(Some(msg))
 }
 
 fn run_it<F: FnOnce() -> Args>(
     f: F,
     shutdown_rx: Receiver<()>,
     send_tx: Sender<(Msg, SocketAddr, bool)>,
-    recv_rx: Receiver<(Msg, SocketAddr)>,
+    recv_rx: Receiver<(Msg, SocketAddr, Vec<u8>)>,
 ) -> Result<Msg> {
     let args = f();
     let runner = TimeoutRunner {
@@ -254,13 +559,126 @@ fn run_it<F: FnOnce() -> Args>(
     }
 }
 
-fn ctrl_channel() -> Result<Receiver<()>> {
-    let (sender, receiver) = crossbeam_channel::bounded(1);
+/// finish a dora once it has an `ack`, from the REQUEST or a rapid commit
+/// DISCOVER: probe the lease, check DNS & wait for a FORCERENEW to RENEW.
+/// Returns the last reply
+fn dora_bound(
+    args: &Args,
+    dora: &DoraArgs,
+    ack: v4::Message,
+    shutdown_rx: &Receiver<()>,
+    send_tx: &Sender<(Msg, SocketAddr, bool)>,
+    recv_rx: &Receiver<(Msg, SocketAddr, Vec<u8>)>,
+) -> Result<Msg> {
+    let ack = probe_lease(args, dora, ack, shutdown_rx, send_tx, recv_rx)?;
+    dora.verify_dns(&ack, Duration::from_secs(args.timeout))?;
+    let Some(wait) = dora.forcerenew else {
+        return Ok(Msg::V4(ack));
+    };
+    forcerenew::wait(
+        &ack,
+        args.auth_key.as_ref(),
+        recv_rx,
+        shutdown_rx,
+        Duration::from_secs(wait),
+        args.output,
+    )?;
+    // unicast the RENEW to the server that sent the ACK
+    let mut renew_args = args.clone();
+    if let Some(v4::DhcpOption::ServerIdentifier(ip)) =
+        ack.opts().get(v4::OptionCode::ServerIdentifier)
+    {
+        renew_args.target = IpAddr::V4(*ip);
+    }
+    renew_args.msg = Some(MsgType::Request(dora.renew(&ack)));
+    run_it(
+        move || renew_args,
+        shutdown_rx.clone(),
+        send_tx.clone(),
+        recv_rx.clone(),
+    )
+}
+
+/// ARP probe the address in a dora `ack` with --arp-probe. On a conflict (or
+/// with --force-decline) DECLINE it & run DORA again, returning the ACK to
+/// carry on with
+fn probe_lease(
+    args: &Args,
+    dora: &DoraArgs,
+    ack: v4::Message,
+    shutdown_rx: &Receiver<()>,
+    send_tx: &Sender<(Msg, SocketAddr, bool)>,
+    recv_rx: &Receiver<(Msg, SocketAddr, Vec<u8>)>,
+) -> Result<v4::Message> {
+    let ip = ack.yiaddr();
+    let probe = |ip: Ipv4Addr| -> Result<bool> {
+        let interface = probe::interface_for(args.interface(), IpAddr::V4(ip))?;
+        let owner = probe::arp(&interface, ip)?;
+        probe::report(IpAddr::V4(ip), owner);
+        Ok(owner.is_some())
+    };
+    let conflict = dora.arp_probe && probe(ip)?;
+    if !conflict && !dora.force_decline {
+        return Ok(ack);
+    }
+    info!(%ip, forced = !conflict, "declining address");
+    let mut decline_args = args.clone();
+    decline_args.msg = Some(MsgType::Decline(dora.decline(&ack)));
+    TimeoutRunner {
+        args: decline_args,
+        shutdown_rx: shutdown_rx.clone(),
+        send_tx: send_tx.clone(),
+        recv_rx: recv_rx.clone(),
+    }
+    .send_only()?;
+    let wait = Duration::from_secs(dora.decline_wait);
+    info!(wait = %util::PrettyTime(wait), "waiting before restarting DORA");
+    if shutdown_rx.recv_timeout(wait).is_ok() {
+        bail!("shutdown while waiting to restart DORA after the DECLINE");
+    }
+
+    // run DORA again, the server should hand out a different address
+    let run = |msg: MsgType| -> Result<v4::Message> {
+        let mut new_args = args.clone();
+        new_args.msg = Some(msg);
+        match run_it(
+            move || new_args,
+            shutdown_rx.clone(),
+            send_tx.clone(),
+            recv_rx.clone(),
+        )? {
+            Msg::V4(msg) => Ok(msg),
+            Msg::V6(_) => bail!("expected a dhcpv4 reply"),
+        }
+    };
+    let offer = run(MsgType::Discover(dora.discover()))?;
+    let new_ack = if dora.rapid_commit && discover::rapid_commit_done(&offer)? {
+        offer
+    } else {
+        run(MsgType::Request(dora.request(offer.yiaddr())))?
+    };
+    if new_ack.yiaddr() == ip {
+        warn!(%ip, "server handed out the declined address again");
+    } else {
+        info!(declined = %ip, new = %new_ack.yiaddr(), "server handed out a different address");
+    }
+    if dora.arp_probe {
+        probe(new_ack.yiaddr())?;
+    }
+    Ok(new_ack)
+}
+
+/// `n` receivers that are each notified on ctrl-c
+fn ctrl_channel(n: usize) -> Result<Vec<Receiver<()>>> {
+    let (senders, receivers): (Vec<_>, Vec<_>) =
+        (0..n).map(|_| crossbeam_channel::bounded(1)).unzip();
     ctrlc::set_handler(move || {
-        let _ = sender.send(());
+        for sender in &senders {
+            let _ = sender.send(());
+        }
     })?;
 
-    Ok(receiver)
+    Ok(receivers)
 }
 
 #[derive(Debug, FromArgs, Clone, PartialEq, Eq)]
@@ -272,11 +690,44 @@ ex  dhcpv4:
         dhcpm 0.0.0.0 -p 9901 discover              (unicast discover to 0.0.0.0:9901)
         dhcpm 192.168.0.1 dora                      (unicast DORA to 192.168.0.1)
         dhcpm 192.168.0.1 dora -o 118,hex,C0A80001  (unicast DORA, incl opt 118:192.168.0.1)
+        dhcpm 192.168.0.1 --relay 192.168.1.1 dora  (DORA relayed from 192.168.1.1:67 to 192.168.0.1)
+        dhcpm 192.168.0.1 dora --rapid-commit       (DISCOVER with opt 80, done on ACK or DORA on OFFER)
+        dhcpm 192.168.0.1 dora --forcerenew 60      (DORA, then wait for an authenticated FORCERENEW & RENEW)
+        dhcpm 192.168.0.1 dora --fqdn host.example.com --dns-verify 192.168.0.53
+                                                    (DORA with opt 81, then check the server's DNS updates)
+        dhcpm 192.168.0.1 dora --vendor-class docsis3.0 --vi-opt 4491:1,u16,32
+                                                    (DORA with opt 60 & a V-I vendor-specific opt 125)
+        dhcpm 192.168.0.1 dora --arp-probe          (DORA, ARP probe the address & DECLINE it if in use)
+        dhcpm 192.168.0.1 request --state renewing --ciaddr 192.168.0.100
+                                                    (RENEWING REQUEST unicast to the server, RFC 2131 4.3.2)
     bootp:
         dhcpm 255.255.255.255 bootreq           (broadcast BOOTREQ)
+        dhcpm 255.255.255.255 bootreq --tftp    (BOOTREQ then fetch the boot file over TFTP)
+    pxe:
+        dhcpm 255.255.255.255 pxe -a efi-x64            (PXE DORA as a UEFI x64 client)
+        dhcpm 255.255.255.255 pxe -a efi-x64-http --http (UEFI HTTP boot DORA)
+        dhcpm 255.255.255.255 pxe --boot                 (PXE DORA then REQUEST to the boot server on 4011)
+    leasequery:
+        dhcpm 192.168.0.1 leasequery -g 192.168.1.1 --ip 192.168.1.100  (query lease by ip, reply to 192.168.1.1:67)
+        dhcpm 192.168.0.1 leasequery -g 192.168.1.1 --mac 80:FA:5B:41:10:6B
+        dhcpm 192.168.0.1 bulk-leasequery --link 192.168.1.1  (all leases on a link over TCP, as JSON lines)
     dhcpv6:
         dhcpm ::0 -p 9901 inforeq       (unicast inforeq to [::0]:9901)
         dhcpm ff02::1:2 inforeq         (multicast inforeq to default port)
+        dhcpm ff02::1:2 -i eth0 solicit --rapid-commit --reconfigure-accept --wait-reconfigure 60
+                                        (SOLICIT expecting a REPLY, then wait for a RECONFIGURE)
+        dhcpm ff02::1:2 -i eth0 --probe solicit
+                                        (SOLICIT, then DAD each offered address & report conflicts)
+        dhcpm 2001:db8::1 leasequery6 --ip 2001:db8:1::100          (query a binding by address)
+        dhcpm 2001:db8::1 bulk-leasequery6 --link 2001:db8:1::1     (all bindings on a link over TCP, as JSON lines)
+    options:
+        dhcpm options list              (list known option codes, names & types)
+        dhcpm 192.168.0.1 discover --params subnet,router,dns,ntp -o hostname,str,foo
+    interfaces:
+        dhcpm interfaces                (list interfaces with index, MAC, addresses & state)
+        dhcpm 192.168.0.1 -i auto dora  (DORA on the interface the route to 192.168.0.1 uses)
+        dhcpm 255.255.255.255 -i 'eth0.*' dora
+                                        (DORA on each eth0 VLAN at once, then print a table of results)
         ")]
 pub struct Args {
     /// ip address to send to
@@ -288,9 +739,11 @@ pub struct Args {
     /// address to bind to [default: INADDR_ANY:0]
     #[argh(option, short = 'b')]
     pub bind: Option<SocketAddr>,
-    /// interface to use (requires root or `cap_net_raw`) [default: None - selected by OS]
+    /// interface to use (requires root or `cap_net_raw`), "auto" picks the interface the
+    /// route to the target goes through. Repeat it or use a glob (ex. "eth0.*") to run on
+    /// each interface concurrently [default: None - selected by OS]
     #[argh(option, short = 'i')]
-    pub interface: Option<String>,
+    pub interface: Vec<String>,
     /// which port use. [default: 67 (v4) or 546 (v6)]
     #[argh(option, short = 'p')]
     pub port: Option<u16>,
@@ -308,9 +761,40 @@ pub struct Args {
     /// setting to "true" will prevent re-sending if we don't get a response [default: false]
     #[argh(option, default = "false")]
     pub no_retry: bool,
+    /// act as a relay agent: bind to <relay>:67, set giaddr, increment hops & add opt 82,
+    /// then validate the server replies to the relay with opt 82 echoed (dhcpv4) [default: None]
+    #[argh(option)]
+    pub relay: Option<Ipv4Addr>,
+    /// circuit id to insert into opt 82 when using --relay [default: "dhcpm"]
+    #[argh(option, default = "String::from(\"dhcpm\")")]
+    pub relay_circuit_id: String,
+    /// max size of a sent dhcpv4 message (ex. 576), options that don't fit are moved to
+    /// `file`/`sname` with opt 52 overload (RFC 2132 9.3) [default: None - no limit]
+    #[argh(option)]
+    pub max_size: Option<u16>,
+    /// authenticate sent messages & require authenticated replies with a shared secret,
+    /// "<key id>:<hex secret>". Uses delayed auth, RFC 3118 (v4) or RFC 3315 (v6) [default: None]
+    #[argh(option, from_str_fn(auth::parse_key))]
+    pub auth_key: Option<auth::Key>,
+    /// DHCP realm for dhcpv6 delayed auth [default: ""]
+    #[argh(option, default = "String::new()")]
+    pub auth_realm: String,
+    /// first replay detection value for auth, incremented for each message sent
+    /// [default: current time in ns]
+    #[argh(option)]
+    pub auth_replay: Option<u64>,
+    /// after an OFFER/ACK (v4) or ADVERTISE/REPLY (v6), ARP probe (RFC 5227) or send a Neighbor
+    /// Solicitation for DAD (RFC 4862) for each offered address & report if another host owns
+    /// it. Probes on --interface or the interface on the address's network [default: false]
+    #[argh(switch)]
+    pub probe: bool,
 }
 
 impl Args {
+    /// the interface this run uses, `main` splits multiple `-i` into one run each
+    pub fn interface(&self) -> Option<&str> {
+        self.interface.first().map(String::as_str)
+    }
     pub fn get_target(&self) -> (SocketAddr, bool) {
         match self.target {
             IpAddr::V4(addr) => {
@@ -338,21 +822,60 @@ pub enum MsgType {
     Decline(DeclineArgs),
     Dora(DoraArgs),
     BootReq(BootReqArgs),
+    Pxe(PxeArgs),
+    LeaseQuery(LeaseQueryArgs),
+    BulkLeaseQuery(BulkLeaseQueryArgs),
     InformationReq(InformationReqArgs),
+    Solicit(SolicitArgs),
+    LeaseQuery6(LeaseQuery6Args),
+    BulkLeaseQuery6(BulkLeaseQuery6Args),
+}
+
+impl MsgType {
+    /// the client's hardware address, for messages that send one
+    pub fn chaddr_mut(&mut self) -> Option<&mut Chaddr> {
+        match self {
+            MsgType::Discover(args) => Some(&mut args.chaddr),
+            MsgType::Request(args) => Some(&mut args.chaddr),
+            MsgType::Release(args) => Some(&mut args.chaddr),
+            MsgType::Inform(args) => Some(&mut args.chaddr),
+            MsgType::Decline(args) => Some(&mut args.chaddr),
+            MsgType::Dora(args) => Some(&mut args.chaddr),
+            MsgType::BootReq(args) => Some(&mut args.chaddr),
+            MsgType::Pxe(args) => Some(&mut args.chaddr),
+            MsgType::InformationReq(args) => Some(&mut args.chaddr),
+            MsgType::Solicit(args) => Some(&mut args.chaddr),
+            _ => None,
+        }
+    }
 }
 
 #[derive(FromArgs, PartialEq, Eq, Debug, Clone)]
 /// Sends Discover then Request
 #[argh(subcommand, name = "dora")]
 pub struct DoraArgs {
-    /// supply a mac address for DHCPv4 (use "random" for a random mac) [default: first interface mac]
+    /// supply a hardware address of up to 16 bytes for DHCPv4 (use "random" for a random mac,
+    /// "none" for hlen 0) [default: first interface mac]
     #[argh(
         option,
         short = 'c',
         from_str_fn(parse_mac),
         default = "opts::get_mac()"
     )]
-    pub chaddr: MacAddress,
+    pub chaddr: Chaddr,
+    /// hardware type, a number or "eth", "ieee802", "ieee1394" or "infiniband", hlen is the
+    /// length of chaddr [default: eth]
+    #[argh(option, from_str_fn(opts::parse_htype), default = "v4::HType::Eth")]
+    pub htype: v4::HType,
+    /// client id opt 61: "hwtype+mac", "mac" (no hardware type), "none", "hex:<hex>" or
+    /// "duid[:<iaid>[:<duid>]]" for an RFC 4361 IAID & DUID, defaulting to IAID 1 &
+    /// DUID-LL of chaddr [default: hwtype+mac, duid if chaddr is "none"]
+    #[argh(
+        option,
+        from_str_fn(opts::parse_client_id),
+        default = "opts::ClientId::default()"
+    )]
+    pub client_id: opts::ClientId,
     /// address of client [default: None]
     #[argh(option, default = "Ipv4Addr::UNSPECIFIED")]
     pub ciaddr: Ipv4Addr,
@@ -381,36 +904,153 @@ pub struct DoraArgs {
     /// params to include: [default: 1,3,6,15 (Subnet, Router, DnsServer, DomainName]
     #[argh(option, default = "opts::default_params()")]
     pub params: ParamList,
+    /// include rapid commit opt 80 in the DISCOVER, an ACK reply completes the
+    /// exchange without a REQUEST (RFC 4039) [default: false]
+    #[argh(switch)]
+    pub rapid_commit: bool,
+    /// after the ACK, wait this many seconds for a FORCERENEW (RFC 3203), validate its
+    /// auth with the nonce from the ACK (RFC 6704) or --auth-key (RFC 3118) & RENEW.
+    /// Adds opt 145 [default: None]
+    #[argh(option)]
+    pub forcerenew: Option<u64>,
+    /// after the ACK, ARP probe the address (RFC 5227) on --interface or the interface on its
+    /// network. On a conflict DECLINE it & run DORA again [default: false]
+    #[argh(switch)]
+    pub arp_probe: bool,
+    /// after the ACK, DECLINE the address as if it were in use & run DORA again [default: false]
+    #[argh(switch)]
+    pub force_decline: bool,
+    /// seconds to wait after a DECLINE before running DORA again, RFC 2131 section 3.1
+    /// says at least 10 [default: 10]
+    #[argh(option, default = "10")]
+    pub decline_wait: u64,
+    /// client FQDN opt 81 as "<name>[,<flags>]", flags any of S, O, E & N [default: SE], without E
+    /// the name is ASCII encoded [ex: "host.example.com,SE"] [default: None]
+    #[argh(option, from_str_fn(fqdn::parse_fqdn))]
+    pub fqdn: Option<fqdn::Fqdn>,
+    /// after the ACK, query this resolver ("<ip>[:port]") for the A, PTR & DHCID records
+    /// the server should have created for --fqdn, retrying until --timeout [default: None]
+    #[argh(option, from_str_fn(fqdn::parse_resolver))]
+    pub dns_verify: Option<SocketAddr>,
+    /// vendor class identifier opt 60 [default: None]
+    #[argh(option)]
+    pub vendor_class: Option<String>,
+    /// vendor-identifying vendor class opt 124 as "<enterprise>:<data>[,<data>...]",
+    /// can be repeated [ex: "4491:docsis3.0"]
+    #[argh(option, from_str_fn(vendor::parse_vi_class))]
+    pub vi_class: Vec<vendor::ViClass>,
+    /// vendor-identifying vendor-specific info opt 125 sub-option as
+    /// "<enterprise>:<code>,<type>,<value>" with the same types as --opt, can be repeated
+    /// [ex: "3561:1,str,ABC123"]
+    #[argh(option, from_str_fn(vendor::parse_vi_opt))]
+    pub vi_opt: Vec<vendor::ViOpt>,
 }
 
 impl DoraArgs {
     pub fn discover(&self) -> DiscoverArgs {
         DiscoverArgs {
             chaddr: self.chaddr,
+            htype: self.htype,
+            client_id: self.client_id.clone(),
             ciaddr: self.ciaddr,
             req_addr: self.req_addr,
             giaddr: self.giaddr,
             subnet_select: self.subnet_select,
             relay_link: self.relay_link,
-            opt: self.opt.clone(),
+            opt: self.opts(),
             params: self.params.clone(),
+            rapid_commit: self.rapid_commit,
+            fqdn: self.fqdn.clone(),
+            vendor_class: self.vendor_class.clone(),
+            vi_class: self.vi_class.clone(),
+            vi_opt: self.vi_opt.clone(),
         }
     }
     pub fn request(&self, req_addr: Ipv4Addr) -> RequestArgs {
         RequestArgs {
             chaddr: self.chaddr,
+            htype: self.htype,
+            client_id: self.client_id.clone(),
             ciaddr: self.ciaddr,
             yiaddr: self.yiaddr,
             // insert the IP we got back in OFFER
             req_addr: Some(req_addr),
             sident: self.sident,
+            state: None,
+            giaddr: self.giaddr,
+            subnet_select: self.subnet_select,
+            relay_link: self.relay_link,
+            opt: self.opts(),
+            params: self.params.clone(),
+            fqdn: self.fqdn.clone(),
+            vendor_class: self.vendor_class.clone(),
+            vi_class: self.vi_class.clone(),
+            vi_opt: self.vi_opt.clone(),
+        }
+    }
+    /// RENEW the lease from `ack`, ciaddr set & no server id or requested ip
+    /// (RFC 2131 section 4.3.2)
+    pub fn renew(&self, ack: &v4::Message) -> RequestArgs {
+        RequestArgs {
+            chaddr: self.chaddr,
+            htype: self.htype,
+            client_id: self.client_id.clone(),
+            ciaddr: ack.yiaddr(),
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            req_addr: None,
+            sident: None,
+            state: None,
             giaddr: self.giaddr,
             subnet_select: self.subnet_select,
             relay_link: self.relay_link,
-            opt: self.opt.clone(),
+            opt: self.opts(),
             params: self.params.clone(),
+            fqdn: self.fqdn.clone(),
+            vendor_class: self.vendor_class.clone(),
+            vi_class: self.vi_class.clone(),
+            vi_opt: self.vi_opt.clone(),
         }
     }
+    /// DECLINE the address from `ack`, it's in use by another host
+    /// (RFC 2131 section 4.4.4)
+    pub fn decline(&self, ack: &v4::Message) -> DeclineArgs {
+        DeclineArgs {
+            chaddr: self.chaddr,
+            htype: self.htype,
+            client_id: self.client_id.clone(),
+            giaddr: self.giaddr,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            req_addr: Some(ack.yiaddr()),
+            sident: match ack.opts().get(v4::OptionCode::ServerIdentifier) {
+                Some(v4::DhcpOption::ServerIdentifier(ip)) => Some(*ip),
+                _ => None,
+            },
+            subnet_select: None,
+            relay_link: None,
+            opt: Vec::new(),
+            params: ParamList::default(),
+        }
+    }
+    /// check the DNS records for the lease in `ack` with --dns-verify
+    pub fn verify_dns(&self, ack: &v4::Message, wait: Duration) -> Result<()> {
+        let Some(resolver) = self.dns_verify else {
+            return Ok(());
+        };
+        let fqdn = self
+            .fqdn
+            .as_ref()
+            .context("--dns-verify needs an --fqdn to check")?;
+        let req = self.request(ack.yiaddr()).build(false);
+        fqdn::Expected::v4(fqdn, &req, ack).verify(resolver, wait)
+    }
+    fn opts(&self) -> Vec<v4::DhcpOption> {
+        let mut opts = self.opt.clone();
+        if self.forcerenew.is_some() {
+            opts.push(forcerenew::nonce_capable());
+        }
+        opts
+    }
 }
 
 pub mod util {
@@ -438,7 +1078,7 @@ pub mod util {
                     .map(|m| format!("{m:?}"))
                     .unwrap_or(format!("{:?}", m.opcode()))
                     .to_uppercase(),
-                Msg::V6(m) => format!("{:?}", m.opts()),
+                Msg::V6(m) => format!("{:?}", m.msg_type()).to_uppercase(),
             }
         }
         #[cfg(feature = "script")]
@@ -546,26 +1186,26 @@ pub mod util {
     }
 }
 
-/// Returns:
-/// - interfaces matching the list supplied that are 'up' and have an IPv6
-/// - OR any 'up' interfaces that also have an IPv6
-pub fn find_interface(interface: &Option<String>) -> Result<Option<NetworkInterface>> {
-    let found_interfaces = pnet_datalink::interfaces()
+/// Returns the interface named `interface`, it must be up & have an address.
+/// The error says which it is if it can't be used
+pub fn find_interface(interface: Option<&str>) -> Result<Option<NetworkInterface>> {
+    let Some(interface) = interface else {
+        return Ok(None);
+    };
+    match pnet_datalink::interfaces()
         .into_iter()
-        .filter(|e| e.is_up() && !e.ips.is_empty())
-        .collect::<Vec<_>>();
-    match interface {
-        Some(interface) => match found_interfaces.iter().find(|i| &i.name == interface) {
-            Some(i) => Ok(Some(i.clone())),
-            None => bail!("unable to find interface {interface}"),
-        },
-        None => Ok(None),
+        .find(|i| i.name == interface)
+    {
+        Some(i) if !i.is_up() => bail!("interface {interface} is down"),
+        Some(i) if i.ips.is_empty() => bail!("interface {interface} has no IP address"),
+        Some(i) => Ok(Some(i)),
+        None => bail!("unable to find interface {interface}, see `dhcpm interfaces`"),
     }
 }
 
 pub fn find_link_local(interface: &NetworkInterface) -> Option<Ipv6Addr> {
     interface.ips.iter().find_map(|ip| match ip.ip() {
-        IpAddr::V6(ip) if (ip.segments()[0] & 0xffc0) == 0xfe80 => Some(ip),
+        IpAddr::V6(ip) if interfaces::is_link_local(ip) => Some(ip),
         _ => None,
     })
 }
@@ -1,29 +1,90 @@
 use std::{net::Ipv4Addr, str::FromStr};
 
 use anyhow::{anyhow, Error, Result};
+use base64::Engine;
 use dhcproto::{v4, Decodable, Decoder, Encoder};
 use mac_address::MacAddress;
 use tracing_subscriber::{
     fmt::{
         self,
         format::{Format, Pretty, PrettyFields},
+        writer::BoxMakeWriter,
     },
     prelude::__tracing_subscriber_SubscriberExt,
     util::SubscriberInitExt,
     EnvFilter,
 };
 
-use crate::Args;
+use crate::{codes, Args, MsgType};
 
 /// default timeout is set to 5 (seconds)
 pub fn default_timeout() -> u64 {
     5
 }
 
-pub fn get_mac() -> MacAddress {
+pub fn get_mac() -> Chaddr {
     mac_address::get_mac_address()
         .expect("unable to get MAC addr")
         .unwrap()
+        .into()
+}
+
+/// a client hardware address of up to 16 bytes, its length is sent as hlen
+#[derive(Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Chaddr {
+    addr: [u8; 16],
+    len: u8,
+}
+
+impl Chaddr {
+    pub fn bytes(&self) -> &[u8] {
+        &self.addr[..self.len as usize]
+    }
+}
+
+impl TryFrom<&[u8]> for Chaddr {
+    type Error = String;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if bytes.len() > 16 {
+            return Err(format!("chaddr is {} bytes, max is 16", bytes.len()));
+        }
+        let mut addr = [0; 16];
+        addr[..bytes.len()].copy_from_slice(bytes);
+        Ok(Self {
+            addr,
+            len: bytes.len() as u8,
+        })
+    }
+}
+
+impl From<[u8; 6]> for Chaddr {
+    fn from(mac: [u8; 6]) -> Self {
+        Self::try_from(&mac[..]).expect("6 bytes fits in chaddr")
+    }
+}
+
+impl From<MacAddress> for Chaddr {
+    fn from(mac: MacAddress) -> Self {
+        mac.bytes().into()
+    }
+}
+
+impl std::fmt::Debug for Chaddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl std::fmt::Display for Chaddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hex = self
+            .bytes()
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect::<Vec<_>>();
+        write!(f, "{}", hex.join(":"))
+    }
 }
 
 #[derive(Default, Copy, PartialOrd, Ord, Clone, PartialEq, Eq, Hash, Debug)]
@@ -52,6 +113,13 @@ pub fn init_tracing(args: &Args) {
     let filter_layer = EnvFilter::try_from_default_env()
         .or_else(|_| EnvFilter::try_new("info"))
         .unwrap();
+    // commands that print their results on stdout log to stderr
+    let writer = match args.msg {
+        Some(MsgType::BulkLeaseQuery(_) | MsgType::BulkLeaseQuery6(_)) => {
+            BoxMakeWriter::new(std::io::stderr)
+        }
+        _ => BoxMakeWriter::new(std::io::stdout),
+    };
     match args.output {
         LogStructure::Pretty => {
             tracing_subscriber::registry()
@@ -60,56 +128,209 @@ pub fn init_tracing(args: &Args) {
                     fmt::layer()
                         .event_format(Format::default().with_source_location(false))
                         .fmt_fields(PrettyFields::new())
-                        .with_target(false),
+                        .with_target(false)
+                        .with_writer(writer),
                 )
                 .init();
         }
         LogStructure::Debug => {
             tracing_subscriber::registry()
                 .with(filter_layer)
-                .with(fmt::layer().fmt_fields(Pretty::default()))
+                .with(
+                    fmt::layer()
+                        .fmt_fields(Pretty::default())
+                        .with_writer(writer),
+                )
                 .init();
         }
         LogStructure::Json => {
             tracing_subscriber::registry()
                 .with(filter_layer)
-                .with(fmt::layer().json())
+                .with(fmt::layer().json().with_writer(writer))
                 .init();
         }
     }
 }
 
 /// takes input like: "118,hex,C0A80001" or "118,ip,192.168.0.1"
-/// and converts to a valid DhcpOption
+/// and converts to a valid DhcpOption. The code can also be given by name,
+/// ex. "subnet-select,ip,192.168.0.1"
+///
+/// supported types:
+/// - `hex`: hex encoded bytes
+/// - `b64`: base64 encoded bytes
+/// - `str`: utf-8 string
+/// - `ip`: single ipv4 address
+/// - `ips`: list of ipv4 addresses separated by `,` or `;`
+/// - `u8`, `u16`, `u32`, `i32`: big-endian integers (decimal or `0x` prefixed hex)
+/// - `bool`: `true`/`false` (or `1`/`0`)
+/// - `domain`: a single DNS encoded domain name
+/// - `domains`: list of domain names separated by `,` or `;`, DNS encoded
+///   with RFC 1035 compression (ex. opt 119)
+/// - `domains-nc`: same as `domains` but without compression
+/// - `cidr-routes`: list of `dest/prefix,router` separated by `;` (ex. opt 121)
 pub fn parse_opts(input: &str) -> Result<v4::DhcpOption, String> {
     match &input.splitn(3, ',').collect::<Vec<&str>>()[..] {
         [code, ty, val] => {
-            let code = code.parse::<u8>().map_err(|_| "error parsing OptionCode")?;
-            let opt = match *ty {
-                "hex" => Ok(hex::decode(val).map_err(|_| "decoding hex failed")?),
-                "str" => Ok(val.as_bytes().to_vec()),
-                "ip" => Ok(val
-                    .parse::<Ipv4Addr>()
-                    .map_err(|_| "decoding IP failed")?
-                    .octets()
-                    .to_vec()),
-                _ => Err("failed to decode with a type we understand \"hex\" or \"ip\" or \"str\""),
-            }?;
-            Ok(write_opt(code, opt).map_err(|e| {
-                eprintln!("{e}");
-                "failed to encode to DhcpOption"
-            })?)
-        }
-        _ => Err("parsing options failed".to_string()),
+            let code = u8::from(codes::parse_v4_code(code)?);
+            let opt = encode_value(ty, val)?;
+            write_opt(code, opt).map_err(|e| format!("failed to encode opt {code}: {e}"))
+        }
+        _ => Err(format!(
+            "parsing option {input:?} failed, expected \"code,type,value\""
+        )),
     }
 }
 
+/// encode `val` to the bytes of an option value according to `ty`
+pub fn encode_value(ty: &str, val: &str) -> Result<Vec<u8>, String> {
+    Ok(match ty {
+        "hex" => hex::decode(val).map_err(|e| format!("decoding hex {val:?} failed: {e}"))?,
+        "b64" => base64::engine::general_purpose::STANDARD
+            .decode(val)
+            .map_err(|e| format!("decoding base64 {val:?} failed: {e}"))?,
+        "str" => val.as_bytes().to_vec(),
+        "ip" => parse_ip(val)?.octets().to_vec(),
+        "ips" => split_list(val)
+            .map(|ip| parse_ip(ip).map(|ip| ip.octets()))
+            .collect::<Result<Vec<_>, _>>()?
+            .concat(),
+        "u8" => vec![parse_int(val, u8::MAX.into())? as u8],
+        "u16" => (parse_int(val, u16::MAX.into())? as u16)
+            .to_be_bytes()
+            .to_vec(),
+        "u32" => (parse_int(val, u32::MAX.into())? as u32)
+            .to_be_bytes()
+            .to_vec(),
+        "i32" => val
+            .parse::<i32>()
+            .map_err(|e| format!("parsing i32 {val:?} failed: {e}"))?
+            .to_be_bytes()
+            .to_vec(),
+        "bool" => match val {
+            "true" | "1" => vec![1],
+            "false" | "0" => vec![0],
+            _ => return Err(format!("parsing bool {val:?} failed, use \"true\" or \"false\"")),
+        },
+        "domain" => encode_domains(&[val], false)?,
+        "domains" => encode_domains(&split_list(val).collect::<Vec<_>>(), true)?,
+        "domains-nc" => encode_domains(&split_list(val).collect::<Vec<_>>(), false)?,
+        "cidr-routes" => encode_routes(val)?,
+        _ => {
+            return Err(format!(
+                "unknown type {ty:?}, expected one of: hex, b64, str, ip, ips, u8, u16, u32, i32, bool, domain, domains, domains-nc, cidr-routes"
+            ))
+        }
+    })
+}
+
+fn split_list(val: &str) -> impl Iterator<Item = &str> {
+    val.split([',', ';'])
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+}
+
+fn parse_ip(ip: &str) -> Result<Ipv4Addr, String> {
+    ip.trim()
+        .parse::<Ipv4Addr>()
+        .map_err(|e| format!("decoding IP {ip:?} failed: {e}"))
+}
+
+/// parse a decimal or `0x` prefixed hex number no bigger than `max`
+fn parse_int(val: &str, max: u64) -> Result<u64, String> {
+    let n = match val.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => val.parse::<u64>(),
+    }
+    .map_err(|e| format!("parsing integer {val:?} failed: {e}"))?;
+    if n > max {
+        return Err(format!("integer {val:?} is larger than max {max}"));
+    }
+    Ok(n)
+}
+
+/// DNS encode a list of domain names (RFC 1035 3.1), optionally using
+/// message compression (RFC 1035 4.1.4) with offsets relative to the start of the
+/// option data as described in RFC 3397
+pub fn encode_domains(names: &[&str], compress: bool) -> Result<Vec<u8>, String> {
+    let mut buf = Vec::new();
+    // suffix -> offset in buf
+    let mut suffixes: Vec<(String, usize)> = Vec::new();
+    for name in names {
+        let labels = name
+            .trim_end_matches('.')
+            .split('.')
+            .filter(|l| !l.is_empty())
+            .collect::<Vec<_>>();
+        if labels.iter().map(|l| l.len() + 1).sum::<usize>() + 1 > 255 {
+            return Err(format!("domain {name:?} is longer than 255 bytes"));
+        }
+        let mut pointer = None;
+        for i in 0..labels.len() {
+            let suffix = labels[i..].join(".").to_ascii_lowercase();
+            if compress {
+                if let Some((_, offset)) = suffixes.iter().find(|(s, _)| *s == suffix) {
+                    pointer = Some(*offset);
+                    break;
+                }
+                // pointers only have 14 bits for the offset
+                if buf.len() < 0x3FFF {
+                    suffixes.push((suffix, buf.len()));
+                }
+            }
+            let label = labels[i];
+            if label.len() > 63 {
+                return Err(format!(
+                    "label {label:?} in {name:?} is longer than 63 bytes"
+                ));
+            }
+            buf.push(label.len() as u8);
+            buf.extend(label.as_bytes());
+        }
+        match pointer {
+            Some(offset) => buf.extend((0xC000 | offset as u16).to_be_bytes()),
+            None => buf.push(0),
+        }
+    }
+    Ok(buf)
+}
+
+/// encode classless static routes (RFC 3442) given as "dest/prefix,router;dest/prefix,router"
+fn encode_routes(val: &str) -> Result<Vec<u8>, String> {
+    let mut buf = Vec::new();
+    for route in val.split(';').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        let (dest, router) = route
+            .split_once(',')
+            .ok_or_else(|| format!("route {route:?} must be \"dest/prefix,router\""))?;
+        let (dest, prefix) = dest
+            .split_once('/')
+            .ok_or_else(|| format!("route destination {dest:?} must be \"dest/prefix\""))?;
+        let prefix = prefix
+            .trim()
+            .parse::<u8>()
+            .ok()
+            .filter(|p| *p <= 32)
+            .ok_or_else(|| format!("invalid prefix length {prefix:?} in route {route:?}"))?;
+        let dest = parse_ip(dest)?;
+        // only the significant octets of the destination are encoded
+        buf.push(prefix);
+        buf.extend(&dest.octets()[..prefix.div_ceil(8) as usize]);
+        buf.extend(parse_ip(router)?.octets());
+    }
+    Ok(buf)
+}
+
+/// values over 255 bytes are split into consecutive options with the same
+/// code, to be concatenated by the receiver (RFC 3396)
 fn write_opt(code: u8, opt: Vec<u8>) -> Result<v4::DhcpOption> {
     let mut buf = vec![];
     let mut enc = Encoder::new(&mut buf);
-    enc.write_u8(code)?;
-    enc.write_u8(opt.len() as u8)?;
-    enc.write_slice(&opt)?;
+    if opt.is_empty() {
+        enc.write_u8(code)?;
+        enc.write_u8(0)?;
+    } else {
+        v4::encode_long_opt_bytes(code.into(), &opt, &mut enc)?;
+    }
 
     Ok(v4::DhcpOption::decode(&mut Decoder::new(&buf))?)
 }
@@ -140,33 +361,138 @@ pub fn default_params() -> ParamList {
     ])
 }
 
+/// parse a comma separated list of codes or names, ex. "1,3,6" or "subnet,router,dns"
 pub fn parse_params(params: &str) -> Result<Vec<v4::OptionCode>, String> {
-    params
-        .split(',')
-        .map(|code| {
-            code.parse::<u8>()
-                .map(v4::OptionCode::from)
-                .map_err(|_| "parsing OptionCode failed".to_string())
-        })
-        .collect()
+    params.split(',').map(codes::parse_v4_code).collect()
 }
 
-pub fn parse_mac(mac: &str) -> Result<MacAddress, String> {
+/// parse a hardware address of up to 16 bytes, separated by ':' or '-' or
+/// plain hex, "random" for a random mac or "none" for an empty chaddr (hlen 0)
+pub fn parse_mac(mac: &str) -> Result<Chaddr, String> {
     match mac {
         "random" => Ok(rand::random::<[u8; 6]>().into()),
-        mac => match MacAddress::from_str(mac) {
-            Ok(mac) => Ok(mac),
-            Err(err) => MacAddress::from_str(
-                &mac.chars()
-                    .collect::<Vec<_>>()
-                    .chunks(2)
-                    .map(|chunk| chunk.iter().collect::<String>())
-                    .collect::<Vec<String>>()
-                    .join(":"),
-            )
-            .map_err(|_| format!("{err:?}")),
+        "none" => Ok(Chaddr::default()),
+        mac => {
+            let bytes = if mac.contains([':', '-']) {
+                mac.split([':', '-'])
+                    .map(|b| match b.len() {
+                        1 | 2 => u8::from_str_radix(b, 16).map_err(|e| e.to_string()),
+                        _ => Err(format!("invalid byte {b:?}")),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            } else {
+                hex::decode(mac).map_err(|e| e.to_string())
+            }
+            .map_err(|e| format!("parsing hardware address {mac:?} failed: {e}"))?;
+            Chaddr::try_from(&bytes[..])
         }
-        .map_err(|err| format!("{err:?}")),
+    }
+}
+
+/// parse a hardware type (htype) number or one of "eth", "ieee802",
+/// "ieee1394" or "infiniband"
+pub fn parse_htype(htype: &str) -> Result<v4::HType, String> {
+    Ok(match htype {
+        "eth" | "ethernet" => v4::HType::Eth,
+        "ieee802" => v4::HType::IEEE802,
+        "ieee1394" => 24.into(),
+        "ib" | "infiniband" => 32.into(),
+        htype => (parse_int(htype, u8::MAX.into())? as u8).into(),
+    })
+}
+
+/// an IPoIB client can't receive replies sent to its chaddr, it MUST set the
+/// BROADCAST flag (RFC 4390 section 2.1)
+pub fn needs_broadcast(htype: v4::HType) -> bool {
+    htype == v4::HType::Infiniband
+}
+
+/// client identifier opt 61 sent in dhcpv4 messages
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub enum ClientId {
+    /// no opt 61, the server identifies the client by chaddr
+    None,
+    /// htype & chaddr (RFC 2132 section 9.14)
+    #[default]
+    HwTypeMac,
+    /// chaddr without a hardware type
+    Mac,
+    Raw(Vec<u8>),
+    /// type 255, IAID & DUID (RFC 4361 section 6.1), the DUID defaults to
+    /// DUID-LL from chaddr like `solicit`
+    Duid {
+        iaid: u32,
+        duid: Option<Vec<u8>>,
+    },
+}
+
+/// parse "none", "hwtype+mac", "mac", "hex:<hex>" or "duid[:<iaid>[:<duid>]]"
+/// where the DUID is in any format accepted by [`v6::parse_duid`]
+pub fn parse_client_id(id: &str) -> Result<ClientId, String> {
+    Ok(match id {
+        "none" => ClientId::None,
+        "hwtype+mac" => ClientId::HwTypeMac,
+        "mac" => ClientId::Mac,
+        "duid" => ClientId::Duid {
+            iaid: 1,
+            duid: None,
+        },
+        id => {
+            if let Some(raw) = id.strip_prefix("hex:") {
+                ClientId::Raw(
+                    hex::decode(raw).map_err(|e| format!("decoding hex {raw:?} failed: {e}"))?,
+                )
+            } else if let Some(duid) = id.strip_prefix("duid:") {
+                let (iaid, duid) = match duid.split_once(':') {
+                    Some((iaid, duid)) => (iaid, Some(v6::parse_duid(duid)?)),
+                    None => (duid, None),
+                };
+                ClientId::Duid {
+                    iaid: parse_int(iaid, u32::MAX.into())? as u32,
+                    duid,
+                }
+            } else {
+                return Err(format!(
+                    "unknown client id {id:?}, expected none, hwtype+mac, mac, hex:<hex> or duid[:<iaid>[:<duid>]]"
+                ));
+            }
+        }
+    })
+}
+
+impl ClientId {
+    pub fn opt(&self, htype: v4::HType, chaddr: Chaddr) -> Option<v4::DhcpOption> {
+        let id = match self {
+            ClientId::None => return None,
+            // with hlen 0 there's no hardware address to identify the client,
+            // use IAID & DUID like IPoIB clients do (RFC 4390 section 2.1)
+            ClientId::HwTypeMac | ClientId::Mac if chaddr.bytes().is_empty() => {
+                return ClientId::Duid {
+                    iaid: 1,
+                    duid: None,
+                }
+                .opt(htype, chaddr);
+            }
+            ClientId::HwTypeMac => {
+                let mut id = vec![u8::from(htype)];
+                id.extend(chaddr.bytes());
+                id
+            }
+            ClientId::Mac => chaddr.bytes().to_vec(),
+            ClientId::Raw(id) => id.clone(),
+            ClientId::Duid { iaid, duid } => {
+                let mut id = vec![255];
+                id.extend(iaid.to_be_bytes());
+                match duid {
+                    Some(duid) => id.extend(duid),
+                    // DUID-LL of the host's MAC if there's no chaddr
+                    None if chaddr.bytes().is_empty() => id.extend(v6::duid_ll(get_mac())),
+                    None => id.extend(v6::duid_ll_htype(u8::from(htype).into(), chaddr)),
+                }
+                id
+            }
+        };
+        Some(v4::DhcpOption::ClientIdentifier(id))
     }
 }
 
@@ -180,7 +506,15 @@ pub fn params_to_str(params: &[v4::OptionCode]) -> String {
 }
 
 pub mod v6 {
-    use dhcproto::v6;
+    use std::{
+        net::Ipv6Addr,
+        time::{Duration, SystemTime},
+    };
+
+    use dhcproto::{v6, Decodable, Decoder, Encoder};
+
+    use super::{encode_domains, parse_int, split_list};
+    use crate::codes;
 
     #[derive(Default, Debug, Clone, PartialEq, Eq)]
     pub struct ParamList(pub Vec<v6::OptionCode>);
@@ -199,14 +533,482 @@ pub mod v6 {
         }
     }
 
+    /// parse a comma separated list of codes or names, ex. "23,24" or "dns,search"
     pub fn parse_params(params: &str) -> Result<Vec<v6::OptionCode>, String> {
-        params
-            .split(',')
-            .map(|code| {
-                code.parse::<u16>()
-                    .map(v6::OptionCode::from)
-                    .map_err(|_| "parsing OptionCode failed".to_string())
-            })
-            .collect()
+        params.split(',').map(crate::codes::parse_v6_code).collect()
+    }
+
+    /// A v6 option from the command line. If `container` is set the option
+    /// is placed inside the IA_NA/IA_TA/IA_PD of that type
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Opt {
+        pub container: Option<v6::OptionCode>,
+        pub opt: v6::DhcpOption,
+    }
+
+    /// takes input like: "16,hex,0000000900..." or "ia-na/ia-addr,ia-addr,2001:db8::1"
+    /// and converts to a valid DhcpOption. Codes are u16 or a name, prefixing
+    /// with "<container>/" nests the option in an IA_NA (3), IA_TA (4) or IA_PD (25)
+    ///
+    /// supported types:
+    /// - `hex`: hex encoded bytes
+    /// - `str`: utf-8 string
+    /// - `ip6`: single ipv6 address
+    /// - `ips6`: list of ipv6 addresses separated by `,` or `;`
+    /// - `u8`, `u16`, `u32`: big-endian integers
+    /// - `duid`: see [`parse_duid`]
+    /// - `domains`: list of domain names separated by `,` or `;`, DNS encoded
+    ///   without compression (RFC 8415 section 10)
+    /// - `ia-addr`: "addr[,preferred,valid]" IA Address (5)
+    /// - `ia-prefix`: "prefix/len[,preferred,valid]" IA Prefix (26)
+    pub fn parse_opts(input: &str) -> Result<Opt, String> {
+        match &input.splitn(3, ',').collect::<Vec<&str>>()[..] {
+            [code, ty, val] => {
+                let (container, code) = match code.split_once('/') {
+                    Some((container, code)) => {
+                        let container = codes::parse_v6_code(container)?;
+                        if !matches!(
+                            container,
+                            v6::OptionCode::IANA | v6::OptionCode::IATA | v6::OptionCode::IAPD
+                        ) {
+                            return Err(format!(
+                                "{container:?} is not a container, use IA_NA (3), IA_TA (4) or IA_PD (25)"
+                            ));
+                        }
+                        (Some(container), codes::parse_v6_code(code)?)
+                    }
+                    None => (None, codes::parse_v6_code(code)?),
+                };
+                let data = encode_value(ty, val)?;
+                let opt = write_opt(code.into(), data)
+                    .map_err(|e| format!("failed to encode opt {}: {e}", u16::from(code)))?;
+                Ok(Opt { container, opt })
+            }
+            _ => Err(format!(
+                "parsing option {input:?} failed, expected \"code,type,value\""
+            )),
+        }
+    }
+
+    pub fn encode_value(ty: &str, val: &str) -> Result<Vec<u8>, String> {
+        Ok(match ty {
+            "hex" => hex::decode(val).map_err(|e| format!("decoding hex {val:?} failed: {e}"))?,
+            "str" => val.as_bytes().to_vec(),
+            "ip6" => parse_ip6(val)?.octets().to_vec(),
+            "ips6" => split_list(val)
+                .map(|ip| parse_ip6(ip).map(|ip| ip.octets()))
+                .collect::<Result<Vec<_>, _>>()?
+                .concat(),
+            "u8" => vec![parse_int(val, u8::MAX.into())? as u8],
+            "u16" => (parse_int(val, u16::MAX.into())? as u16)
+                .to_be_bytes()
+                .to_vec(),
+            "u32" => (parse_int(val, u32::MAX.into())? as u32)
+                .to_be_bytes()
+                .to_vec(),
+            "duid" => parse_duid(val)?,
+            "domains" => encode_domains(&split_list(val).collect::<Vec<_>>(), false)?,
+            "ia-addr" => {
+                let mut parts = val.split(',');
+                let addr = parse_ip6(parts.next().unwrap_or_default())?;
+                let (preferred, valid) = lifetimes(parts)?;
+                let mut buf = addr.octets().to_vec();
+                buf.extend(preferred.to_be_bytes());
+                buf.extend(valid.to_be_bytes());
+                buf
+            }
+            "ia-prefix" => {
+                let mut parts = val.split(',');
+                let prefix = parts.next().unwrap_or_default();
+                let (addr, len) = prefix
+                    .split_once('/')
+                    .ok_or_else(|| format!("prefix {prefix:?} must be \"prefix/len\""))?;
+                let len = len
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|l| *l <= 128)
+                    .ok_or_else(|| format!("invalid prefix length in {prefix:?}"))?;
+                let (preferred, valid) = lifetimes(parts)?;
+                let mut buf = Vec::new();
+                buf.extend(preferred.to_be_bytes());
+                buf.extend(valid.to_be_bytes());
+                buf.push(len);
+                buf.extend(parse_ip6(addr)?.octets());
+                buf
+            }
+            _ => {
+                return Err(format!(
+                    "unknown type {ty:?}, expected one of: hex, str, ip6, ips6, u8, u16, u32, duid, domains, ia-addr, ia-prefix"
+                ))
+            }
+        })
+    }
+
+    /// optional "preferred,valid" lifetimes, both default to 0
+    fn lifetimes<'a>(mut parts: impl Iterator<Item = &'a str>) -> Result<(u32, u32), String> {
+        let preferred = parts
+            .next()
+            .map(|n| parse_int(n, u32::MAX.into()))
+            .transpose()?
+            .unwrap_or(0) as u32;
+        let valid = parts
+            .next()
+            .map(|n| parse_int(n, u32::MAX.into()))
+            .transpose()?
+            .unwrap_or(0) as u32;
+        Ok((preferred, valid))
+    }
+
+    fn parse_ip6(ip: &str) -> Result<Ipv6Addr, String> {
+        ip.trim()
+            .parse::<Ipv6Addr>()
+            .map_err(|e| format!("decoding IPv6 {ip:?} failed: {e}"))
+    }
+
+    /// parse a DUID (RFC 8415 section 11) from:
+    /// - "ll:<mac>": DUID-LL (ethernet)
+    /// - "llt:<mac>": DUID-LLT (ethernet) using the current time
+    /// - "en:<enterprise-number>:<hex id>": DUID-EN
+    /// - "uuid:<uuid>": DUID-UUID
+    /// - "<hex>": raw bytes
+    pub fn parse_duid(val: &str) -> Result<Vec<u8>, String> {
+        // hardware type 1 (ethernet)
+        const ETH: [u8; 2] = [0, 1];
+        let mut buf = Vec::new();
+        if let Some(mac) = val.strip_prefix("ll:") {
+            buf.extend(3_u16.to_be_bytes());
+            buf.extend(ETH);
+            buf.extend(super::parse_mac(mac)?.bytes());
+        } else if let Some(mac) = val.strip_prefix("llt:") {
+            // DUID time is seconds since midnight (UTC), January 1, 2000
+            let epoch = SystemTime::UNIX_EPOCH + Duration::from_secs(946_684_800);
+            let time = SystemTime::now()
+                .duration_since(epoch)
+                .map(|d| d.as_secs() as u32)
+                .unwrap_or(0);
+            buf.extend(1_u16.to_be_bytes());
+            buf.extend(ETH);
+            buf.extend(time.to_be_bytes());
+            buf.extend(super::parse_mac(mac)?.bytes());
+        } else if let Some(en) = val.strip_prefix("en:") {
+            let (num, id) = en
+                .split_once(':')
+                .ok_or_else(|| format!("DUID-EN {val:?} must be \"en:<enterprise>:<hex id>\""))?;
+            buf.extend(2_u16.to_be_bytes());
+            buf.extend((parse_int(num, u32::MAX.into())? as u32).to_be_bytes());
+            buf.extend(hex::decode(id).map_err(|e| format!("decoding hex {id:?} failed: {e}"))?);
+        } else if let Some(uuid) = val.strip_prefix("uuid:") {
+            let uuid = hex::decode(uuid.replace('-', ""))
+                .map_err(|e| format!("decoding uuid {uuid:?} failed: {e}"))?;
+            if uuid.len() != 16 {
+                return Err(format!("uuid {val:?} must be 16 bytes"));
+            }
+            buf.extend(4_u16.to_be_bytes());
+            buf.extend(uuid);
+        } else {
+            buf = hex::decode(val).map_err(|e| format!("decoding DUID {val:?} failed: {e}"))?;
+        }
+        Ok(buf)
+    }
+
+    /// DUID-LL (ethernet) from a mac address
+    pub fn duid_ll(mac: super::Chaddr) -> Vec<u8> {
+        duid_ll_htype(1, mac)
+    }
+
+    /// DUID-LL with a hardware type from the same registry as v4 htype
+    pub fn duid_ll_htype(htype: u16, addr: super::Chaddr) -> Vec<u8> {
+        let mut duid = vec![0, 3];
+        duid.extend(htype.to_be_bytes());
+        duid.extend(addr.bytes());
+        duid
+    }
+
+    fn write_opt(code: u16, opt: Vec<u8>) -> anyhow::Result<v6::DhcpOption> {
+        if opt.len() > u16::MAX as usize {
+            anyhow::bail!("value is {} bytes, max is {}", opt.len(), u16::MAX);
+        }
+        let mut buf = vec![];
+        let mut enc = Encoder::new(&mut buf);
+        enc.write_u16(code)?;
+        enc.write_u16(opt.len() as u16)?;
+        enc.write_slice(&opt)?;
+
+        Ok(v6::DhcpOption::decode(&mut Decoder::new(&buf))?)
+    }
+
+    /// insert opts into `msg`, creating an IA container with IAID 0 if an
+    /// option is nested in a container the message doesn't have yet
+    pub fn insert_opts(msg: &mut v6::Message, opts: &[Opt]) {
+        for Opt { container, opt } in opts {
+            let Some(container) = container else {
+                msg.opts_mut().insert(opt.clone());
+                continue;
+            };
+            if msg.opts().get(*container).is_none() {
+                msg.opts_mut().insert(match container {
+                    v6::OptionCode::IATA => v6::DhcpOption::IATA(v6::IATA {
+                        id: 0,
+                        opts: v6::DhcpOptions::new(),
+                    }),
+                    v6::OptionCode::IAPD => v6::DhcpOption::IAPD(v6::IAPD {
+                        id: 0,
+                        t1: 0,
+                        t2: 0,
+                        opts: v6::DhcpOptions::new(),
+                    }),
+                    _ => v6::DhcpOption::IANA(v6::IANA {
+                        id: 0,
+                        t1: 0,
+                        t2: 0,
+                        opts: v6::DhcpOptions::new(),
+                    }),
+                });
+            }
+            match msg.opts_mut().get_mut(*container) {
+                Some(v6::DhcpOption::IANA(v6::IANA { opts, .. }))
+                | Some(v6::DhcpOption::IATA(v6::IATA { opts, .. }))
+                | Some(v6::DhcpOption::IAPD(v6::IAPD { opts, .. })) => opts.insert(opt.clone()),
+                _ => {}
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn duids() {
+            let duid = |val| parse_duid(val).map(hex::encode);
+            assert_eq!(
+                duid("ll:aa:bb:cc:dd:ee:ff").unwrap(),
+                "00030001aabbccddeeff"
+            );
+            assert_eq!(duid("en:9:0a0b").unwrap(), "0002000000090a0b");
+            assert_eq!(
+                duid("uuid:00112233-4455-6677-8899-aabbccddeeff").unwrap(),
+                "000400112233445566778899aabbccddeeff"
+            );
+            assert_eq!(duid("000100").unwrap(), "000100");
+            let llt = parse_duid("llt:aa:bb:cc:dd:ee:ff").unwrap();
+            assert_eq!(llt[..4], [0, 1, 0, 1]);
+            assert_eq!(llt[8..], [0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]);
+            assert!(duid("uuid:0011").is_err());
+            assert!(duid("en:9").is_err());
+        }
+
+        #[test]
+        fn ia_values() {
+            let enc = |ty, val| encode_value(ty, val).map(hex::encode);
+            assert_eq!(
+                enc("ia-addr", "2001:db8::1,10,20").unwrap(),
+                "20010db80000000000000000000000010000000a00000014"
+            );
+            assert_eq!(
+                enc("ia-prefix", "2001:db8::/56").unwrap(),
+                "00000000000000003820010db8000000000000000000000000"
+            );
+            assert!(enc("ia-prefix", "2001:db8::/129").is_err());
+            assert!(enc("ia-addr", "2001:db8::1,x").is_err());
+        }
+
+        #[test]
+        fn nested() {
+            let opt = parse_opts("ia-na/ia-addr,ia-addr,2001:db8::1,10,20").unwrap();
+            assert_eq!(opt.container, Some(v6::OptionCode::IANA));
+            assert!(parse_opts("dns/ia-addr,ia-addr,2001:db8::1").is_err());
+
+            let mut msg = v6::Message::new(v6::MessageType::Solicit);
+            insert_opts(
+                &mut msg,
+                &[opt, parse_opts("dns,ips6,2001:db8::53").unwrap()],
+            );
+            match msg.opts().get(v6::OptionCode::IANA) {
+                Some(v6::DhcpOption::IANA(ia)) => {
+                    assert_eq!(ia.id, 0);
+                    assert_eq!(
+                        ia.opts.get(v6::OptionCode::IAAddr),
+                        Some(&v6::DhcpOption::IAAddr(v6::IAAddr {
+                            addr: "2001:db8::1".parse().unwrap(),
+                            preferred_life: 10,
+                            valid_life: 20,
+                            opts: v6::DhcpOptions::new(),
+                        }))
+                    );
+                }
+                opt => panic!("{opt:?}"),
+            }
+            assert_eq!(
+                msg.opts().get(v6::OptionCode::DomainNameServers),
+                Some(&v6::DhcpOption::DomainNameServers(vec!["2001:db8::53"
+                    .parse()
+                    .unwrap()]))
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_values() {
+        let enc = |ty, val| encode_value(ty, val).map(hex::encode);
+        assert_eq!(enc("hex", "c0a80001").unwrap(), "c0a80001");
+        assert_eq!(enc("b64", "wKgAAQ==").unwrap(), "c0a80001");
+        assert_eq!(enc("str", "abc").unwrap(), "616263");
+        assert_eq!(enc("ip", "192.168.0.1").unwrap(), "c0a80001");
+        assert_eq!(
+            enc("ips", "10.0.0.1; 10.0.0.2").unwrap(),
+            "0a0000010a000002"
+        );
+        assert_eq!(enc("u8", "0xff").unwrap(), "ff");
+        assert_eq!(enc("u16", "1500").unwrap(), "05dc");
+        assert_eq!(enc("u32", "86400").unwrap(), "00015180");
+        assert_eq!(enc("i32", "-1").unwrap(), "ffffffff");
+        assert_eq!(enc("bool", "true").unwrap(), "01");
+        assert_eq!(enc("bool", "0").unwrap(), "00");
+        assert!(enc("u8", "256").is_err());
+        assert!(enc("u16", "x").is_err());
+        assert!(enc("bool", "yes").is_err());
+        assert!(enc("ip", "10.0.0").is_err());
+        assert!(enc("nope", "1").is_err());
+    }
+
+    // RFC 3397 section 3
+    #[test]
+    fn domains() {
+        assert_eq!(
+            encode_domains(&["eng.apple.com", "marketing.apple.com"], true).unwrap(),
+            b"\x03eng\x05apple\x03com\x00\x09marketing\xc0\x04"
+        );
+        assert_eq!(
+            encode_domains(&["eng.apple.com.", "apple.com"], false).unwrap(),
+            b"\x03eng\x05apple\x03com\x00\x05apple\x03com\x00"
+        );
+        assert!(encode_domains(&[&"a".repeat(64)], false).is_err());
+    }
+
+    // RFC 3442 section 3
+    #[test]
+    fn routes() {
+        assert_eq!(
+            hex::encode(
+                encode_value("cidr-routes", "0.0.0.0/0,10.0.0.1; 10.17.0.0/16,10.0.0.2").unwrap()
+            ),
+            "000a000001100a110a000002"
+        );
+        assert!(encode_routes("10.0.0.0/33,10.0.0.1").is_err());
+        assert!(encode_routes("10.0.0.0,10.0.0.1").is_err());
+    }
+
+    #[test]
+    fn opts() {
+        assert_eq!(
+            parse_opts("118,ip,192.168.0.1").unwrap(),
+            v4::DhcpOption::SubnetSelection(Ipv4Addr::new(192, 168, 0, 1))
+        );
+        assert_eq!(
+            parse_opts("26,u16,1500").unwrap(),
+            v4::DhcpOption::InterfaceMtu(1500)
+        );
+        assert!(parse_opts("26,u16").is_err());
+    }
+
+    #[test]
+    fn client_id() {
+        assert_eq!(parse_client_id("none").unwrap(), ClientId::None);
+        assert_eq!(parse_client_id("hwtype+mac").unwrap(), ClientId::HwTypeMac);
+        assert_eq!(parse_client_id("mac").unwrap(), ClientId::Mac);
+        assert_eq!(
+            parse_client_id("hex:01ff").unwrap(),
+            ClientId::Raw(vec![1, 255])
+        );
+        assert_eq!(
+            parse_client_id("duid").unwrap(),
+            ClientId::Duid {
+                iaid: 1,
+                duid: None
+            }
+        );
+        assert_eq!(
+            parse_client_id("duid:7").unwrap(),
+            ClientId::Duid {
+                iaid: 7,
+                duid: None
+            }
+        );
+        assert_eq!(
+            parse_client_id("duid:7:00030001aabbccddeeff").unwrap(),
+            ClientId::Duid {
+                iaid: 7,
+                duid: Some(hex::decode("00030001aabbccddeeff").unwrap())
+            }
+        );
+        assert!(parse_client_id("hex:zz").is_err());
+        assert!(parse_client_id("duid:x").is_err());
+        assert!(parse_client_id("foo").is_err());
+    }
+
+    #[test]
+    fn client_id_opt() {
+        let chaddr = Chaddr::from([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]);
+        let opt = |id: &str| match parse_client_id(id).unwrap().opt(v4::HType::Eth, chaddr) {
+            Some(v4::DhcpOption::ClientIdentifier(id)) => Some(hex::encode(id)),
+            None => None,
+            opt => panic!("{opt:?}"),
+        };
+        assert_eq!(opt("none"), None);
+        assert_eq!(opt("hwtype+mac").unwrap(), "01aabbccddeeff");
+        assert_eq!(opt("mac").unwrap(), "aabbccddeeff");
+        assert_eq!(opt("hex:0102").unwrap(), "0102");
+        // type 255, IAID, DUID-LL from htype & chaddr
+        assert_eq!(opt("duid").unwrap(), "ff0000000100030001aabbccddeeff");
+        assert_eq!(opt("duid:2:0004aabb").unwrap(), "ff000000020004aabb");
+    }
+
+    #[test]
+    fn mac() {
+        let bytes = |mac: &str| parse_mac(mac).map(|c| c.bytes().to_vec());
+        assert_eq!(
+            bytes("aa:bb:cc:dd:ee:ff").unwrap(),
+            [0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]
+        );
+        assert_eq!(bytes("a-b-c").unwrap(), [0xa, 0xb, 0xc]);
+        assert_eq!(bytes("00112233445566778899").unwrap().len(), 10);
+        assert_eq!(bytes(&"00".repeat(16)).unwrap().len(), 16);
+        assert!(bytes("none").unwrap().is_empty());
+        assert_eq!(bytes("random").unwrap().len(), 6);
+        assert!(bytes(&"00".repeat(17)).is_err());
+        assert!(bytes("aa:bbb").is_err());
+        assert!(bytes("zz").is_err());
+        assert_eq!(parse_mac("0a:0b").unwrap().to_string(), "0A:0B");
+    }
+
+    #[test]
+    fn htype() {
+        assert_eq!(parse_htype("eth").unwrap(), v4::HType::Eth);
+        assert_eq!(parse_htype("ib").unwrap(), v4::HType::Infiniband);
+        assert_eq!(parse_htype("24").unwrap(), 24.into());
+        assert!(parse_htype("256").is_err());
+        assert!(needs_broadcast(parse_htype("infiniband").unwrap()));
+        assert!(!needs_broadcast(v4::HType::Eth));
+    }
+
+    #[test]
+    fn client_id_no_chaddr() {
+        let opt = |id: &str| match parse_client_id(id)
+            .unwrap()
+            .opt(v4::HType::Infiniband, Chaddr::default())
+        {
+            Some(v4::DhcpOption::ClientIdentifier(id)) => hex::encode(id),
+            opt => panic!("{opt:?}"),
+        };
+        // falls back to IAID & DUID-LL of the host's MAC
+        assert!(opt("hwtype+mac").starts_with("ff0000000100030001"));
+        assert_eq!(opt("mac"), opt("hwtype+mac"));
+        assert_eq!(opt("duid:2:0004aabb"), "ff000000020004aabb");
     }
 }
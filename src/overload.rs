@@ -0,0 +1,305 @@
+use anyhow::{bail, Result};
+use dhcproto::{v4, Decodable, Decoder, Encodable, Encoder};
+use tracing::debug;
+
+/// fixed header + magic cookie, options start after this
+const OPTS_START: usize = 240;
+const SNAME: std::ops::Range<usize> = 44..108;
+const FILE: std::ops::Range<usize> = 108..236;
+
+/// opt 52 values (RFC 2132 section 9.3)
+const OVERLOAD_FILE: u8 = 1;
+const OVERLOAD_SNAME: u8 = 2;
+
+/// Read raw options from `buf` until `End`, appending to `opts`. Options
+/// already seen are concatenated in the order they appear (RFC 3396)
+fn read_raw(buf: &[u8], opts: &mut Vec<(u8, Vec<u8>)>) {
+    let mut i = 0;
+    while i < buf.len() {
+        let code = buf[i];
+        match code {
+            0 => {
+                i += 1;
+                continue;
+            }
+            255 => break,
+            _ => {}
+        }
+        let Some(&len) = buf.get(i + 1) else { break };
+        let Some(data) = buf.get(i + 2..i + 2 + len as usize) else {
+            break;
+        };
+        match opts.iter_mut().find(|(c, _)| *c == code) {
+            Some((_, prev)) => prev.extend_from_slice(data),
+            None => opts.push((code, data.to_vec())),
+        }
+        i += 2 + len as usize;
+    }
+}
+
+/// encode `opts` with long options split on 255 bytes and decode them again
+fn to_dhcp_opts(opts: &[(u8, Vec<u8>)]) -> Result<v4::DhcpOptions> {
+    let mut buf = vec![];
+    let mut enc = Encoder::new(&mut buf);
+    for (code, data) in opts {
+        if data.is_empty() {
+            enc.write_u8(*code)?;
+            enc.write_u8(0)?;
+        } else {
+            v4::encode_long_opt_bytes((*code).into(), data, &mut enc)?;
+        }
+    }
+    enc.write_u8(255)?;
+    Ok(v4::DhcpOptions::decode(&mut Decoder::new(&buf))?)
+}
+
+/// Decode a v4 message, if opt 52 is present the options carried in
+/// `file` and `sname` are concatenated with those from the options field
+/// (in that order) and the overloaded header fields are cleared
+pub fn decode(buf: &[u8]) -> Result<v4::Message> {
+    let mut msg = v4::Message::decode(&mut Decoder::new(buf))?;
+    // read the options field raw, an option may be split between it and
+    // `file`/`sname` (RFC 3396) so the chunks from all of them are joined
+    // before dhcproto decodes the options
+    let mut opts = vec![];
+    read_raw(buf.get(OPTS_START..).unwrap_or_default(), &mut opts);
+    let Some(&overload) = opts
+        .iter()
+        .find(|(code, _)| *code == u8::from(v4::OptionCode::OptionOverload))
+        .and_then(|(_, data)| data.first())
+    else {
+        return Ok(msg);
+    };
+    if overload & OVERLOAD_FILE != 0 {
+        read_raw(&buf[FILE], &mut opts);
+        msg.clear_fname();
+    }
+    if overload & OVERLOAD_SNAME != 0 {
+        read_raw(&buf[SNAME], &mut opts);
+        msg.clear_sname();
+    }
+    debug!(overload, "decoded overloaded options");
+    msg.set_opts(to_dhcp_opts(&opts)?);
+    Ok(msg)
+}
+
+/// Write as much of `opts` as fits in `space` bytes (minus the `End` byte),
+/// splitting an option if only part of it fits. Returns what was written,
+/// the rest is left in `opts`
+fn fill(opts: &mut Vec<(u8, Vec<u8>)>, space: usize) -> Vec<(u8, Vec<u8>)> {
+    // keep room for End
+    let mut space = space - 1;
+    let mut written = vec![];
+    while let Some((code, data)) = opts.first_mut() {
+        // header of each 255 byte chunk + data
+        let need = data.len() + 2 * data.len().div_ceil(255).max(1);
+        if need <= space {
+            space -= need;
+            written.push(opts.remove(0));
+            continue;
+        }
+        // split the option on a chunk boundary
+        let chunks = space / 257;
+        let rem = space % 257;
+        let n = chunks * 255 + rem.saturating_sub(2);
+        if n > 0 && !data.is_empty() {
+            let rest = data.split_off(n);
+            written.push((*code, std::mem::replace(data, rest)));
+        }
+        break;
+    }
+    written
+}
+
+/// encode `opts` for the `file`/`sname` fields, the rest of the field is zero padded
+fn raw_bytes(opts: &[(u8, Vec<u8>)]) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    let mut enc = Encoder::new(&mut buf);
+    for (code, data) in opts {
+        for chunk in data.chunks(255) {
+            enc.write_u8(*code)?;
+            enc.write_u8(chunk.len() as u8)?;
+            enc.write_slice(chunk)?;
+        }
+    }
+    enc.write_u8(255)?;
+    Ok(buf)
+}
+
+/// If `msg` encodes to more than `max_size` bytes, move the options that
+/// don't fit into the `file` field and then `sname`, adding opt 52 to say
+/// so (RFC 2132 section 9.3). Options can be split across fields (RFC 3396).
+/// Fields that are already set are left alone.
+pub fn pack(msg: &mut v4::Message, max_size: usize) -> Result<()> {
+    let len = msg.to_vec()?.len();
+    if len <= max_size {
+        return Ok(());
+    }
+    if max_size < OPTS_START + 4 {
+        bail!(
+            "max size {max_size} is too small, must be at least {}",
+            OPTS_START + 4
+        );
+    }
+    // options in the order dhcproto will write them
+    let mut encoded = vec![];
+    msg.opts().encode(&mut Encoder::new(&mut encoded))?;
+    let mut opts = vec![];
+    read_raw(&encoded, &mut opts);
+
+    // leave room for opt 52 in the options field
+    let in_opts = fill(&mut opts, max_size - OPTS_START - 3);
+    // an option partly written to the options field continues in `file`/`sname`
+    let split = opts.first().map(|(code, _)| *code);
+    let mut overload = 0;
+    let mut file = None;
+    let mut sname = None;
+    if !opts.is_empty() && msg.fname().is_none() {
+        overload |= OVERLOAD_FILE;
+        file = Some(raw_bytes(&fill(&mut opts, FILE.len()))?);
+    }
+    if !opts.is_empty() && msg.sname().is_none() {
+        overload |= OVERLOAD_SNAME;
+        sname = Some(raw_bytes(&fill(&mut opts, SNAME.len()))?);
+    }
+    if !opts.is_empty() {
+        let left = opts
+            .iter()
+            .map(|(_, d)| d.len() + 2 * d.len().div_ceil(255).max(1))
+            .sum::<usize>();
+        bail!("options are {left} bytes over max size {max_size} even with opt 52 overload, try a larger --max-size");
+    }
+
+    let mut new_opts = v4::DhcpOptions::new();
+    for (code, data) in in_opts {
+        match msg.opts().get(code.into()) {
+            // the option is whole, keep it as is
+            Some(opt) if split != Some(code) => {
+                new_opts.insert(opt.clone());
+            }
+            // only part of the option is here, it can't be decoded as its type
+            _ => {
+                new_opts.insert(v4::DhcpOption::Unknown(v4::UnknownOption::new(
+                    code.into(),
+                    data,
+                )));
+            }
+        }
+    }
+    new_opts.insert(v4::DhcpOption::OptionOverload(overload));
+    msg.set_opts(new_opts);
+    if let Some(file) = file {
+        msg.set_fname(&file);
+    }
+    if let Some(sname) = sname {
+        msg.set_sname(&sname);
+    }
+    debug!(
+        overload,
+        size = msg.to_vec()?.len(),
+        "packed options with opt 52 overload"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn msg() -> v4::Message {
+        let mut msg = v4::Message::default();
+        msg.opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Offer));
+        msg.opts_mut()
+            .insert(v4::DhcpOption::Hostname("h".repeat(200)));
+        msg.opts_mut()
+            .insert(v4::DhcpOption::DomainName("d".repeat(100)));
+        msg.opts_mut()
+            .insert(v4::DhcpOption::Router(vec![Ipv4Addr::new(10, 0, 0, 1)]));
+        msg
+    }
+
+    fn assert_same_opts(a: &v4::Message, b: &v4::Message) {
+        for (code, opt) in a.opts().iter() {
+            assert_eq!(b.opts().get(*code), Some(opt), "{code:?}");
+        }
+    }
+
+    #[test]
+    fn pack_fits() {
+        let msg = msg();
+        let mut packed = msg.clone();
+        pack(&mut packed, 1500).unwrap();
+        assert_eq!(packed, msg);
+    }
+
+    #[test]
+    fn pack_decode_file() {
+        let mut packed = msg();
+        pack(&mut packed, 500).unwrap();
+        let buf = packed.to_vec().unwrap();
+        assert!(buf.len() <= 500);
+        assert_eq!(
+            packed.opts().get(v4::OptionCode::OptionOverload),
+            Some(&v4::DhcpOption::OptionOverload(OVERLOAD_FILE))
+        );
+        let decoded = decode(&buf).unwrap();
+        assert_same_opts(&msg(), &decoded);
+        assert_eq!(decoded.fname(), None);
+    }
+
+    #[test]
+    fn pack_decode_file_and_sname() {
+        let mut packed = msg();
+        pack(&mut packed, 400).unwrap();
+        let buf = packed.to_vec().unwrap();
+        assert!(buf.len() <= 400);
+        assert_eq!(
+            packed.opts().get(v4::OptionCode::OptionOverload),
+            Some(&v4::DhcpOption::OptionOverload(
+                OVERLOAD_FILE | OVERLOAD_SNAME
+            ))
+        );
+        let decoded = decode(&buf).unwrap();
+        assert_same_opts(&msg(), &decoded);
+        assert_eq!(decoded.sname(), None);
+    }
+
+    #[test]
+    fn pack_too_small() {
+        assert!(pack(&mut msg(), 100).is_err());
+        // doesn't fit even with file & sname
+        let mut big = msg();
+        big.opts_mut()
+            .insert(v4::DhcpOption::Unknown(v4::UnknownOption::new(
+                224.into(),
+                vec![0; 400],
+            )));
+        assert!(pack(&mut big, 300).is_err());
+    }
+
+    #[test]
+    fn decode_split_overload() {
+        // opt 52 after a long option split in two (RFC 3396), with a
+        // hostname continued in `file`
+        let mut buf = v4::Message::default().to_vec().unwrap();
+        buf.truncate(OPTS_START);
+        buf.extend([53, 1, 2]);
+        buf.extend([12, 3]);
+        buf.extend(b"abc");
+        buf.extend([12, 3]);
+        buf.extend(b"def");
+        buf.extend([52, 1, OVERLOAD_FILE, 255]);
+        buf[FILE.start..FILE.start + 6].copy_from_slice(&[12, 3, b'g', b'h', b'i', 255]);
+        let decoded = decode(&buf).unwrap();
+        assert_eq!(
+            decoded.opts().get(v4::OptionCode::Hostname),
+            Some(&v4::DhcpOption::Hostname("abcdefghi".into()))
+        );
+        assert_eq!(
+            decoded.opts().get(v4::OptionCode::MessageType),
+            Some(&v4::DhcpOption::MessageType(v4::MessageType::Offer))
+        );
+    }
+}
@@ -0,0 +1,409 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use dhcproto::{v4, v6};
+use pnet_datalink::{Channel, DataLinkReceiver, DataLinkSender, MacAddr, NetworkInterface};
+use tracing::{debug, info, warn};
+
+use crate::{find_interface, util::Msg};
+
+/// RFC 5227 section 1.1 timing
+const PROBE_WAIT: Duration = Duration::from_secs(1);
+const PROBE_NUM: usize = 3;
+const PROBE_MIN: Duration = Duration::from_secs(1);
+const PROBE_MAX: Duration = Duration::from_secs(2);
+const ANNOUNCE_WAIT: Duration = Duration::from_secs(2);
+/// RFC 4861 section 10 RetransTimer, RFC 4862 DupAddrDetectTransmits is 1
+const RETRANS_TIMER: Duration = Duration::from_secs(1);
+
+const ETH_ARP: [u8; 2] = [0x08, 0x06];
+const ETH_IPV6: [u8; 2] = [0x86, 0xdd];
+const ARP_REQUEST: u16 = 1;
+const ICMPV6: u8 = 58;
+const NEIGHBOR_SOLICIT: u8 = 135;
+const NEIGHBOR_ADVERT: u8 = 136;
+/// target link-layer address NDP option
+const TARGET_LL_ADDR: u8 = 2;
+
+/// the interface given with `-i`, or else the one with a network containing `ip`
+pub fn interface_for(interface: Option<&str>, ip: IpAddr) -> Result<NetworkInterface> {
+    if let Some(int) = find_interface(interface)? {
+        return Ok(int);
+    }
+    pnet_datalink::interfaces()
+        .into_iter()
+        .find(|int| int.is_up() && int.ips.iter().any(|net| net.contains(ip)))
+        .with_context(|| format!("no interface is on the same network as {ip}, use --interface"))
+}
+
+/// probe the addresses offered in an OFFER/ACK or ADVERTISE/REPLY & report
+/// any that another host already uses
+pub fn lease(interface: Option<&str>, msg: &Msg) -> Result<()> {
+    for ip in offered(msg) {
+        let int = interface_for(interface, ip)?;
+        let owner = match ip {
+            IpAddr::V4(ip) => arp(&int, ip)?,
+            IpAddr::V6(ip) => dad(&int, ip)?,
+        };
+        report(ip, owner);
+    }
+    Ok(())
+}
+
+/// the addresses offered in an OFFER/ACK (yiaddr) or ADVERTISE/REPLY (IA_NA &
+/// IA_TA addresses), empty for any other message
+pub fn offered(msg: &Msg) -> Vec<IpAddr> {
+    match msg {
+        Msg::V4(msg) => match msg.opts().msg_type() {
+            Some(v4::MessageType::Offer | v4::MessageType::Ack)
+                if !msg.yiaddr().is_unspecified() =>
+            {
+                vec![IpAddr::V4(msg.yiaddr())]
+            }
+            _ => Vec::new(),
+        },
+        Msg::V6(msg) => match msg.msg_type() {
+            v6::MessageType::Advertise | v6::MessageType::Reply => msg
+                .opts()
+                .iter()
+                .filter_map(|opt| match opt {
+                    v6::DhcpOption::IANA(ia) => Some(ia.opts.iter()),
+                    v6::DhcpOption::IATA(ia) => Some(ia.opts.iter()),
+                    _ => None,
+                })
+                .flatten()
+                .filter_map(|opt| match opt {
+                    v6::DhcpOption::IAAddr(addr) => Some(IpAddr::V6(addr.addr)),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        },
+    }
+}
+
+/// ARP probe for `ip` on `interface` (RFC 5227 section 2.1.1), returning the
+/// hardware address of the host that already uses it. Any ARP from `ip` or
+/// another host's probe for `ip` is a conflict
+pub fn arp(interface: &NetworkInterface, ip: Ipv4Addr) -> Result<Option<MacAddr>> {
+    let (mac, mut tx, mut rx) = open(interface)?;
+    info!(%ip, interface = %interface.name, "ARP probing");
+    let conflict = |frame: &[u8]| arp_conflict(frame, mac, ip);
+
+    let probe = arp_probe(mac, ip);
+    if let Some(owner) = listen(
+        &mut *rx,
+        rand::random_range(Duration::ZERO..PROBE_WAIT),
+        conflict,
+    )? {
+        return Ok(Some(owner));
+    }
+    for n in 0..PROBE_NUM {
+        send(&mut *tx, &probe)?;
+        debug!(%ip, probe = n + 1, "sent ARP probe");
+        let wait = if n + 1 == PROBE_NUM {
+            ANNOUNCE_WAIT
+        } else {
+            rand::random_range(PROBE_MIN..PROBE_MAX)
+        };
+        if let Some(owner) = listen(&mut *rx, wait, conflict)? {
+            return Ok(Some(owner));
+        }
+    }
+    Ok(None)
+}
+
+/// duplicate address detection for `ip` on `interface` (RFC 4862 section
+/// 5.4), a Neighbor Solicitation from :: to the solicited-node address.
+/// A Neighbor Advertisement for `ip` or another host's DAD for it is a conflict
+pub fn dad(interface: &NetworkInterface, ip: Ipv6Addr) -> Result<Option<MacAddr>> {
+    let (mac, mut tx, mut rx) = open(interface)?;
+    info!(%ip, interface = %interface.name, "sending Neighbor Solicitation for DAD");
+    send(&mut *tx, &neighbor_solicit(mac, ip))?;
+    listen(&mut *rx, RETRANS_TIMER, |frame| {
+        dad_conflict(frame, mac, ip)
+    })
+}
+
+/// log the result of a probe
+pub fn report(ip: IpAddr, owner: Option<MacAddr>) {
+    match owner {
+        Some(owner) => warn!(%ip, %owner, "address conflict, another host is using it"),
+        None => info!(%ip, "no conflict, no other host answered for the address"),
+    }
+}
+
+type Link = (MacAddr, Box<dyn DataLinkSender>, Box<dyn DataLinkReceiver>);
+
+/// a raw ethernet channel on `interface` & its hardware address
+fn open(interface: &NetworkInterface) -> Result<Link> {
+    let mac = interface
+        .mac
+        .with_context(|| format!("interface {} has no hardware address", interface.name))?;
+    let config = pnet_datalink::Config {
+        read_timeout: Some(Duration::from_millis(100)),
+        ..Default::default()
+    };
+    match pnet_datalink::channel(interface, config)
+        .with_context(|| format!("opening a raw socket on {} failed", interface.name))?
+    {
+        Channel::Ethernet(tx, rx) => Ok((mac, tx, rx)),
+        _ => bail!("unsupported channel type on {}", interface.name),
+    }
+}
+
+fn send(tx: &mut dyn DataLinkSender, frame: &[u8]) -> Result<()> {
+    tx.send_to(frame, None)
+        .context("no frame was sent")?
+        .context("sending frame failed")
+}
+
+/// read frames for `wait`, returning the owner from the first one `conflict` matches
+fn listen(
+    rx: &mut dyn DataLinkReceiver,
+    wait: Duration,
+    conflict: impl Fn(&[u8]) -> Option<MacAddr>,
+) -> Result<Option<MacAddr>> {
+    let start = Instant::now();
+    while start.elapsed() < wait {
+        match rx.next() {
+            Ok(frame) => {
+                if let Some(owner) = conflict(frame) {
+                    return Ok(Some(owner));
+                }
+            }
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                ) => {}
+            Err(err) => return Err(err).context("reading from raw socket failed"),
+        }
+    }
+    Ok(None)
+}
+
+/// an ARP request from `mac` for `ip` with sender ip 0.0.0.0
+fn arp_probe(mac: MacAddr, ip: Ipv4Addr) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(42);
+    // ethernet
+    buf.extend([0xff; 6]);
+    buf.extend(mac.octets());
+    buf.extend(ETH_ARP);
+    // htype ethernet, ptype ipv4, hlen, plen
+    buf.extend([0, 1, 0x08, 0x00, 6, 4]);
+    buf.extend(ARP_REQUEST.to_be_bytes());
+    buf.extend(mac.octets());
+    buf.extend(Ipv4Addr::UNSPECIFIED.octets());
+    buf.extend([0; 6]);
+    buf.extend(ip.octets());
+    buf
+}
+
+/// an ARP reply or request from `ip`, or another host probing for it
+fn arp_conflict(frame: &[u8], mac: MacAddr, ip: Ipv4Addr) -> Option<MacAddr> {
+    if frame.len() < 42 || frame[12..14] != ETH_ARP {
+        return None;
+    }
+    let arp = &frame[14..42];
+    let sha = MacAddr::new(arp[8], arp[9], arp[10], arp[11], arp[12], arp[13]);
+    let spa = Ipv4Addr::new(arp[14], arp[15], arp[16], arp[17]);
+    let tpa = Ipv4Addr::new(arp[24], arp[25], arp[26], arp[27]);
+    (sha != mac && (spa == ip || (spa.is_unspecified() && tpa == ip))).then_some(sha)
+}
+
+/// a Neighbor Solicitation from `mac` & :: for `ip`, sent to its
+/// solicited-node multicast address (RFC 4291 section 2.7.1)
+fn neighbor_solicit(mac: MacAddr, ip: Ipv6Addr) -> Vec<u8> {
+    let [.., a, b, c] = ip.octets();
+    let dst = Ipv6Addr::new(
+        0xff02,
+        0,
+        0,
+        0,
+        0,
+        1,
+        0xff00 | a as u16,
+        u16::from_be_bytes([b, c]),
+    );
+    let mut icmp = vec![NEIGHBOR_SOLICIT, 0, 0, 0, 0, 0, 0, 0];
+    icmp.extend(ip.octets());
+    let sum = checksum(Ipv6Addr::UNSPECIFIED, dst, &icmp);
+    icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+
+    let mut buf = Vec::with_capacity(14 + 40 + icmp.len());
+    // ethernet, 33:33 + the low 32 bits of the multicast address
+    buf.extend([0x33, 0x33]);
+    buf.extend(&dst.octets()[12..]);
+    buf.extend(mac.octets());
+    buf.extend(ETH_IPV6);
+    // ipv6, hop limit must be 255
+    buf.extend([0x60, 0, 0, 0]);
+    buf.extend((icmp.len() as u16).to_be_bytes());
+    buf.extend([ICMPV6, 255]);
+    buf.extend(Ipv6Addr::UNSPECIFIED.octets());
+    buf.extend(dst.octets());
+    buf.extend(icmp);
+    buf
+}
+
+/// a Neighbor Advertisement for `ip`, or another host's DAD for it
+fn dad_conflict(frame: &[u8], mac: MacAddr, ip: Ipv6Addr) -> Option<MacAddr> {
+    // ethernet + ipv6 + icmpv6 up to the target address
+    if frame.len() < 14 + 40 + 24 || frame[12..14] != ETH_IPV6 || frame[14 + 6] != ICMPV6 {
+        return None;
+    }
+    let src = MacAddr::new(frame[6], frame[7], frame[8], frame[9], frame[10], frame[11]);
+    let ip_src = &frame[14 + 8..14 + 24];
+    let icmp = &frame[14 + 40..];
+    if src == mac || icmp[8..24] != ip.octets() {
+        return None;
+    }
+    match icmp[0] {
+        NEIGHBOR_ADVERT => {
+            // prefer the target link-layer address option over the frame source
+            let mut opts = &icmp[24..];
+            while opts.len() >= 8 && opts[1] != 0 {
+                let len = opts[1] as usize * 8;
+                if opts[0] == TARGET_LL_ADDR {
+                    return Some(MacAddr::new(
+                        opts[2], opts[3], opts[4], opts[5], opts[6], opts[7],
+                    ));
+                }
+                opts = opts.get(len..)?;
+            }
+            Some(src)
+        }
+        NEIGHBOR_SOLICIT if ip_src == Ipv6Addr::UNSPECIFIED.octets() => Some(src),
+        _ => None,
+    }
+}
+
+/// ICMPv6 checksum over the pseudo-header & message (RFC 4443 section 2.3)
+fn checksum(src: Ipv6Addr, dst: Ipv6Addr, icmp: &[u8]) -> u16 {
+    let mut data = Vec::with_capacity(40 + icmp.len());
+    data.extend(src.octets());
+    data.extend(dst.octets());
+    data.extend((icmp.len() as u32).to_be_bytes());
+    data.extend([0, 0, 0, ICMPV6]);
+    data.extend(icmp);
+    let mut sum = data
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 1);
+    const OTHER: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 2);
+
+    #[test]
+    fn ns_checksum() {
+        let ip = "2001:db8::1".parse().unwrap();
+        let frame = neighbor_solicit(MAC, ip);
+        // solicited-node multicast ff02::1:ff00:1
+        assert_eq!(frame[..6], [0x33, 0x33, 0xff, 0, 0, 1]);
+        let icmp = &frame[14 + 40..];
+        assert_eq!(icmp[2..4], [0x4c, 0xed]);
+        // summing with the checksum in place gives 0
+        let dst = "ff02::1:ff00:1".parse().unwrap();
+        assert_eq!(checksum(Ipv6Addr::UNSPECIFIED, dst, icmp), 0);
+    }
+
+    #[test]
+    fn arp() {
+        let ip = Ipv4Addr::new(10, 0, 0, 5);
+        let probe = arp_probe(MAC, ip);
+        assert_eq!(probe.len(), 42);
+        // our own probe isn't a conflict, another host's is
+        assert_eq!(arp_conflict(&probe, MAC, ip), None);
+        assert_eq!(arp_conflict(&probe, OTHER, ip), Some(MAC));
+        assert_eq!(
+            arp_conflict(&probe, OTHER, Ipv4Addr::new(10, 0, 0, 6)),
+            None
+        );
+        // a reply from the address
+        let mut reply = probe.clone();
+        reply[28..32].copy_from_slice(&ip.octets());
+        reply[38..42].copy_from_slice(&[10, 0, 0, 1]);
+        assert_eq!(arp_conflict(&reply, OTHER, ip), Some(MAC));
+    }
+
+    #[test]
+    fn dad() {
+        let ip = "2001:db8::1".parse().unwrap();
+        let ns = neighbor_solicit(MAC, ip);
+        assert_eq!(dad_conflict(&ns, MAC, ip), None);
+        assert_eq!(dad_conflict(&ns, OTHER, ip), Some(MAC));
+        assert_eq!(
+            dad_conflict(&ns, OTHER, "2001:db8::2".parse().unwrap()),
+            None
+        );
+        // an advertisement with a target link-layer address option
+        let mut na = ns.clone();
+        na[14 + 40] = NEIGHBOR_ADVERT;
+        na.extend([TARGET_LL_ADDR, 1, 2, 0, 0, 0, 0, 9]);
+        assert_eq!(
+            dad_conflict(&na, OTHER, ip),
+            Some(MacAddr(2, 0, 0, 0, 0, 9))
+        );
+    }
+
+    #[test]
+    fn offered_addrs() {
+        let mut offer = v4::Message::default();
+        offer.set_yiaddr(Ipv4Addr::new(10, 0, 0, 5));
+        offer
+            .opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Offer));
+        assert_eq!(
+            offered(&Msg::V4(offer.clone())),
+            [IpAddr::V4(Ipv4Addr::new(10, 0, 0, 5))]
+        );
+        offer
+            .opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Nak));
+        assert!(offered(&Msg::V4(offer)).is_empty());
+
+        let addr = |ip: &str| {
+            v6::DhcpOption::IAAddr(v6::IAAddr {
+                addr: ip.parse().unwrap(),
+                preferred_life: 0,
+                valid_life: 0,
+                opts: v6::DhcpOptions::new(),
+            })
+        };
+        let mut reply = v6::Message::new(v6::MessageType::Reply);
+        let mut na = v6::DhcpOptions::new();
+        na.insert(addr("2001:db8::5"));
+        reply.opts_mut().insert(v6::DhcpOption::IANA(v6::IANA {
+            id: 1,
+            t1: 0,
+            t2: 0,
+            opts: na,
+        }));
+        let mut ta = v6::DhcpOptions::new();
+        ta.insert(addr("2001:db8::6"));
+        reply
+            .opts_mut()
+            .insert(v6::DhcpOption::IATA(v6::IATA { id: 2, opts: ta }));
+        assert_eq!(
+            offered(&Msg::V6(reply)),
+            [
+                IpAddr::V6("2001:db8::5".parse().unwrap()),
+                IpAddr::V6("2001:db8::6".parse().unwrap())
+            ]
+        );
+    }
+}
@@ -0,0 +1,602 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use anyhow::{Context, Result};
+use argh::FromArgs;
+use dhcproto::v4;
+use tracing::{info, warn};
+
+use crate::{
+    discover::DiscoverArgs,
+    opts::{self, parse_htype, parse_mac, parse_opts, Chaddr, ClientId, ParamList},
+    request::RequestArgs,
+    tftp, Args, MsgType,
+};
+
+/// PXE boot server port
+pub const BOOT_SERVER_PORT: u16 = 4011;
+
+/// client architecture names (RFC 4578 & IANA processor architecture types)
+const ARCH_NAMES: &[(&str, u16)] = &[
+    ("bios", 0),
+    ("efi-ia32", 6),
+    ("efi-x64", 7),
+    ("efi-bc", 9),
+    ("efi-arm32", 10),
+    ("efi-arm64", 11),
+    ("efi-ia32-http", 15),
+    ("efi-x64-http", 16),
+    ("efi-arm32-http", 18),
+    ("efi-arm64-http", 19),
+    ("bios-http", 20),
+];
+
+#[derive(FromArgs, PartialEq, Eq, Debug, Clone)]
+/// Sends a PXE Discover then Request, reporting the boot server & file
+#[argh(subcommand, name = "pxe")]
+pub struct PxeArgs {
+    /// supply a hardware address of up to 16 bytes for DHCPv4 (use "random" for a random mac,
+    /// "none" for hlen 0) [default: first interface mac]
+    #[argh(
+        option,
+        short = 'c',
+        from_str_fn(parse_mac),
+        default = "opts::get_mac()"
+    )]
+    pub chaddr: Chaddr,
+    /// hardware type, a number or "eth", "ieee802", "ieee1394" or "infiniband", hlen is the
+    /// length of chaddr [default: eth]
+    #[argh(option, from_str_fn(parse_htype), default = "v4::HType::Eth")]
+    pub htype: v4::HType,
+    /// giaddr [default: 0.0.0.0]
+    #[argh(option, short = 'g', default = "Ipv4Addr::UNSPECIFIED")]
+    pub giaddr: Ipv4Addr,
+    /// client architecture opt 93, a number or one of: bios, efi-ia32, efi-x64, efi-bc,
+    /// efi-arm32, efi-arm64, efi-ia32-http, efi-x64-http, efi-arm32-http, efi-arm64-http, bios-http [default: bios]
+    #[argh(option, short = 'a', from_str_fn(parse_arch), default = "0")]
+    pub arch: u16,
+    /// network interface identifier opt 94 as "type.major.minor" [default: 1.2.1 (UNDI 2.1)]
+    #[argh(option, from_str_fn(parse_nii), default = "(1, 2, 1)")]
+    pub nii: (u8, u8, u8),
+    /// client machine uuid/guid opt 97 (use "random" for a random uuid) [default: random]
+    #[argh(option, from_str_fn(parse_uuid), default = "rand::random()")]
+    pub uuid: [u8; 16],
+    /// UEFI HTTP boot, send "HTTPClient" in opt 60 instead of "PXEClient" [default: false]
+    #[argh(switch)]
+    pub http: bool,
+    /// add opts to the message
+    /// [ex: these are equivalent- "118,hex,C0A80001" or "118,ip,192.168.0.1"]
+    #[argh(option, short = 'o', from_str_fn(parse_opts))]
+    pub opt: Vec<v4::DhcpOption>,
+    /// params to include: [default: 1,3,6,15,43,60,66,67]
+    #[argh(option, default = "default_params()")]
+    pub params: ParamList,
+    /// after DORA, send a REQUEST to the PXE boot server (proxyDHCP) [default: false]
+    #[argh(switch)]
+    pub boot: bool,
+    /// boot server to use with --boot [default: from opt 43 boot servers or the server identifier]
+    #[argh(option)]
+    pub boot_server: Option<Ipv4Addr>,
+    /// port of the boot server [default: 4011]
+    #[argh(option, default = "BOOT_SERVER_PORT")]
+    pub boot_port: u16,
+    /// boot server type to request in opt 43 sub-opt 71 [default: first boot menu item, or 0]
+    #[argh(option)]
+    pub boot_type: Option<u16>,
+    /// fetch the offered boot file over TFTP and report its size, sha256 & transfer time [default: false]
+    #[argh(switch)]
+    pub tftp: bool,
+    /// TFTP port to use with --tftp [default: 69]
+    #[argh(option, default = "tftp::TFTP_PORT")]
+    pub tftp_port: u16,
+    /// TFTP blksize option to ask for (RFC 2348) [default: 1468]
+    #[argh(option, default = "tftp::DEFAULT_BLKSIZE")]
+    pub tftp_blksize: u16,
+}
+
+fn default_params() -> ParamList {
+    "1,3,6,15,43,60,66,67".parse().expect("valid params")
+}
+
+pub fn parse_arch(arch: &str) -> Result<u16, String> {
+    if let Ok(n) = arch.parse::<u16>() {
+        return Ok(n);
+    }
+    ARCH_NAMES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(arch))
+        .map(|(_, n)| *n)
+        .ok_or_else(|| format!("unknown arch {arch:?}"))
+}
+
+fn parse_nii(nii: &str) -> Result<(u8, u8, u8), String> {
+    let parts = nii
+        .split('.')
+        .map(|n| n.parse::<u8>().map_err(|err| err.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match parts[..] {
+        [ty, major, minor] => Ok((ty, major, minor)),
+        _ => Err(format!("expected nii as \"type.major.minor\" got {nii:?}")),
+    }
+}
+
+fn parse_uuid(uuid: &str) -> Result<[u8; 16], String> {
+    if uuid == "random" {
+        return Ok(rand::random());
+    }
+    hex::decode(uuid.replace('-', ""))
+        .map_err(|err| err.to_string())?
+        .try_into()
+        .map_err(|_| format!("uuid must be 16 bytes, got {uuid:?}"))
+}
+
+impl PxeArgs {
+    /// opt 60, ex. "PXEClient:Arch:00007:UNDI:003001"
+    pub fn class_id(&self) -> String {
+        let (_, major, minor) = self.nii;
+        let client = if self.http { "HTTPClient" } else { "PXEClient" };
+        format!("{client}:Arch:{:05}:UNDI:{major:03}{minor:03}", self.arch)
+    }
+
+    /// the options a PXE client sends in DISCOVER & REQUEST
+    fn pxe_opts(&self) -> Vec<v4::DhcpOption> {
+        let (ty, major, minor) = self.nii;
+        // type 0 followed by the 16 byte guid (RFC 4578 2.2)
+        let mut uuid = vec![0];
+        uuid.extend(self.uuid);
+        let mut opts = vec![
+            v4::DhcpOption::ClassIdentifier(self.class_id().into_bytes()),
+            v4::DhcpOption::ClientSystemArchitecture(self.arch.into()),
+            v4::DhcpOption::ClientNetworkInterface(ty, major, minor),
+            v4::DhcpOption::ClientMachineIdentifier(uuid),
+        ];
+        // manually entered opts take precedence
+        opts.extend(self.opt.iter().cloned());
+        opts
+    }
+
+    pub fn discover(&self) -> DiscoverArgs {
+        DiscoverArgs {
+            chaddr: self.chaddr,
+            htype: self.htype,
+            client_id: ClientId::default(),
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            req_addr: None,
+            giaddr: self.giaddr,
+            subnet_select: None,
+            relay_link: None,
+            opt: self.pxe_opts(),
+            params: self.params.clone(),
+            rapid_commit: false,
+            fqdn: None,
+            vendor_class: None,
+            vi_class: Vec::new(),
+            vi_opt: Vec::new(),
+        }
+    }
+
+    /// REQUEST the address from `offer`, selecting the server that sent it
+    pub fn request(&self, offer: &v4::Message) -> RequestArgs {
+        let sident = match offer.opts().get(v4::OptionCode::ServerIdentifier) {
+            Some(v4::DhcpOption::ServerIdentifier(ip)) => Some(*ip),
+            _ => None,
+        };
+        RequestArgs {
+            chaddr: self.chaddr,
+            htype: self.htype,
+            client_id: ClientId::default(),
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            req_addr: Some(offer.yiaddr()),
+            sident,
+            state: None,
+            giaddr: self.giaddr,
+            subnet_select: None,
+            relay_link: None,
+            opt: self.pxe_opts(),
+            params: self.params.clone(),
+            fqdn: None,
+            vendor_class: None,
+            vi_class: Vec::new(),
+            vi_opt: Vec::new(),
+        }
+    }
+
+    /// REQUEST sent from the leased address to the boot server, asking for
+    /// boot item `ty` layer 0 in opt 43 sub-opt 71 (PXE spec 2.1 section 2.2.5)
+    pub fn boot_request(&self, ciaddr: Ipv4Addr, ty: u16) -> RequestArgs {
+        let [hi, lo] = ty.to_be_bytes();
+        let mut opt = self.pxe_opts();
+        opt.push(v4::DhcpOption::VendorExtensions(vec![
+            71, 4, hi, lo, 0, 0, 255,
+        ]));
+        RequestArgs {
+            chaddr: self.chaddr,
+            htype: self.htype,
+            client_id: ClientId::default(),
+            ciaddr,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            req_addr: None,
+            sident: None,
+            state: None,
+            giaddr: Ipv4Addr::UNSPECIFIED,
+            subnet_select: None,
+            relay_link: None,
+            opt,
+            params: self.params.clone(),
+            fqdn: None,
+            vendor_class: None,
+            vi_class: Vec::new(),
+            vi_opt: Vec::new(),
+        }
+    }
+
+    /// `Args` to send the boot server REQUEST after getting `offer` & `ack`,
+    /// the target & port are replaced, everything else is reused
+    pub fn boot_args(&self, args: &Args, offer: &v4::Message, ack: &v4::Message) -> Result<Args> {
+        let pxe = [ack, offer]
+            .iter()
+            .flat_map(
+                |msg| match msg.opts().get(v4::OptionCode::VendorExtensions) {
+                    Some(v4::DhcpOption::VendorExtensions(buf)) => decode_vendor(buf),
+                    _ => vec![],
+                },
+            )
+            .collect::<Vec<_>>();
+        let ty = self
+            .boot_type
+            .or_else(|| {
+                pxe.iter().find_map(|opt| match opt {
+                    PxeSubOpt::BootMenu(items) => items.first().map(|item| item.ty),
+                    _ => None,
+                })
+            })
+            .unwrap_or(0);
+        let server = self
+            .boot_server
+            .or_else(|| {
+                pxe.iter().find_map(|opt| match opt {
+                    PxeSubOpt::BootServers(servers) => servers
+                        .iter()
+                        .find(|server| server.ty == ty)
+                        .and_then(|server| server.addrs.first().copied()),
+                    _ => None,
+                })
+            })
+            .or_else(|| {
+                [offer, ack].iter().find_map(|msg| {
+                    match msg.opts().get(v4::OptionCode::ServerIdentifier) {
+                        Some(v4::DhcpOption::ServerIdentifier(ip)) => Some(*ip),
+                        _ => None,
+                    }
+                })
+            })
+            .or_else(|| Some(ack.siaddr()).filter(|ip| !ip.is_unspecified()))
+            .context("no boot server found in reply, use --boot-server")?;
+        info!(%server, port = self.boot_port, boot_type = ty, "sending boot server REQUEST");
+
+        let mut new_args = args.clone();
+        new_args.target = IpAddr::V4(server);
+        new_args.port = Some(self.boot_port);
+        // the client talks to the boot server directly
+        new_args.relay = None;
+        new_args.msg = Some(MsgType::Request(self.boot_request(ack.yiaddr(), ty)));
+        Ok(new_args)
+    }
+}
+
+/// PXE boot server entry from sub-opt 8
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootServer {
+    pub ty: u16,
+    pub addrs: Vec<Ipv4Addr>,
+}
+
+/// PXE boot menu entry from sub-opt 9
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootMenuItem {
+    pub ty: u16,
+    pub desc: String,
+}
+
+/// opt 43 sub-options when opt 60 is "PXEClient" (PXE spec 2.1 section 2.4.1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PxeSubOpt {
+    MtftpIp(Ipv4Addr),
+    MtftpClientPort(u16),
+    MtftpServerPort(u16),
+    MtftpTimeout(u8),
+    MtftpDelay(u8),
+    DiscoveryControl(u8),
+    DiscoveryMcastAddr(Ipv4Addr),
+    BootServers(Vec<BootServer>),
+    BootMenu(Vec<BootMenuItem>),
+    MenuPrompt { timeout: u8, prompt: String },
+    BootItem { ty: u16, layer: u16 },
+    Unknown(u8, Vec<u8>),
+}
+
+fn ip(buf: &[u8]) -> Option<Ipv4Addr> {
+    <[u8; 4]>::try_from(buf).ok().map(Ipv4Addr::from)
+}
+
+fn u16_at(buf: &[u8], i: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*buf.get(i)?, *buf.get(i + 1)?]))
+}
+
+fn decode_sub_opt(code: u8, val: &[u8]) -> Option<PxeSubOpt> {
+    use PxeSubOpt::*;
+    Some(match code {
+        1 => MtftpIp(ip(val)?),
+        2 => MtftpClientPort(u16_at(val, 0)?),
+        3 => MtftpServerPort(u16_at(val, 0)?),
+        4 => MtftpTimeout(*val.first()?),
+        5 => MtftpDelay(*val.first()?),
+        6 => DiscoveryControl(*val.first()?),
+        7 => DiscoveryMcastAddr(ip(val)?),
+        8 => {
+            let mut servers = vec![];
+            let mut i = 0;
+            while i < val.len() {
+                let ty = u16_at(val, i)?;
+                let count = *val.get(i + 2)? as usize;
+                let addrs = val
+                    .get(i + 3..i + 3 + count * 4)?
+                    .chunks(4)
+                    .filter_map(ip)
+                    .collect();
+                servers.push(BootServer { ty, addrs });
+                i += 3 + count * 4;
+            }
+            BootServers(servers)
+        }
+        9 => {
+            let mut items = vec![];
+            let mut i = 0;
+            while i < val.len() {
+                let ty = u16_at(val, i)?;
+                let len = *val.get(i + 2)? as usize;
+                let desc = String::from_utf8_lossy(val.get(i + 3..i + 3 + len)?).into_owned();
+                items.push(BootMenuItem { ty, desc });
+                i += 3 + len;
+            }
+            BootMenu(items)
+        }
+        10 => MenuPrompt {
+            timeout: *val.first()?,
+            prompt: String::from_utf8_lossy(&val[1..]).into_owned(),
+        },
+        71 => BootItem {
+            ty: u16_at(val, 0)?,
+            layer: u16_at(val, 2)?,
+        },
+        _ => return None,
+    })
+}
+
+/// decode PXE sub-options from the value of opt 43
+pub fn decode_vendor(buf: &[u8]) -> Vec<PxeSubOpt> {
+    let mut opts = vec![];
+    let mut i = 0;
+    while i < buf.len() {
+        let code = buf[i];
+        match code {
+            0 => {
+                i += 1;
+                continue;
+            }
+            255 => break,
+            _ => {}
+        }
+        let Some(&len) = buf.get(i + 1) else { break };
+        let Some(val) = buf.get(i + 2..i + 2 + len as usize) else {
+            break;
+        };
+        opts.push(
+            decode_sub_opt(code, val).unwrap_or_else(|| PxeSubOpt::Unknown(code, val.to_vec())),
+        );
+        i += 2 + len as usize;
+    }
+    opts
+}
+
+fn opt_str(msg: &v4::Message, code: v4::OptionCode) -> Option<String> {
+    match msg.opts().get(code)? {
+        v4::DhcpOption::ClassIdentifier(s)
+        | v4::DhcpOption::TFTPServerName(s)
+        | v4::DhcpOption::BootfileName(s) => {
+            Some(String::from_utf8_lossy(s).trim_end_matches('\0').to_owned())
+        }
+        _ => None,
+    }
+}
+
+/// boot file name from opt 67 or the `file` header field
+pub fn bootfile(msg: &v4::Message) -> Option<String> {
+    opt_str(msg, v4::OptionCode::BootfileName)
+        .or_else(|| msg.fname_str().and_then(Result::ok).map(trim_nul))
+}
+
+/// TFTP server name from opt 66 or the `sname` header field
+pub fn tftp_server(msg: &v4::Message) -> Option<String> {
+    opt_str(msg, v4::OptionCode::TFTPServerName)
+        .or_else(|| msg.sname_str().and_then(Result::ok).map(trim_nul))
+}
+
+/// Log the boot info from a reply: next-server (`siaddr`), boot file from
+/// `file` or opt 67, TFTP server from `sname` or opt 66 and opt 43 PXE sub-opts
+pub fn report(msg: &v4::Message) {
+    let class = opt_str(msg, v4::OptionCode::ClassIdentifier);
+    let bootfile = bootfile(msg);
+    let tftp_server = tftp_server(msg);
+    let pxe = match msg.opts().get(v4::OptionCode::VendorExtensions) {
+        Some(v4::DhcpOption::VendorExtensions(buf)) => decode_vendor(buf),
+        _ => vec![],
+    };
+    info!(
+        msg_type = ?msg.opts().msg_type(),
+        next_server = %msg.siaddr(),
+        ?tftp_server,
+        ?bootfile,
+        ?class,
+        ?pxe,
+        "PXE boot info"
+    );
+    if bootfile.is_none() && pxe.is_empty() {
+        warn!("reply has no boot file or PXE vendor options");
+    }
+}
+
+fn trim_nul(s: &str) -> String {
+    s.trim_end_matches('\0').to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> PxeArgs {
+        PxeArgs::from_args(&["pxe"], args).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_arch("efi-x64").unwrap(), 7);
+        assert_eq!(parse_arch("EFI-ARM64").unwrap(), 11);
+        assert_eq!(parse_arch("21").unwrap(), 21);
+        assert!(parse_arch("sparc").is_err());
+        assert_eq!(parse_nii("1.3.10").unwrap(), (1, 3, 10));
+        assert!(parse_nii("1.3").is_err());
+        assert_eq!(
+            parse_uuid("00112233-4455-6677-8899-aabbccddeeff").unwrap(),
+            [
+                0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd,
+                0xee, 0xff
+            ]
+        );
+        assert!(parse_uuid("0011").is_err());
+    }
+
+    #[test]
+    fn class_id() {
+        assert_eq!(args(&[]).class_id(), "PXEClient:Arch:00000:UNDI:002001");
+        assert_eq!(
+            args(&["-a", "efi-x64-http", "--http", "--nii", "1.3.0"]).class_id(),
+            "HTTPClient:Arch:00016:UNDI:003000"
+        );
+    }
+
+    #[test]
+    fn vendor() {
+        let buf = [
+            &[6, 1, 3][..],
+            // boot servers: type 1 with 2 addresses, type 2 with none
+            &[8, 14, 0, 1, 2, 10, 0, 0, 1, 10, 0, 0, 2, 0, 2, 0],
+            &[9, 7, 0, 1, 4, b'b', b'o', b'o', b't'],
+            &[10, 3, 5, b'g', b'o'],
+            &[71, 4, 0, 1, 0, 0],
+            &[0, 200, 1, 9],
+            &[255, 1, 1],
+        ]
+        .concat();
+        assert_eq!(
+            decode_vendor(&buf),
+            [
+                PxeSubOpt::DiscoveryControl(3),
+                PxeSubOpt::BootServers(vec![
+                    BootServer {
+                        ty: 1,
+                        addrs: vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)]
+                    },
+                    BootServer {
+                        ty: 2,
+                        addrs: vec![]
+                    }
+                ]),
+                PxeSubOpt::BootMenu(vec![BootMenuItem {
+                    ty: 1,
+                    desc: "boot".into()
+                }]),
+                PxeSubOpt::MenuPrompt {
+                    timeout: 5,
+                    prompt: "go".into()
+                },
+                PxeSubOpt::BootItem { ty: 1, layer: 0 },
+                PxeSubOpt::Unknown(200, vec![9]),
+            ]
+        );
+        // a truncated sub-opt is left undecoded
+        assert_eq!(
+            decode_vendor(&[1, 2, 10, 0]),
+            [PxeSubOpt::Unknown(1, vec![10, 0])]
+        );
+    }
+
+    #[test]
+    fn boot_info() {
+        let mut msg = v4::Message::default();
+        msg.set_fname_str("pxelinux.0");
+        msg.set_sname_str("tftp.example.com");
+        assert_eq!(bootfile(&msg).unwrap(), "pxelinux.0");
+        assert_eq!(tftp_server(&msg).unwrap(), "tftp.example.com");
+        // options take precedence over the header fields
+        msg.opts_mut()
+            .insert(v4::DhcpOption::BootfileName(b"ipxe.efi\0".to_vec()));
+        msg.opts_mut()
+            .insert(v4::DhcpOption::TFTPServerName(b"10.0.0.1".to_vec()));
+        assert_eq!(bootfile(&msg).unwrap(), "ipxe.efi");
+        assert_eq!(tftp_server(&msg).unwrap(), "10.0.0.1");
+    }
+
+    #[test]
+    fn boot_server() {
+        let main_args = Args::from_args(&["dhcpm"], &["255.255.255.255", "pxe"]).unwrap();
+        let pxe = args(&[]);
+        let mut offer = v4::Message::default();
+        offer.set_yiaddr(Ipv4Addr::new(10, 0, 0, 50));
+        offer
+            .opts_mut()
+            .insert(v4::DhcpOption::ServerIdentifier(Ipv4Addr::new(10, 0, 0, 1)));
+        let mut ack = offer.clone();
+        let boot = |pxe: &PxeArgs, offer: &v4::Message, ack: &v4::Message| {
+            let args = pxe.boot_args(&main_args, offer, ack)?;
+            let Some(MsgType::Request(req)) = args.msg else {
+                panic!("{:?}", args.msg)
+            };
+            anyhow::Ok((args.target, args.port, req))
+        };
+
+        // no PXE sub-opts, the DHCP server is the boot server
+        let (target, port, req) = boot(&pxe, &offer, &ack).unwrap();
+        assert_eq!(target, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(port, Some(BOOT_SERVER_PORT));
+        assert_eq!(req.ciaddr, Ipv4Addr::new(10, 0, 0, 50));
+        assert!(req.opt.contains(&v4::DhcpOption::VendorExtensions(vec![
+            71, 4, 0, 0, 0, 0, 255
+        ])));
+
+        // the first menu item's type & its boot server
+        ack.opts_mut().insert(v4::DhcpOption::VendorExtensions(vec![
+            8, 7, 0, 5, 1, 10, 0, 0, 9, 9, 5, 0, 5, 2, b'o', b's', 255,
+        ]));
+        let (target, _, req) = boot(&pxe, &offer, &ack).unwrap();
+        assert_eq!(target, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 9)));
+        assert!(req.opt.contains(&v4::DhcpOption::VendorExtensions(vec![
+            71, 4, 0, 5, 0, 0, 255
+        ])));
+
+        // no boot server for the type asked for
+        let (target, _, _) = boot(&args(&["--boot-type", "6"]), &offer, &ack).unwrap();
+        assert_eq!(target, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        let (target, port, _) = boot(
+            &args(&["--boot-server", "10.0.0.7", "--boot-port", "4012"]),
+            &offer,
+            &ack,
+        )
+        .unwrap();
+        assert_eq!(target, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7)));
+        assert_eq!(port, Some(4012));
+
+        let none = v4::Message::default();
+        assert!(boot(&pxe, &none, &none).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use std::net::{Ipv4Addr, SocketAddr};
+
+use anyhow::{bail, Result};
+use dhcproto::v4::{self, relay};
+use tracing::{info, trace};
+
+/// Modify `msg` the way a relay agent would before forwarding it to the server:
+/// - set `giaddr` to the relay address (if the client didn't already set one)
+/// - increment `hops`
+/// - add opt 82 with our circuit id, keeping any sub-opts already present
+pub fn relay_msg(msg: &mut v4::Message, giaddr: Ipv4Addr, circuit_id: &str) {
+    if msg.giaddr().is_unspecified() {
+        msg.set_giaddr(giaddr);
+    }
+    msg.set_hops(msg.hops().saturating_add(1));
+
+    let mut info = match msg.opts_mut().remove(v4::OptionCode::RelayAgentInformation) {
+        Some(v4::DhcpOption::RelayAgentInformation(info)) => info,
+        _ => relay::RelayAgentInformation::default(),
+    };
+    if info.get(relay::RelayCode::AgentCircuitId).is_none() {
+        info.insert(relay::RelayInfo::AgentCircuitId(
+            circuit_id.as_bytes().to_vec(),
+        ));
+    }
+    msg.opts_mut()
+        .insert(v4::DhcpOption::RelayAgentInformation(info));
+    trace!(giaddr = ?msg.giaddr(), hops = msg.hops(), "relayed msg");
+}
+
+/// Validate that the server treated the message as relayed: the reply must
+/// arrive from the server port, address `giaddr` and echo opt 82 unchanged
+/// (RFC 3046 section 2.2)
+pub fn check_reply(
+    sent: &v4::Message,
+    reply: &v4::Message,
+    from: SocketAddr,
+    server_port: u16,
+) -> Result<()> {
+    if from.port() != server_port {
+        bail!(
+            "relay reply came from port {} expected {server_port}",
+            from.port()
+        );
+    }
+    if reply.giaddr() != sent.giaddr() {
+        bail!(
+            "relay reply has giaddr {} expected {}",
+            reply.giaddr(),
+            sent.giaddr()
+        );
+    }
+    let sent_info = sent.opts().get(v4::OptionCode::RelayAgentInformation);
+    let reply_info = reply.opts().get(v4::OptionCode::RelayAgentInformation);
+    match (sent_info, reply_info) {
+        (Some(sent_info), Some(reply_info)) if sent_info == reply_info => {}
+        (Some(_), Some(reply_info)) => {
+            bail!("relay reply opt 82 differs from what was sent: {reply_info:?}")
+        }
+        (Some(_), None) => bail!("relay reply did not echo opt 82"),
+        (None, _) => {}
+    }
+    info!(giaddr = ?reply.giaddr(), ?from, "relay reply validated (giaddr & opt 82 echoed)");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+    fn circuit_id(msg: &v4::Message) -> Option<&relay::RelayInfo> {
+        match msg.opts().get(v4::OptionCode::RelayAgentInformation) {
+            Some(v4::DhcpOption::RelayAgentInformation(info)) => {
+                info.get(relay::RelayCode::AgentCircuitId)
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn relay() {
+        let mut msg = v4::Message::default();
+        relay_msg(&mut msg, GIADDR, "eth0");
+        assert_eq!(msg.giaddr(), GIADDR);
+        assert_eq!(msg.hops(), 1);
+        assert_eq!(
+            circuit_id(&msg),
+            Some(&relay::RelayInfo::AgentCircuitId(b"eth0".to_vec()))
+        );
+        // a second relay keeps giaddr & the circuit id
+        relay_msg(&mut msg, Ipv4Addr::new(10, 0, 1, 1), "eth1");
+        assert_eq!(msg.giaddr(), GIADDR);
+        assert_eq!(msg.hops(), 2);
+        assert_eq!(
+            circuit_id(&msg),
+            Some(&relay::RelayInfo::AgentCircuitId(b"eth0".to_vec()))
+        );
+    }
+
+    #[test]
+    fn reply() {
+        let mut sent = v4::Message::default();
+        relay_msg(&mut sent, GIADDR, "eth0");
+        let from = SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 67));
+        let mut reply = sent.clone();
+        check_reply(&sent, &reply, from, 67).unwrap();
+        assert!(check_reply(&sent, &reply, from, 1067).is_err());
+
+        reply.set_giaddr(Ipv4Addr::UNSPECIFIED);
+        assert!(check_reply(&sent, &reply, from, 67).is_err());
+        reply.set_giaddr(GIADDR);
+
+        reply
+            .opts_mut()
+            .remove(v4::OptionCode::RelayAgentInformation);
+        assert!(check_reply(&sent, &reply, from, 67).is_err());
+        let mut info = relay::RelayAgentInformation::default();
+        info.insert(relay::RelayInfo::AgentCircuitId(b"other".to_vec()));
+        reply
+            .opts_mut()
+            .insert(v4::DhcpOption::RelayAgentInformation(info));
+        assert!(check_reply(&sent, &reply, from, 67).is_err());
+    }
+}
@@ -1,23 +1,43 @@
-use std::net::Ipv4Addr;
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    str::FromStr,
+};
 
+use anyhow::{bail, Result};
 use argh::FromArgs;
 use dhcproto::v4;
-use mac_address::MacAddress;
+use tracing::info;
 
-use crate::opts::{self, parse_mac, parse_opts, ParamList};
+use crate::{
+    fqdn::{parse_fqdn, Fqdn},
+    opts::{
+        self, parse_client_id, parse_htype, parse_mac, parse_opts, Chaddr, ClientId, ParamList,
+    },
+    vendor::{self, parse_vi_class, parse_vi_opt, ViClass, ViOpt},
+};
 
 #[derive(FromArgs, PartialEq, Eq, Debug, Clone)]
 /// Send a REQUEST msg
 #[argh(subcommand, name = "request")]
 pub struct RequestArgs {
-    /// supply a mac address for DHCPv4 (use "random" for a random mac) [default: first interface mac]
+    /// supply a hardware address of up to 16 bytes for DHCPv4 (use "random" for a random mac,
+    /// "none" for hlen 0) [default: first interface mac]
     #[argh(
         option,
         short = 'c',
         from_str_fn(parse_mac),
         default = "opts::get_mac()"
     )]
-    pub chaddr: MacAddress,
+    pub chaddr: Chaddr,
+    /// hardware type, a number or "eth", "ieee802", "ieee1394" or "infiniband", hlen is the
+    /// length of chaddr [default: eth]
+    #[argh(option, from_str_fn(parse_htype), default = "v4::HType::Eth")]
+    pub htype: v4::HType,
+    /// client id opt 61: "hwtype+mac", "mac" (no hardware type), "none", "hex:<hex>" or
+    /// "duid[:<iaid>[:<duid>]]" for an RFC 4361 IAID & DUID, defaulting to IAID 1 &
+    /// DUID-LL of chaddr [default: hwtype+mac, duid if chaddr is "none"]
+    #[argh(option, from_str_fn(parse_client_id), default = "ClientId::default()")]
+    pub client_id: ClientId,
     /// address for client [default: None]
     #[argh(option, short = 'y', default = "Ipv4Addr::UNSPECIFIED")]
     pub yiaddr: Ipv4Addr,
@@ -33,6 +53,11 @@ pub struct RequestArgs {
     /// giaddr [default: 0.0.0.0]
     #[argh(option, short = 'g', default = "Ipv4Addr::UNSPECIFIED")]
     pub giaddr: Ipv4Addr,
+    /// client state, one of "selecting", "init-reboot", "renewing" or "rebinding". Checks
+    /// ciaddr, --req-addr & --sident match RFC 2131 table 5 and picks the destination:
+    /// unicast to the target for renewing, broadcast otherwise unless relayed [default: None]
+    #[argh(option)]
+    pub state: Option<ReqState>,
     /// subnet selection opt 118 [default: None]
     #[argh(option)]
     pub subnet_select: Option<Ipv4Addr>,
@@ -46,14 +71,33 @@ pub struct RequestArgs {
     /// params to include: [default: 1,3,6,15 (Subnet, Router, DnsServer, DomainName]
     #[argh(option, default = "opts::default_params()")]
     pub params: ParamList,
+    /// client FQDN opt 81 as "<name>[,<flags>]", flags any of S, O, E & N [default: SE], without E
+    /// the name is ASCII encoded [ex: "host.example.com,SE"] [default: None]
+    #[argh(option, from_str_fn(parse_fqdn))]
+    pub fqdn: Option<Fqdn>,
+    /// vendor class identifier opt 60 [default: None]
+    #[argh(option)]
+    pub vendor_class: Option<String>,
+    /// vendor-identifying vendor class opt 124 as "<enterprise>:<data>[,<data>...]",
+    /// can be repeated [ex: "4491:docsis3.0"]
+    #[argh(option, from_str_fn(parse_vi_class))]
+    pub vi_class: Vec<ViClass>,
+    /// vendor-identifying vendor-specific info opt 125 sub-option as
+    /// "<enterprise>:<code>,<type>,<value>" with the same types as --opt, can be repeated
+    /// [ex: "3561:1,str,ABC123"]
+    #[argh(option, from_str_fn(parse_vi_opt))]
+    pub vi_opt: Vec<ViOpt>,
 }
 
 impl Default for RequestArgs {
     fn default() -> Self {
         Self {
             chaddr: opts::get_mac(),
+            htype: v4::HType::Eth,
+            client_id: ClientId::default(),
             ciaddr: Ipv4Addr::UNSPECIFIED,
             giaddr: Ipv4Addr::UNSPECIFIED,
+            state: None,
             yiaddr: Ipv4Addr::UNSPECIFIED,
             sident: None,
             req_addr: None,
@@ -61,32 +105,167 @@ impl Default for RequestArgs {
             relay_link: None,
             opt: Vec::new(),
             params: opts::default_params(),
+            fqdn: None,
+            vendor_class: None,
+            vi_class: Vec::new(),
+            vi_opt: Vec::new(),
         }
     }
 }
 
+/// the client state a REQUEST is sent from (RFC 2131 section 4.3.2)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReqState {
+    Selecting,
+    InitReboot,
+    Renewing,
+    Rebinding,
+}
+
+impl FromStr for ReqState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "selecting" => ReqState::Selecting,
+            "init-reboot" => ReqState::InitReboot,
+            "renewing" => ReqState::Renewing,
+            "rebinding" => ReqState::Rebinding,
+            _ => {
+                return Err(format!(
+                    "unknown state {s:?}, expected selecting, init-reboot, renewing or rebinding"
+                ))
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for ReqState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ReqState::Selecting => "selecting",
+            ReqState::InitReboot => "init-reboot",
+            ReqState::Renewing => "renewing",
+            ReqState::Rebinding => "rebinding",
+        })
+    }
+}
+
+/// the v4 addresses & prefix lengths on our interfaces
+pub fn v4_networks() -> Vec<(Ipv4Addr, u8)> {
+    pnet_datalink::interfaces()
+        .iter()
+        .flat_map(|int| &int.ips)
+        .filter_map(|net| match net.ip() {
+            IpAddr::V4(ip) => Some((ip, net.prefix())),
+            IpAddr::V6(_) => None,
+        })
+        .collect()
+}
+
+/// `ip` is the limited broadcast address or the broadcast address of one of
+/// `nets`, from [`v4_networks`]
+fn is_broadcast(ip: Ipv4Addr, nets: &[(Ipv4Addr, u8)]) -> bool {
+    ip.is_broadcast()
+        || nets.iter().any(|&(addr, prefix)| {
+            // /31 & /32 have no broadcast address (RFC 3021)
+            prefix < 31 && u32::from(addr) | (u32::MAX >> prefix) == u32::from(ip)
+        })
+}
+
 impl RequestArgs {
+    /// check ciaddr, opt 50 & opt 54 against `--state` (RFC 2131 table 5) and
+    /// return the destination to send to. A relayed REQUEST is always unicast,
+    /// `nets` are our v4 networks to tell if `target` is a broadcast address
+    pub fn state_target(
+        &self,
+        target: IpAddr,
+        relayed: bool,
+        nets: &[(Ipv4Addr, u8)],
+    ) -> Result<IpAddr> {
+        let Some(state) = self.state else {
+            return Ok(target);
+        };
+        let has =
+            |code: v4::OptionCode| self.opt.iter().any(|opt| v4::OptionCode::from(opt) == code);
+        let req_addr = self.req_addr.is_some() || has(v4::OptionCode::RequestedIpAddress);
+        let sident = self.sident.is_some() || has(v4::OptionCode::ServerIdentifier);
+        let ciaddr = !self.ciaddr.is_unspecified();
+        match state {
+            ReqState::Selecting | ReqState::InitReboot => {
+                if ciaddr {
+                    bail!("{state} must not set --ciaddr");
+                }
+                if !req_addr {
+                    bail!("{state} needs --req-addr, the address being requested");
+                }
+            }
+            ReqState::Renewing | ReqState::Rebinding => {
+                if !ciaddr {
+                    bail!("{state} needs --ciaddr, the leased address");
+                }
+                if req_addr {
+                    bail!("{state} must not send a requested ip (opt 50)");
+                }
+            }
+        }
+        match (state, sident) {
+            (ReqState::Selecting, false) => {
+                bail!("selecting needs --sident, the server from the OFFER")
+            }
+            (ReqState::Selecting, true) => {}
+            (state, true) => bail!("{state} must not send a server identifier (opt 54)"),
+            _ => {}
+        }
+        let broadcast = match target {
+            IpAddr::V4(ip) => is_broadcast(ip, nets),
+            IpAddr::V6(_) => bail!("--state is only supported for dhcpv4"),
+        };
+        match state {
+            ReqState::Renewing if broadcast => {
+                bail!("renewing is unicast to the server, use its address as the target")
+            }
+            ReqState::Renewing => Ok(target),
+            _ if broadcast || relayed || !self.giaddr.is_unspecified() => Ok(target),
+            state => {
+                info!(%state, "REQUEST is broadcast, sending to 255.255.255.255");
+                Ok(IpAddr::V4(Ipv4Addr::BROADCAST))
+            }
+        }
+    }
+
     pub fn build(&self, broadcast: bool) -> v4::Message {
         let mut msg = v4::Message::new(
             self.ciaddr,
             self.yiaddr,
             Ipv4Addr::UNSPECIFIED,
             self.giaddr,
-            &self.chaddr.bytes(),
+            self.chaddr.bytes(),
         );
+        msg.set_htype(self.htype);
 
+        // a renewing or rebinding client has ciaddr & can receive unicast replies
+        // (RFC 2131 section 4.1), otherwise the flag follows the target
+        let broadcast = match self.state {
+            Some(ReqState::Renewing | ReqState::Rebinding) => false,
+            _ => broadcast || opts::needs_broadcast(self.htype),
+        };
         if broadcast {
             msg.set_flags(v4::Flags::default().set_broadcast());
         }
 
         msg.opts_mut()
             .insert(v4::DhcpOption::MessageType(v4::MessageType::Request));
-        msg.opts_mut().insert(v4::DhcpOption::ClientIdentifier(
-            self.chaddr.bytes().to_vec(),
-        ));
+        if let Some(id) = self.client_id.opt(self.htype, self.chaddr) {
+            msg.opts_mut().insert(id);
+        }
         // insert parse params
         msg.opts_mut()
             .insert(v4::DhcpOption::ParameterRequestList(self.params.0.clone()));
+        // insert vendor class & vendor-specific info
+        for opt in vendor::v4_opts(self.vendor_class.as_deref(), &self.vi_class, &self.vi_opt) {
+            msg.opts_mut().insert(opt);
+        }
         // insert manually entered opts
         for opt in &self.opt {
             msg.opts_mut().insert(opt.clone());
@@ -109,6 +288,9 @@ impl RequestArgs {
         if let Some(ip) = self.subnet_select {
             msg.opts_mut().insert(v4::DhcpOption::SubnetSelection(ip));
         }
+        if let Some(fqdn) = &self.fqdn {
+            msg.opts_mut().insert(fqdn.v4());
+        }
         msg
     }
 }
@@ -181,8 +363,17 @@ pub mod request_mod {
     #[rhai_fn(global, set = "chaddr")]
     pub fn set_chaddr(args: &mut RequestArgs, chaddr: rhai::Blob) {
         trace!(?chaddr, "setting chaddr");
-        let bytes: [u8; 6] = chaddr.try_into().expect("failed to convert macaddress");
-        args.chaddr = bytes.into();
+        args.chaddr = Chaddr::try_from(&chaddr[..]).expect("failed to convert chaddr");
+    }
+    // htype
+    #[rhai_fn(global, get = "htype", pure)]
+    pub fn get_htype(args: &mut RequestArgs) -> i64 {
+        u8::from(args.htype) as i64
+    }
+    #[rhai_fn(global, set = "htype")]
+    pub fn set_htype(args: &mut RequestArgs, htype: i64) {
+        trace!(?htype, "setting htype");
+        args.htype = u8::try_from(htype).expect("htype must fit in a u8").into();
     }
     #[rhai_fn(global, name = "rand_chaddr")]
     pub fn rand_chaddr(args: &mut RequestArgs) {
@@ -214,6 +405,25 @@ pub mod request_mod {
         trace!(?sident, "setting req_addr");
         args.sident = Some(sident.parse::<Ipv4Addr>().expect("failed to parse sident"));
     }
+    // fqdn
+    #[rhai_fn(global, set = "fqdn")]
+    pub fn set_fqdn(args: &mut RequestArgs, fqdn: &str) {
+        trace!(?fqdn, "setting fqdn");
+        args.fqdn = Some(crate::fqdn::parse_fqdn(fqdn).expect("failed to parse fqdn"));
+    }
+    // vendor_class
+    #[rhai_fn(global, set = "vendor_class")]
+    pub fn set_vendor_class(args: &mut RequestArgs, vendor_class: &str) {
+        trace!(?vendor_class, "setting vendor_class");
+        args.vendor_class = Some(vendor_class.to_owned());
+    }
+    // client_id
+    #[rhai_fn(global, set = "client_id")]
+    pub fn set_client_id(args: &mut RequestArgs, client_id: &str) {
+        trace!(?client_id, "setting client_id");
+        args.client_id =
+            crate::opts::parse_client_id(client_id).expect("failed to parse client_id");
+    }
     // opt
     #[rhai_fn(global, set = "opt")]
     pub fn set_opt(args: &mut RequestArgs, opt: String) {
@@ -234,3 +444,114 @@ pub mod request_mod {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3));
+    const BROADCAST: IpAddr = IpAddr::V4(Ipv4Addr::BROADCAST);
+    const NETS: [(Ipv4Addr, u8); 1] = [(Ipv4Addr::new(10, 1, 2, 10), 24)];
+
+    fn args(state: &str) -> RequestArgs {
+        RequestArgs {
+            chaddr: [1, 2, 3, 4, 5, 6].into(),
+            state: Some(state.parse().unwrap()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn no_state() {
+        let args = RequestArgs {
+            state: None,
+            ..args("selecting")
+        };
+        assert_eq!(args.state_target(SERVER, false, &NETS).unwrap(), SERVER);
+    }
+
+    #[test]
+    fn selecting() {
+        let mut args = args("selecting");
+        args.req_addr = Some(Ipv4Addr::new(10, 1, 2, 10));
+        assert!(args.state_target(SERVER, false, &NETS).is_err());
+        args.sident = Some(Ipv4Addr::new(10, 1, 2, 3));
+        assert_eq!(args.state_target(SERVER, false, &NETS).unwrap(), BROADCAST);
+        // relayed requests go to the target
+        assert_eq!(args.state_target(SERVER, true, &NETS).unwrap(), SERVER);
+        args.ciaddr = Ipv4Addr::new(10, 1, 2, 10);
+        assert!(args.state_target(SERVER, false, &NETS).is_err());
+    }
+
+    #[test]
+    fn init_reboot() {
+        let mut args = args("init-reboot");
+        assert!(args.state_target(SERVER, false, &NETS).is_err());
+        // opt 50 from --opt counts as --req-addr
+        args.opt = vec![v4::DhcpOption::RequestedIpAddress(Ipv4Addr::new(
+            10, 1, 2, 10,
+        ))];
+        assert_eq!(args.state_target(SERVER, false, &NETS).unwrap(), BROADCAST);
+        args.giaddr = Ipv4Addr::new(10, 1, 2, 1);
+        assert_eq!(args.state_target(SERVER, false, &NETS).unwrap(), SERVER);
+        args.sident = Some(Ipv4Addr::new(10, 1, 2, 3));
+        assert!(args.state_target(SERVER, false, &NETS).is_err());
+    }
+
+    #[test]
+    fn renewing() {
+        let mut args = args("renewing");
+        assert!(args.state_target(SERVER, false, &NETS).is_err());
+        args.ciaddr = Ipv4Addr::new(10, 1, 2, 10);
+        assert_eq!(args.state_target(SERVER, false, &NETS).unwrap(), SERVER);
+        assert!(args.state_target(BROADCAST, false, &NETS).is_err());
+        // the broadcast address of our network
+        let subnet = IpAddr::V4(Ipv4Addr::new(10, 1, 2, 255));
+        assert!(args.state_target(subnet, false, &NETS).is_err());
+        assert_eq!(args.state_target(subnet, false, &[]).unwrap(), subnet);
+        assert!(args
+            .state_target("::1".parse().unwrap(), false, &NETS)
+            .is_err());
+        args.req_addr = Some(Ipv4Addr::new(10, 1, 2, 10));
+        assert!(args.state_target(SERVER, false, &NETS).is_err());
+    }
+
+    #[test]
+    fn rebinding() {
+        let mut args = args("rebinding");
+        args.ciaddr = Ipv4Addr::new(10, 1, 2, 10);
+        assert_eq!(args.state_target(SERVER, false, &NETS).unwrap(), BROADCAST);
+        assert_eq!(
+            args.state_target(BROADCAST, false, &NETS).unwrap(),
+            BROADCAST
+        );
+    }
+
+    #[test]
+    fn broadcast_flag() {
+        let flag = |args: &RequestArgs, broadcast| args.build(broadcast).flags().broadcast();
+        assert!(flag(&args("selecting"), true));
+        assert!(!flag(&args("selecting"), false));
+        assert!(!flag(&args("renewing"), true));
+        assert!(!flag(&args("rebinding"), true));
+        let ib = RequestArgs {
+            htype: v4::HType::Infiniband,
+            ..args("init-reboot")
+        };
+        assert!(flag(&ib, false));
+    }
+
+    #[test]
+    fn broadcast() {
+        let nets = [
+            (Ipv4Addr::new(10, 1, 2, 10), 24),
+            (Ipv4Addr::new(192, 0, 2, 1), 31),
+        ];
+        assert!(is_broadcast(Ipv4Addr::BROADCAST, &[]));
+        assert!(is_broadcast(Ipv4Addr::new(10, 1, 2, 255), &nets));
+        assert!(!is_broadcast(Ipv4Addr::new(10, 1, 2, 255), &[]));
+        assert!(!is_broadcast(Ipv4Addr::new(10, 1, 3, 255), &nets));
+        assert!(!is_broadcast(Ipv4Addr::new(10, 1, 2, 10), &nets));
+        assert!(!is_broadcast(Ipv4Addr::new(192, 0, 2, 1), &nets));
+    }
+}
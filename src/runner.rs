@@ -7,20 +7,25 @@ use std::{
 
 use anyhow::{Context, Result};
 use crossbeam_channel::{select, tick, Receiver, Sender};
-use tracing::{debug, error, info, trace};
+use tracing::{debug, error, info, trace, warn, Span};
 
 use dhcproto::{
     decoder::{Decodable, Decoder},
-    v4, v6,
+    v6,
 };
 
 use crate::{
+    auth, fqdn,
     opts::LogStructure,
+    overload, probe, relay,
     util::{Msg, PrettyPrint, PrettyTime},
-    Args, MsgType,
+    vendor, Args, MsgType,
 };
 
 const MAX_RETRIES: usize = 2;
+/// largest UDP payload, replies with long or overloaded options can be
+/// bigger than the 576 bytes every client must accept
+const MAX_UDP: usize = 65535;
 
 // Runner is still fundamentally written to send a single
 // DHCP message over a single socket at a time.
@@ -32,7 +37,7 @@ pub struct TimeoutRunner {
     pub args: Args,
     pub shutdown_rx: Receiver<()>,
     pub send_tx: Sender<(Msg, SocketAddr, bool)>,
-    pub recv_rx: Receiver<(Msg, SocketAddr)>,
+    pub recv_rx: Receiver<(Msg, SocketAddr, Vec<u8>)>,
 }
 
 impl TimeoutRunner {
@@ -46,15 +51,29 @@ impl TimeoutRunner {
         let timeout = tick(Duration::from_secs(self.args.timeout));
 
         // do send
-        self.send_msg()?;
+        let mut sent = self.send_msg()?;
         let mut count = 0;
         while count < MAX_RETRIES {
             select! {
                 // we will recv on this channel
                 recv(self.recv_rx) -> res => {
                     match res {
-                        Ok((msg, _addr)) => {
+                        Ok((msg, addr, raw)) => {
                             info!(msg_type = %msg.get_type(), elapsed = %PrettyTime(start.elapsed()), msg = %PrettyPrint(&msg, self.args.output), "RECEIVED");
+                            if let Some(key) = &self.args.auth_key {
+                                auth::check_reply(&raw, &msg, key, &self.args.auth_realm, &auth::REPLIES)?;
+                                debug!("reply authenticated");
+                            }
+                            if let (Some(_), Msg::V4(sent), Msg::V4(reply)) = (self.args.relay, &sent, &msg) {
+                                relay::check_reply(sent, reply, addr, self.args.get_target().0.port())?;
+                            }
+                            fqdn::report(&msg);
+                            vendor::report(&msg);
+                            if self.args.probe {
+                                if let Err(err) = probe::lease(self.args.interface(), &msg) {
+                                    warn!(%err, "probing offered address failed");
+                                }
+                            }
                             return Ok(msg);
                         }
                         Err(err) => {
@@ -73,7 +92,7 @@ impl TimeoutRunner {
                     debug!(elapsed = %PrettyTime(start.elapsed()), "received timeout-- retrying");
                     count += 1;
                     // try again
-                    self.send_msg()?;
+                    sent = self.send_msg()?;
                     start = Instant::now();
                     if self.args.no_retry {
                         break;
@@ -92,9 +111,16 @@ impl TimeoutRunner {
         ))
     }
 
-    fn send_msg(&mut self) -> Result<()> {
+    /// Generate a message from `Args` and send it without waiting for a
+    /// reply, for messages the server doesn't answer like DECLINE
+    pub fn send_only(mut self) -> Result<()> {
+        self.send_msg()?;
+        Ok(())
+    }
+
+    fn send_msg(&mut self) -> Result<Msg> {
         let (target, broadcast) = self.args.get_target();
-        let msg = match &self
+        let mut msg = match &self
             .args
             .msg
             .as_ref()
@@ -108,14 +134,35 @@ impl TimeoutRunner {
             MsgType::Decline(args) => Msg::V4(args.build()),
             // bootp
             MsgType::BootReq(args) => Msg::V4(args.build(broadcast)),
+            MsgType::LeaseQuery(args) => Msg::V4(args.build()?),
             // should be removed by now
-            MsgType::Dora(_) => panic!("should be removed in main"),
+            MsgType::Dora(_) | MsgType::Pxe(_) => panic!("should be removed in main"),
+            MsgType::BulkLeaseQuery(_) | MsgType::BulkLeaseQuery6(_) => {
+                panic!("bulk leasequery uses TCP")
+            }
             // dhcpv6
             MsgType::InformationReq(args) => Msg::V6(args.build()),
+            MsgType::Solicit(args) => Msg::V6(args.build()),
+            MsgType::LeaseQuery6(args) => Msg::V6(args.build()?),
         };
+        if let Msg::V4(msg) = &mut msg {
+            if let Some(key) = &self.args.auth_key {
+                auth::insert_v4(msg, key, self.args.auth_replay);
+            }
+            // opt 82 counts towards the max size
+            if let Some(giaddr) = self.args.relay {
+                relay::relay_msg(msg, giaddr, &self.args.relay_circuit_id);
+            }
+            if let Some(max_size) = self.args.max_size {
+                overload::pack(msg, max_size as usize)?;
+            }
+        }
+        if let Some(key) = &self.args.auth_key {
+            auth::sign(&mut msg, key, &self.args.auth_realm, self.args.auth_replay)?;
+        }
         trace!("send");
-        self.send_tx.send((msg, target, broadcast))?;
-        Ok(())
+        self.send_tx.send((msg.clone(), target, broadcast))?;
+        Ok(msg)
     }
 }
 
@@ -124,7 +171,10 @@ pub fn sender_thread(
     soc: Arc<UdpSocket>,
     output: LogStructure,
 ) {
+    // keep the caller's span (ex. the interface with multiple -i)
+    let span = Span::current();
     thread::spawn(move || {
+        let _span = span.enter();
         while let Ok((msg, target, brd)) = send_rx.recv() {
             let port = target.port();
             // set broadcast appropriately
@@ -145,21 +195,65 @@ pub fn sender_thread(
     });
 }
 
-pub fn recv_thread(tx: Sender<(Msg, SocketAddr)>, soc: Arc<UdpSocket>) {
+pub fn recv_thread(tx: Sender<(Msg, SocketAddr, Vec<u8>)>, soc: Arc<UdpSocket>) {
+    let span = Span::current();
     thread::spawn(move || {
-        let mut buf = vec![0; 1024];
+        let _span = span.enter();
+        let mut buf = vec![0; MAX_UDP];
         while let Ok((len, addr)) = soc.recv_from(&mut buf) {
             trace!(buf = ?&buf[..len], "recv");
             let msg = if addr.is_ipv6() {
                 Msg::V6(v6::Message::decode(&mut Decoder::new(&buf[..len]))?)
             } else {
-                Msg::V4(v4::Message::decode(&mut Decoder::new(&buf[..len]))?)
+                Msg::V4(overload::decode(&buf[..len])?)
             };
+            // keep the raw bytes, auth digests are computed over the message as sent
+            let raw = buf[..len].to_vec();
             // reset buffer
-            buf = vec![0; 1024];
-            tx.send_timeout((msg, addr), Duration::from_secs(1))?;
+            buf = vec![0; MAX_UDP];
+            tx.send_timeout((msg, addr, raw), Duration::from_secs(1))?;
         }
         trace!("recv thread exited");
         Ok::<_, anyhow::Error>(())
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crossbeam_channel::unbounded;
+    use dhcproto::{v4, Encodable};
+
+    use super::*;
+
+    #[test]
+    fn recv_large() {
+        let soc = Arc::new(UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap());
+        let addr = soc.local_addr().unwrap();
+        let (tx, rx) = unbounded();
+        recv_thread(tx, soc);
+
+        // a long option split in 255 byte chunks, larger than a 1500 MTU
+        let mut msg = v4::Message::default();
+        msg.opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Offer));
+        msg.opts_mut()
+            .insert(v4::DhcpOption::Unknown(v4::UnknownOption::new(
+                224.into(),
+                vec![7; 2000],
+            )));
+        let buf = msg.to_vec().unwrap();
+        assert!(buf.len() > 2000);
+        let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        client.send_to(&buf, addr).unwrap();
+
+        let (recv, _, raw) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(raw, buf);
+        let Msg::V4(recv) = recv else { unreachable!() };
+        assert_eq!(
+            recv.opts().get(v4::OptionCode::Unknown(224)),
+            msg.opts().get(v4::OptionCode::Unknown(224))
+        );
+    }
+}
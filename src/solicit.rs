@@ -0,0 +1,438 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use argh::FromArgs;
+use crossbeam_channel::{after, select, Receiver};
+use dhcproto::v6;
+use tracing::{debug, info, warn};
+
+use crate::{
+    auth,
+    fqdn::{self, parse_fqdn, parse_resolver, Fqdn},
+    opts::{
+        self, parse_mac,
+        v6::{parse_opts as parse_v6_opts, Opt as V6Opt, ParamList as V6ParamList},
+        Chaddr, LogStructure,
+    },
+    util::{Msg, PrettyPrint, PrettyTime},
+    vendor::{self, parse_vi_class, parse_vi_opt6, ViClass, ViOpt},
+};
+
+#[derive(FromArgs, PartialEq, Eq, Debug, Clone)]
+/// Send a SOLICIT msg (dhcpv6)
+#[argh(subcommand, name = "solicit")]
+pub struct SolicitArgs {
+    /// supply a mac address for the DUID-LL client id (use "random" for a random mac) [default: first interface mac]
+    #[argh(
+        option,
+        short = 'c',
+        from_str_fn(parse_mac),
+        default = "opts::get_mac()"
+    )]
+    pub chaddr: Chaddr,
+    /// IAID of the IA_NA [default: 1]
+    #[argh(option, default = "1")]
+    pub iaid: u32,
+    /// also ask for a prefix with an IA_PD using the same IAID [default: false]
+    #[argh(switch)]
+    pub pd: bool,
+    /// include rapid commit opt 14, the server may REPLY directly [default: false]
+    #[argh(switch)]
+    pub rapid_commit: bool,
+    /// include reconfigure accept opt 20 [default: false]
+    #[argh(switch)]
+    pub reconfigure_accept: bool,
+    /// after the reply, wait this many seconds for a RECONFIGURE on the client port
+    /// & validate it [default: None]
+    #[argh(option)]
+    pub wait_reconfigure: Option<u64>,
+    /// params to include: [default: 23,24]
+    #[argh(option, default = "default_params()")]
+    pub params: V6ParamList,
+    /// add opts to the message ("code,type,value", nest in an IA with "container/code")
+    /// [ex: "ia-na/ia-addr,ia-addr,2001:db8::1"]
+    #[argh(option, short = 'o', from_str_fn(parse_v6_opts))]
+    pub opt: Vec<V6Opt>,
+    /// client FQDN opt 39 as "<name>[,<flags>]", flags any of S, O & N [default: S]
+    /// [ex: "host.example.com,S"] [default: None]
+    #[argh(option, from_str_fn(parse_fqdn))]
+    pub fqdn: Option<Fqdn>,
+    /// after a rapid commit REPLY, query this resolver ("<ip>[:port]") for the AAAA, PTR
+    /// & DHCID records the server should have created for --fqdn [default: None]
+    #[argh(option, from_str_fn(parse_resolver))]
+    pub dns_verify: Option<SocketAddr>,
+    /// vendor class opt 16 as "<enterprise>:<data>[,<data>...]", can be repeated
+    /// [ex: "4491:docsis3.0"]
+    #[argh(option, from_str_fn(parse_vi_class))]
+    pub vi_class: Vec<ViClass>,
+    /// vendor-specific info opt 17 sub-option as "<enterprise>:<code>,<type>,<value>"
+    /// with the same types as --opt, can be repeated [ex: "4491:1,str,ABC123"]
+    #[argh(option, from_str_fn(parse_vi_opt6))]
+    pub vi_opt: Vec<ViOpt>,
+}
+
+fn default_params() -> V6ParamList {
+    V6ParamList(vec![
+        v6::OptionCode::DomainNameServers,
+        v6::OptionCode::DomainSearchList,
+    ])
+}
+
+impl Default for SolicitArgs {
+    fn default() -> Self {
+        Self {
+            chaddr: opts::get_mac(),
+            iaid: 1,
+            pd: false,
+            rapid_commit: false,
+            reconfigure_accept: false,
+            wait_reconfigure: None,
+            params: default_params(),
+            opt: Vec::new(),
+            fqdn: None,
+            dns_verify: None,
+            vi_class: Vec::new(),
+            vi_opt: Vec::new(),
+        }
+    }
+}
+
+impl SolicitArgs {
+    pub fn build(&self) -> v6::Message {
+        let mut msg = v6::Message::new(v6::MessageType::Solicit);
+        msg.opts_mut()
+            .insert(v6::DhcpOption::ClientId(opts::v6::duid_ll(self.chaddr)));
+        msg.opts_mut().insert(v6::DhcpOption::ElapsedTime(0));
+        msg.opts_mut().insert(v6::DhcpOption::ORO(v6::ORO {
+            opts: self.params.0.clone(),
+        }));
+        msg.opts_mut().insert(v6::DhcpOption::IANA(v6::IANA {
+            id: self.iaid,
+            t1: 0,
+            t2: 0,
+            opts: v6::DhcpOptions::new(),
+        }));
+        if self.pd {
+            msg.opts_mut().insert(v6::DhcpOption::IAPD(v6::IAPD {
+                id: self.iaid,
+                t1: 0,
+                t2: 0,
+                opts: v6::DhcpOptions::new(),
+            }));
+        }
+        if self.rapid_commit {
+            msg.opts_mut().insert(v6::DhcpOption::RapidCommit);
+        }
+        if self.reconfigure_accept {
+            msg.opts_mut().insert(v6::DhcpOption::ReconfAccept);
+        }
+        if let Some(fqdn) = &self.fqdn {
+            msg.opts_mut().insert(fqdn.v6());
+        }
+        // insert vendor class & vendor-specific info
+        for opt in vendor::v6_opts(&self.vi_class, &self.vi_opt) {
+            msg.opts_mut().insert(opt);
+        }
+        // insert manually entered opts
+        opts::v6::insert_opts(&mut msg, &self.opt);
+        msg
+    }
+
+    /// Check the flags go together: without rapid commit the flow stops at the
+    /// ADVERTISE, & the server only sends the reconfigure key in a REPLY to a
+    /// client that sent reconfigure accept (RFC 8415 section 20.4)
+    pub fn validate(&self) -> Result<()> {
+        if self.wait_reconfigure.is_some() && !(self.rapid_commit && self.reconfigure_accept) {
+            bail!("--wait-reconfigure needs --rapid-commit & --reconfigure-accept");
+        }
+        Ok(())
+    }
+
+    /// Check the reply to the SOLICIT, a REPLY must carry rapid commit
+    /// (RFC 8415 section 18.3.1)
+    pub fn check_reply(&self, reply: &v6::Message) -> Result<()> {
+        match reply.msg_type() {
+            v6::MessageType::Reply => {
+                if !self.rapid_commit {
+                    bail!("got a REPLY to SOLICIT without asking for rapid commit");
+                }
+                if reply.opts().get(v6::OptionCode::RapidCommit).is_none() {
+                    bail!("REPLY to SOLICIT is missing rapid commit opt 14");
+                }
+                info!("rapid commit REPLY, bindings committed");
+            }
+            v6::MessageType::Advertise if self.rapid_commit => {
+                info!("server didn't rapid commit");
+            }
+            v6::MessageType::Advertise => {}
+            other => warn!(msg_type = ?other, "expected an ADVERTISE or REPLY"),
+        }
+        if self.reconfigure_accept {
+            info!(
+                accepted = reply.opts().get(v6::OptionCode::ReconfAccept).is_some(),
+                has_key = auth::reconfigure_key(reply).is_some(),
+                "reconfigure accept"
+            );
+        }
+        Ok(())
+    }
+
+    /// check the DNS records for the binding in a rapid commit `reply` with
+    /// --dns-verify
+    pub fn verify_dns(&self, reply: &v6::Message, wait: Duration) -> Result<()> {
+        let Some(resolver) = self.dns_verify else {
+            return Ok(());
+        };
+        let Some(fqdn) = &self.fqdn else {
+            bail!("--dns-verify needs an --fqdn to check");
+        };
+        if reply.msg_type() != v6::MessageType::Reply {
+            bail!("no binding was committed to verify in DNS, use --rapid-commit");
+        }
+        fqdn::Expected::v6(fqdn, opts::v6::duid_ll(self.chaddr), reply)?.verify(resolver, wait)
+    }
+
+    /// Wait for a RECONFIGURE after `reply`, checking it's for this client
+    /// from the same server, has a valid reconfigure-message option &
+    /// authenticates with the reconfigure key from `reply`
+    pub fn wait_reconfigure(
+        &self,
+        reply: &v6::Message,
+        recv_rx: &Receiver<(Msg, SocketAddr, Vec<u8>)>,
+        shutdown_rx: &Receiver<()>,
+        wait: Duration,
+        output: LogStructure,
+    ) -> Result<()> {
+        if reply.msg_type() != v6::MessageType::Reply {
+            bail!("the server didn't rapid commit, only a REPLY has a reconfigure key");
+        }
+        let Some((last_replay, key)) = auth::reconfigure_key(reply) else {
+            bail!("the reply has no reconfigure key, a RECONFIGURE can't be authenticated");
+        };
+        let start = Instant::now();
+        let timeout = after(wait);
+        info!(wait = %PrettyTime(wait), "waiting for RECONFIGURE");
+        loop {
+            select! {
+                recv(recv_rx) -> res => {
+                    let (msg, addr, raw) = res?;
+                    match msg {
+                        Msg::V6(msg) if msg.msg_type() == v6::MessageType::Reconfigure => {
+                            info!(?addr, elapsed = %PrettyTime(start.elapsed()), msg = %PrettyPrint(&Msg::V6(msg.clone()), output), "RECEIVED RECONFIGURE");
+                            return self.check_reconfigure(reply, &msg, &raw, &key, last_replay);
+                        }
+                        msg => debug!(msg_type = %msg.get_type(), ?addr, "ignoring message while waiting for RECONFIGURE"),
+                    }
+                }
+                recv(shutdown_rx) -> _ => bail!("shutdown while waiting for RECONFIGURE"),
+                recv(timeout) -> _ => bail!("no RECONFIGURE received after {}", PrettyTime(wait)),
+            }
+        }
+    }
+
+    fn check_reconfigure(
+        &self,
+        reply: &v6::Message,
+        msg: &v6::Message,
+        raw: &[u8],
+        key: &[u8; 16],
+        last_replay: u64,
+    ) -> Result<()> {
+        let reconf = match msg.opts().get(v6::OptionCode::ReconfMsg) {
+            Some(v6::DhcpOption::ReconfMsg(ty)) => *ty,
+            _ => bail!("RECONFIGURE is missing reconfigure message opt 19"),
+        };
+        if !matches!(
+            reconf,
+            v6::MessageType::Renew | v6::MessageType::Rebind | v6::MessageType::InformationRequest
+        ) {
+            bail!("RECONFIGURE asks for {reconf:?}, expected Renew, Rebind or InformationRequest");
+        }
+        if msg.opts().get(v6::OptionCode::ClientId)
+            != Some(&v6::DhcpOption::ClientId(opts::v6::duid_ll(self.chaddr)))
+        {
+            bail!("RECONFIGURE client id doesn't match ours");
+        }
+        if msg.opts().get(v6::OptionCode::ServerId) != reply.opts().get(v6::OptionCode::ServerId) {
+            bail!("RECONFIGURE server id doesn't match the server that replied");
+        }
+        auth::check_reconfigure(raw, msg, key, last_replay)?;
+        info!(reconfigure = ?reconf, "RECONFIGURE is valid");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dhcproto::Encodable;
+
+    use super::*;
+
+    const KEY: [u8; 16] = [7; 16];
+
+    fn args() -> SolicitArgs {
+        SolicitArgs {
+            chaddr: [2, 0, 0, 0, 0, 1].into(),
+            rapid_commit: true,
+            reconfigure_accept: true,
+            ..Default::default()
+        }
+    }
+
+    fn auth(replay_detection: u64, info: Vec<u8>) -> v6::DhcpOption {
+        // reconfigure key protocol, HMAC-MD5, monotonic counter
+        v6::DhcpOption::Authentication(v6::Authentication {
+            proto: 3,
+            algo: 1,
+            rdm: 0,
+            replay_detection,
+            info,
+        })
+    }
+
+    /// a REPLY with a reconfigure key & a RECONFIGURE signed with it
+    fn reconfigure(replay: u64, ty: v6::MessageType) -> (v6::Message, v6::Message, Vec<u8>) {
+        let mut reply = v6::Message::new(v6::MessageType::Reply);
+        reply
+            .opts_mut()
+            .insert(v6::DhcpOption::ServerId(vec![0, 9]));
+        reply.opts_mut().insert(v6::DhcpOption::RapidCommit);
+        let mut key = vec![1];
+        key.extend(KEY);
+        reply.opts_mut().insert(auth(5, key));
+
+        let mut msg = v6::Message::new(v6::MessageType::Reconfigure);
+        msg.opts_mut().insert(v6::DhcpOption::ServerId(vec![0, 9]));
+        msg.opts_mut()
+            .insert(v6::DhcpOption::ClientId(opts::v6::duid_ll(args().chaddr)));
+        msg.opts_mut().insert(v6::DhcpOption::ReconfMsg(ty));
+        let mut digest = vec![2];
+        digest.extend([0; 16]);
+        msg.opts_mut().insert(auth(replay, digest));
+        let mac = auth::hmac_md5(&KEY, &msg.to_vec().unwrap());
+        if let Some(v6::DhcpOption::Authentication(auth)) =
+            msg.opts_mut().get_mut(v6::OptionCode::Authentication)
+        {
+            auth.info[1..].copy_from_slice(&mac);
+        }
+        let raw = msg.to_vec().unwrap();
+        (reply, msg, raw)
+    }
+
+    #[test]
+    fn build() {
+        let msg = args().build();
+        assert_eq!(
+            msg.opts().get(v6::OptionCode::RapidCommit),
+            Some(&v6::DhcpOption::RapidCommit)
+        );
+        assert_eq!(
+            msg.opts().get(v6::OptionCode::ReconfAccept),
+            Some(&v6::DhcpOption::ReconfAccept)
+        );
+        assert_eq!(msg.opts().get(v6::OptionCode::IAPD), None);
+    }
+
+    #[test]
+    fn reply() {
+        let (reply, ..) = reconfigure(6, v6::MessageType::Renew);
+        args().check_reply(&reply).unwrap();
+        assert_eq!(auth::reconfigure_key(&reply), Some((5, KEY)));
+        // a REPLY without asking for rapid commit
+        let no_rapid = SolicitArgs {
+            rapid_commit: false,
+            ..args()
+        };
+        assert!(no_rapid.check_reply(&reply).is_err());
+        let mut reply = reply;
+        reply.opts_mut().remove(v6::OptionCode::RapidCommit);
+        assert!(args().check_reply(&reply).is_err());
+    }
+
+    #[test]
+    fn valid_reconfigure() {
+        let (reply, msg, raw) = reconfigure(6, v6::MessageType::Renew);
+        args()
+            .check_reconfigure(&reply, &msg, &raw, &KEY, 5)
+            .unwrap();
+        // replayed
+        assert!(args()
+            .check_reconfigure(&reply, &msg, &raw, &KEY, 6)
+            .is_err());
+        assert!(args()
+            .check_reconfigure(&reply, &msg, &raw, &[0; 16], 5)
+            .is_err());
+        let other = SolicitArgs {
+            chaddr: [2, 0, 0, 0, 0, 2].into(),
+            ..args()
+        };
+        assert!(other
+            .check_reconfigure(&reply, &msg, &raw, &KEY, 5)
+            .is_err());
+    }
+
+    #[test]
+    fn invalid_reconfigure() {
+        let (reply, msg, raw) = reconfigure(6, v6::MessageType::Solicit);
+        assert!(args()
+            .check_reconfigure(&reply, &msg, &raw, &KEY, 5)
+            .is_err());
+        let (reply, mut msg, raw) = reconfigure(6, v6::MessageType::Rebind);
+        args()
+            .check_reconfigure(&reply, &msg, &raw, &KEY, 5)
+            .unwrap();
+        msg.opts_mut().remove(v6::OptionCode::ServerId);
+        msg.opts_mut().insert(v6::DhcpOption::ServerId(vec![0, 8]));
+        assert!(args()
+            .check_reconfigure(&reply, &msg, &raw, &KEY, 5)
+            .is_err());
+    }
+
+    #[test]
+    fn wait_reconfigure_flags() {
+        let parse = |args: &[&str]| SolicitArgs::from_args(&["solicit"], args).unwrap();
+        assert!(parse(&["--wait-reconfigure", "60"]).validate().is_err());
+        assert!(parse(&["--wait-reconfigure", "60", "--reconfigure-accept"])
+            .validate()
+            .is_err());
+        assert!(parse(&["--wait-reconfigure", "60", "--rapid-commit"])
+            .validate()
+            .is_err());
+        parse(&[
+            "--wait-reconfigure",
+            "60",
+            "--rapid-commit",
+            "--reconfigure-accept",
+        ])
+        .validate()
+        .unwrap();
+        parse(&["--rapid-commit"]).validate().unwrap();
+    }
+
+    #[test]
+    fn wait_after_advertise() {
+        // the server didn't rapid commit
+        let (mut reply, msg, raw) = reconfigure(6, v6::MessageType::Renew);
+        reply.set_msg_type(v6::MessageType::Advertise);
+        reply.opts_mut().remove(v6::OptionCode::RapidCommit);
+        args().check_reply(&reply).unwrap();
+        let (recv_tx, recv_rx) = crossbeam_channel::unbounded();
+        let (_shutdown_tx, shutdown_rx) = crossbeam_channel::unbounded();
+        recv_tx
+            .send((Msg::V6(msg), "[fe80::1]:547".parse().unwrap(), raw))
+            .unwrap();
+        let err = args()
+            .wait_reconfigure(
+                &reply,
+                &recv_rx,
+                &shutdown_rx,
+                Duration::from_millis(100),
+                LogStructure::Pretty,
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("didn't rapid commit"));
+    }
+}
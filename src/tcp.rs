@@ -0,0 +1,105 @@
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use tracing::trace;
+
+/// Connect to `target` over TCP, binding to `bind` first if given
+pub fn connect(
+    target: SocketAddr,
+    bind: Option<SocketAddr>,
+    timeout: Duration,
+) -> Result<TcpStream> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(target),
+        socket2::Type::STREAM,
+        None,
+    )?;
+    if let Some(bind) = bind {
+        socket
+            .bind(&bind.into())
+            .with_context(|| format!("failed to bind {bind}"))?;
+    }
+    socket
+        .connect_timeout(&target.into(), timeout)
+        .with_context(|| format!("failed to connect to {target}"))?;
+    let stream: TcpStream = socket.into();
+    stream.set_read_timeout(Some(timeout))?;
+    trace!(?target, "connected");
+    Ok(stream)
+}
+
+/// Write a message prefixed with its 2 byte length, the framing used for
+/// DHCP over TCP (RFC 6926 section 7.1, RFC 5460 section 5.1)
+pub fn send(stream: &mut TcpStream, msg: &[u8]) -> Result<()> {
+    let len = u16::try_from(msg.len()).context("message too large for TCP framing")?;
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(msg)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Read one length prefixed message, `None` if the connection was closed
+/// between messages
+pub fn recv(stream: &mut TcpStream) -> Result<Option<Vec<u8>>> {
+    let mut len = [0; 2];
+    match stream.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            bail!("timed out waiting for a message")
+        }
+        Err(err) => return Err(err.into()),
+    }
+    let mut buf = vec![0; u16::from_be_bytes(len) as usize];
+    stream
+        .read_exact(&mut buf)
+        .context("connection closed mid message")?;
+    trace!(?buf, "recv");
+    Ok(Some(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+
+    #[test]
+    fn framing() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let msg = recv(&mut stream).unwrap().unwrap();
+            send(&mut stream, &msg).unwrap();
+            send(&mut stream, &[]).unwrap();
+            // a length with no message after it
+            stream.write_all(&[0, 4, 1]).unwrap();
+        });
+        let mut stream = connect(addr, None, Duration::from_secs(5)).unwrap();
+        send(&mut stream, b"hello").unwrap();
+        assert_eq!(recv(&mut stream).unwrap().unwrap(), b"hello");
+        assert_eq!(recv(&mut stream).unwrap().unwrap(), b"");
+        server.join().unwrap();
+        assert!(recv(&mut stream).is_err());
+    }
+
+    #[test]
+    fn closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || drop(listener.accept().unwrap()));
+        let mut stream = connect(addr, None, Duration::from_secs(5)).unwrap();
+        server.join().unwrap();
+        assert_eq!(recv(&mut stream).unwrap(), None);
+    }
+}
@@ -0,0 +1,400 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use dhcproto::v4;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, trace, warn};
+
+use crate::{pxe, util::PrettyTime};
+
+pub const TFTP_PORT: u16 = 69;
+/// blksize to ask for, fits in a 1500 byte MTU (RFC 2348)
+pub const DEFAULT_BLKSIZE: u16 = 1468;
+/// blksize when the server doesn't accept options (RFC 1350)
+const RFC1350_BLKSIZE: usize = 512;
+const MAX_RETRIES: usize = 3;
+
+const RRQ: u16 = 1;
+const DATA: u16 = 3;
+const ACK: u16 = 4;
+const ERROR: u16 = 5;
+const OACK: u16 = 6;
+/// ERROR code for a refused option negotiation (RFC 2347)
+const ERR_OPTIONS: u16 = 8;
+
+/// result of a completed TFTP transfer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transfer {
+    pub size: usize,
+    pub sha256: String,
+    /// blksize used for the transfer
+    pub blksize: usize,
+    /// file size the server announced with the tsize option (RFC 2349)
+    pub tsize: Option<usize>,
+    pub elapsed: Duration,
+}
+
+/// RRQ for `file` in octet mode, asking for `blksize` & the transfer size
+/// unless it's `None` for a plain RFC 1350 request
+fn rrq(file: &str, blksize: Option<u16>) -> Vec<u8> {
+    let mut buf = RRQ.to_be_bytes().to_vec();
+    let mut fields = vec![file.to_owned(), "octet".to_owned()];
+    if let Some(blksize) = blksize {
+        fields.extend(["blksize".to_owned(), blksize.to_string()]);
+        fields.extend(["tsize".to_owned(), "0".to_owned()]);
+    }
+    for field in fields {
+        buf.extend(field.as_bytes());
+        buf.push(0);
+    }
+    buf
+}
+
+fn ack(block: u16) -> Vec<u8> {
+    [ACK.to_be_bytes(), block.to_be_bytes()].concat()
+}
+
+fn error(code: u16, msg: &str) -> Vec<u8> {
+    let mut buf = [ERROR.to_be_bytes(), code.to_be_bytes()].concat();
+    buf.extend(msg.as_bytes());
+    buf.push(0);
+    buf
+}
+
+/// parse OACK `name\0value\0` pairs (RFC 2347)
+fn parse_oack(buf: &[u8]) -> Vec<(String, String)> {
+    let fields = buf
+        .split(|b| *b == 0)
+        .map(|s| String::from_utf8_lossy(s).to_ascii_lowercase())
+        .collect::<Vec<_>>();
+    fields
+        .chunks_exact(2)
+        .map(|kv| (kv[0].clone(), kv[1].clone()))
+        .collect()
+}
+
+/// Fetch `file` from `server` with a read request, the file contents are
+/// only hashed, not saved
+pub fn fetch(server: SocketAddr, file: &str, blksize: u16, timeout: Duration) -> Result<Transfer> {
+    let soc = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).context("bind tftp socket")?;
+    soc.set_read_timeout(Some(timeout))?;
+
+    let start = Instant::now();
+    let mut last = rrq(file, Some(blksize));
+    let mut options = true;
+    soc.send_to(&last, server)?;
+    debug!(?server, file, blksize, "sent tftp RRQ");
+
+    // the server replies from a new port, its transfer id (RFC 1350 section 4)
+    let mut tid: Option<SocketAddr> = None;
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    let mut block: u16 = 1;
+    let mut cur_blksize = RFC1350_BLKSIZE;
+    let mut tsize = None;
+    let mut retries = 0;
+    let mut buf = vec![0; (blksize as usize).max(RFC1350_BLKSIZE) + 4];
+    loop {
+        let (len, from) = match soc.recv_from(&mut buf) {
+            Ok(res) => res,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                retries += 1;
+                if retries > MAX_RETRIES {
+                    bail!("tftp timed out waiting for block {block}");
+                }
+                debug!(block, retries, "tftp timeout-- retrying");
+                soc.send_to(&last, tid.unwrap_or(server))?;
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        match tid {
+            Some(tid) if tid != from => {
+                trace!(?from, "ignoring packet from unknown transfer id");
+                continue;
+            }
+            None if from.ip() != server.ip() => {
+                trace!(?from, "ignoring packet from unknown host");
+                continue;
+            }
+            None => tid = Some(from),
+            _ => {}
+        }
+        let pkt = &buf[..len];
+        if pkt.len() < 4 {
+            bail!("tftp packet too short ({} bytes)", pkt.len());
+        }
+        let op = u16::from_be_bytes([pkt[0], pkt[1]]);
+        match op {
+            OACK if block == 1 => {
+                for (name, val) in parse_oack(&pkt[2..]) {
+                    match name.as_str() {
+                        "blksize" => {
+                            let n: usize = val.parse().context("invalid blksize in OACK")?;
+                            // `buf` only fits the blksize we asked for (RFC 2348)
+                            if !(8..=blksize as usize).contains(&n) {
+                                soc.send_to(&error(ERR_OPTIONS, "blksize"), from)?;
+                                bail!("OACK blksize {n} isn't between 8 & the {blksize} asked for");
+                            }
+                            cur_blksize = n;
+                        }
+                        "tsize" => tsize = val.parse().ok(),
+                        _ => warn!(name, val, "unknown option in OACK"),
+                    }
+                }
+                debug!(blksize = cur_blksize, ?tsize, "tftp OACK");
+                last = ack(0);
+            }
+            DATA => {
+                let n = u16::from_be_bytes([pkt[2], pkt[3]]);
+                let data = &pkt[4..];
+                if n == block {
+                    hasher.update(data);
+                    size += data.len();
+                    retries = 0;
+                    block = block.wrapping_add(1);
+                    last = ack(n);
+                    if data.len() < cur_blksize {
+                        soc.send_to(&last, from)?;
+                        break;
+                    }
+                } else {
+                    // duplicate, the server missed our ACK
+                    trace!(n, block, "duplicate tftp block");
+                    last = ack(n);
+                }
+            }
+            ERROR => {
+                let code = u16::from_be_bytes([pkt[2], pkt[3]]);
+                let msg = String::from_utf8_lossy(&pkt[4..]);
+                // the server refused the options, retry without them (RFC 2347)
+                if code == ERR_OPTIONS && options && block == 1 {
+                    warn!(msg = %msg.trim_end_matches('\0'), "tftp server refused options, retrying a plain RRQ");
+                    options = false;
+                    tid = None;
+                    retries = 0;
+                    last = rrq(file, None);
+                    soc.send_to(&last, server)?;
+                    continue;
+                }
+                bail!("tftp error {code}: {}", msg.trim_end_matches('\0'));
+            }
+            op => bail!("unexpected tftp opcode {op}"),
+        }
+        soc.send_to(&last, from)?;
+    }
+    Ok(Transfer {
+        size,
+        sha256: hex::encode(hasher.finalize()),
+        blksize: cur_blksize,
+        tsize,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Fetch the boot file offered in `msg` over TFTP from `siaddr`, or opt 66 or
+/// the server identifier, and log the size, checksum & time it took
+pub fn fetch_boot_file(
+    msg: &v4::Message,
+    port: u16,
+    blksize: u16,
+    timeout: Duration,
+) -> Result<()> {
+    let file = pxe::bootfile(msg).context("reply has no boot file to fetch")?;
+    let server = Some(msg.siaddr())
+        .filter(|ip| !ip.is_unspecified())
+        .map(IpAddr::V4)
+        .or_else(|| {
+            let name = pxe::tftp_server(msg)?;
+            (name.as_str(), port)
+                .to_socket_addrs()
+                .ok()?
+                .map(|addr| addr.ip())
+                .find(IpAddr::is_ipv4)
+        })
+        .or_else(|| match msg.opts().get(v4::OptionCode::ServerIdentifier) {
+            Some(v4::DhcpOption::ServerIdentifier(ip)) => Some(IpAddr::V4(*ip)),
+            _ => None,
+        })
+        .context("reply has no next-server, tftp server name or server identifier")?;
+    let server = SocketAddr::new(server, port);
+    info!(%server, file, "fetching boot file over TFTP");
+
+    let transfer = fetch(server, &file, blksize, timeout)?;
+    info!(
+        file,
+        size = transfer.size,
+        sha256 = transfer.sha256,
+        blksize = transfer.blksize,
+        elapsed = %PrettyTime(transfer.elapsed),
+        "TFTP transfer complete"
+    );
+    if let Some(tsize) = transfer.tsize.filter(|tsize| *tsize != transfer.size) {
+        bail!(
+            "server announced tsize {tsize} but sent {} bytes",
+            transfer.size
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::{self, JoinHandle};
+
+    use super::*;
+
+    #[test]
+    fn rrq_options() {
+        assert_eq!(
+            rrq("pxelinux.0", Some(1468)),
+            b"\x00\x01pxelinux.0\0octet\0blksize\x001468\0tsize\x000\0"
+        );
+    }
+
+    #[test]
+    fn rrq_plain() {
+        assert_eq!(rrq("pxelinux.0", None), b"\x00\x01pxelinux.0\0octet\0");
+    }
+
+    #[test]
+    fn oack() {
+        assert_eq!(
+            parse_oack(b"BLKSIZE\x001468\0tsize\x0042\0"),
+            [
+                ("blksize".to_owned(), "1468".to_owned()),
+                ("tsize".to_owned(), "42".to_owned())
+            ]
+        );
+    }
+
+    /// a server for one transfer of `data` on 127.0.0.1, it answers an RRQ
+    /// with options with `oack`, ERROR 8 if it's `None` or ignores them if
+    /// it's empty. Returns the requests & ACKs for the OACK it got
+    fn serve(data: Vec<u8>, oack: Option<&'static str>) -> (SocketAddr, JoinHandle<Vec<Vec<u8>>>) {
+        let listen = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        listen
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let addr = listen.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut reqs = vec![];
+            let mut buf = [0; 1024];
+            loop {
+                let (len, client) = listen.recv_from(&mut buf).unwrap();
+                let req = buf[..len].to_vec();
+                reqs.push(req.clone());
+                // a new transfer id for each request
+                let soc = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+                soc.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+                let recv = |soc: &UdpSocket| {
+                    let mut buf = [0; 1024];
+                    let len = soc.recv(&mut buf).unwrap();
+                    buf[..len].to_vec()
+                };
+                let mut blksize = RFC1350_BLKSIZE;
+                match oack {
+                    // no options asked for, or ignore them like an RFC 1350 server
+                    _ if parse_oack(&req[2..]).len() < 2 => {}
+                    Some("") => {}
+                    None => {
+                        soc.send_to(&error(ERR_OPTIONS, "no options"), client)
+                            .unwrap();
+                        continue;
+                    }
+                    Some(oack) => {
+                        let mut pkt = OACK.to_be_bytes().to_vec();
+                        pkt.extend(oack.replace(',', "\0").as_bytes());
+                        pkt.push(0);
+                        soc.send_to(&pkt, client).unwrap();
+                        let reply = recv(&soc);
+                        reqs.push(reply.clone());
+                        if reply != ack(0) {
+                            return reqs;
+                        }
+                        blksize = parse_oack(&pkt[2..])[0].1.parse().unwrap();
+                    }
+                }
+                // always ends with a short block, empty if it's a multiple of blksize
+                for (i, chunk) in data
+                    .chunks(blksize)
+                    .chain([&[][..]])
+                    .take(data.len() / blksize + 1)
+                    .enumerate()
+                {
+                    let n = i as u16 + 1;
+                    let mut pkt = [DATA.to_be_bytes(), n.to_be_bytes()].concat();
+                    pkt.extend(chunk);
+                    soc.send_to(&pkt, client).unwrap();
+                    assert_eq!(recv(&soc), ack(n));
+                }
+                return reqs;
+            }
+        });
+        (addr, handle)
+    }
+
+    fn data() -> Vec<u8> {
+        (0..2000).map(|i| i as u8).collect()
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    #[test]
+    fn fetch_oack() {
+        let (addr, server) = serve(data(), Some("blksize,1024,tsize,2000"));
+        let transfer = fetch(addr, "boot", DEFAULT_BLKSIZE, Duration::from_secs(5)).unwrap();
+        assert_eq!(transfer.size, 2000);
+        assert_eq!(transfer.sha256, sha256(&data()));
+        assert_eq!(transfer.blksize, 1024);
+        assert_eq!(transfer.tsize, Some(2000));
+        let reqs = server.join().unwrap();
+        assert_eq!(reqs, [rrq("boot", Some(DEFAULT_BLKSIZE)), ack(0)]);
+    }
+
+    #[test]
+    fn fetch_plain() {
+        // 4 full blocks & an empty one
+        let data = vec![1; 2048];
+        let (addr, server) = serve(data.clone(), Some(""));
+        let transfer = fetch(addr, "boot", DEFAULT_BLKSIZE, Duration::from_secs(5)).unwrap();
+        assert_eq!(transfer.size, 2048);
+        assert_eq!(transfer.sha256, sha256(&data));
+        assert_eq!(transfer.blksize, RFC1350_BLKSIZE);
+        assert_eq!(server.join().unwrap(), [rrq("boot", Some(DEFAULT_BLKSIZE))]);
+    }
+
+    #[test]
+    fn fetch_refused_options() {
+        let (addr, server) = serve(data(), None);
+        let transfer = fetch(addr, "boot", DEFAULT_BLKSIZE, Duration::from_secs(5)).unwrap();
+        assert_eq!(transfer.size, 2000);
+        assert_eq!(transfer.sha256, sha256(&data()));
+        assert_eq!(transfer.blksize, RFC1350_BLKSIZE);
+        assert_eq!(transfer.tsize, None);
+        let reqs = server.join().unwrap();
+        assert_eq!(
+            reqs,
+            [rrq("boot", Some(DEFAULT_BLKSIZE)), rrq("boot", None)]
+        );
+    }
+
+    #[test]
+    fn fetch_larger_blksize() {
+        let (addr, server) = serve(data(), Some("blksize,1024"));
+        assert!(fetch(addr, "boot", 512, Duration::from_secs(5)).is_err());
+        let reqs = server.join().unwrap();
+        assert_eq!(reqs[1], error(ERR_OPTIONS, "blksize"));
+    }
+}
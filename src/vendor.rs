@@ -0,0 +1,404 @@
+use dhcproto::{v4, v6, Encodable};
+use serde_json::{Map, Value};
+use tracing::info;
+
+use crate::{opts, util::Msg};
+
+/// V-I vendor class opt 124 (RFC 3925)
+const VI_CLASS: u8 = 124;
+/// V-I vendor-specific information opt 125 (RFC 3925)
+const VI_OPTS: u8 = 125;
+
+/// A vendor class for an enterprise number, v4 opt 124 or v6 opt 16
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ViClass {
+    pub enterprise: u32,
+    pub data: Vec<Vec<u8>>,
+}
+
+/// A vendor-specific sub-option for an enterprise number, v4 opt 125 or
+/// v6 opt 17
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ViOpt {
+    pub enterprise: u32,
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+fn parse_enterprise(input: &str) -> Result<(u32, &str), String> {
+    let (enterprise, rest) = input
+        .split_once(':')
+        .ok_or_else(|| format!("{input:?} must start with \"<enterprise number>:\""))?;
+    let enterprise = enterprise
+        .parse::<u32>()
+        .map_err(|e| format!("invalid enterprise number {enterprise:?}: {e}"))?;
+    Ok((enterprise, rest))
+}
+
+/// parse "<enterprise>:<data>[,<data>...]", each data item a string
+pub fn parse_vi_class(input: &str) -> Result<ViClass, String> {
+    let (enterprise, data) = parse_enterprise(input)?;
+    let data = data
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.as_bytes().to_vec())
+        .collect::<Vec<_>>();
+    if data.iter().map(|d| d.len() + 1).sum::<usize>() > 255 {
+        return Err(format!(
+            "vendor class data in {input:?} is longer than 255 bytes"
+        ));
+    }
+    Ok(ViClass { enterprise, data })
+}
+
+/// parse "<enterprise>:<code>,<type>,<value>" with the same types as `-o`
+pub fn parse_vi_opt(input: &str) -> Result<ViOpt, String> {
+    let (enterprise, opt) = parse_enterprise(input)?;
+    match &opt.splitn(3, ',').collect::<Vec<&str>>()[..] {
+        [code, ty, val] => {
+            let code = code
+                .parse::<u8>()
+                .map_err(|e| format!("invalid sub-option code {code:?}: {e}"))?;
+            let data = opts::encode_value(ty, val)?;
+            if data.len() > 253 {
+                return Err(format!("sub-option {code} is longer than 253 bytes"));
+            }
+            Ok(ViOpt {
+                enterprise,
+                code: code.into(),
+                data,
+            })
+        }
+        _ => Err(format!(
+            "parsing {input:?} failed, expected \"<enterprise>:<code>,<type>,<value>\""
+        )),
+    }
+}
+
+/// parse "<enterprise>:<code>,<type>,<value>" with the same types as v6 `-o`
+pub fn parse_vi_opt6(input: &str) -> Result<ViOpt, String> {
+    let (enterprise, opt) = parse_enterprise(input)?;
+    match &opt.splitn(3, ',').collect::<Vec<&str>>()[..] {
+        [code, ty, val] => Ok(ViOpt {
+            enterprise,
+            code: code
+                .parse::<u16>()
+                .map_err(|e| format!("invalid sub-option code {code:?}: {e}"))?,
+            data: opts::v6::encode_value(ty, val)?,
+        }),
+        _ => Err(format!(
+            "parsing {input:?} failed, expected \"<enterprise>:<code>,<type>,<value>\""
+        )),
+    }
+}
+
+/// opts 60, 124 & 125 to add to a v4 message
+pub fn v4_opts(class: Option<&str>, vi_class: &[ViClass], vi_opt: &[ViOpt]) -> Vec<v4::DhcpOption> {
+    let mut opts = Vec::new();
+    if let Some(class) = class {
+        opts.push(v4::DhcpOption::ClassIdentifier(class.as_bytes().to_vec()));
+    }
+    if !vi_class.is_empty() {
+        let mut buf = Vec::new();
+        for class in vi_class {
+            buf.extend(class.enterprise.to_be_bytes());
+            buf.push(class.data.iter().map(|d| d.len() as u8 + 1).sum());
+            for data in &class.data {
+                buf.push(data.len() as u8);
+                buf.extend(data);
+            }
+        }
+        opts.push(unknown_v4(VI_CLASS, buf));
+    }
+    if !vi_opt.is_empty() {
+        let mut buf = Vec::new();
+        for (enterprise, subopts) in group(vi_opt) {
+            // data-len is a single byte, an enterprise with more sub-options
+            // than fit is continued in another block with the same number
+            let mut block = Vec::new();
+            for opt in subopts {
+                if block.len() + 2 + opt.data.len() > 255 {
+                    push_block(&mut buf, enterprise, &block);
+                    block.clear();
+                }
+                block.push(opt.code as u8);
+                block.push(opt.data.len() as u8);
+                block.extend(&opt.data);
+            }
+            push_block(&mut buf, enterprise, &block);
+        }
+        opts.push(unknown_v4(VI_OPTS, buf));
+    }
+    opts
+}
+
+fn push_block(buf: &mut Vec<u8>, enterprise: u32, block: &[u8]) {
+    buf.extend(enterprise.to_be_bytes());
+    buf.push(block.len() as u8);
+    buf.extend(block);
+}
+
+fn unknown_v4(code: u8, data: Vec<u8>) -> v4::DhcpOption {
+    v4::DhcpOption::Unknown(v4::UnknownOption::new(code.into(), data))
+}
+
+/// opts 16 & 17 to add to a v6 message, one per enterprise number. Both are
+/// encoded by hand as dhcproto writes the wrong length for VendorClass
+pub fn v6_opts(vi_class: &[ViClass], vi_opt: &[ViOpt]) -> Vec<v6::DhcpOption> {
+    let mut opts = Vec::new();
+    for class in vi_class {
+        let mut buf = class.enterprise.to_be_bytes().to_vec();
+        for data in &class.data {
+            buf.extend((data.len() as u16).to_be_bytes());
+            buf.extend(data);
+        }
+        opts.push(v6::DhcpOption::Unknown(v6::UnknownOption::new(
+            v6::OptionCode::VendorClass,
+            buf,
+        )));
+    }
+    for (enterprise, subopts) in group(vi_opt) {
+        let mut buf = enterprise.to_be_bytes().to_vec();
+        for opt in subopts {
+            buf.extend(opt.code.to_be_bytes());
+            buf.extend((opt.data.len() as u16).to_be_bytes());
+            buf.extend(&opt.data);
+        }
+        opts.push(v6::DhcpOption::Unknown(v6::UnknownOption::new(
+            v6::OptionCode::VendorOpts,
+            buf,
+        )));
+    }
+    opts
+}
+
+/// group sub-options by enterprise number, in the order first given
+fn group(opts: &[ViOpt]) -> Vec<(u32, Vec<&ViOpt>)> {
+    let mut groups: Vec<(u32, Vec<&ViOpt>)> = Vec::new();
+    for opt in opts {
+        match groups.iter_mut().find(|(e, _)| *e == opt.enterprise) {
+            Some((_, group)) => group.push(opt),
+            None => groups.push((opt.enterprise, vec![opt])),
+        }
+    }
+    groups
+}
+
+/// log opt 43 & 125 (v4) or opt 17 (v6) sub-options in a reply as trees keyed
+/// by enterprise number (or the vendor class for opt 43)
+pub fn report(msg: &Msg) {
+    match msg {
+        Msg::V4(msg) => {
+            if let Some(v4::DhcpOption::VendorExtensions(buf)) =
+                msg.opts().get(v4::OptionCode::VendorExtensions)
+            {
+                let class = match msg.opts().get(v4::OptionCode::ClassIdentifier) {
+                    Some(v4::DhcpOption::ClassIdentifier(class)) => {
+                        String::from_utf8_lossy(class).into_owned()
+                    }
+                    _ => "43".to_owned(),
+                };
+                let mut tree = Map::new();
+                tree.insert(class, decode_subopts(buf, 1).unwrap_or_else(|| render(buf)));
+                let tree = Value::Object(tree);
+                info!(vendor = %tree, "vendor extensions (opt 43)");
+            }
+            if let Some(v4::DhcpOption::Unknown(opt)) =
+                msg.opts().get(v4::OptionCode::Unknown(VI_OPTS))
+            {
+                let mut tree = Map::new();
+                let mut buf = opt.data();
+                while buf.len() >= 5 {
+                    let enterprise = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+                    let len = buf[4] as usize;
+                    let Some(block) = buf.get(5..5 + len) else {
+                        break;
+                    };
+                    insert_enterprise(&mut tree, enterprise, block, 1);
+                    buf = &buf[5 + len..];
+                }
+                let tree = Value::Object(tree);
+                info!(vendor = %tree, "vendor-specific info (opt 125)");
+            }
+        }
+        Msg::V6(msg) => {
+            let mut tree = Map::new();
+            for opt in msg.opts().iter() {
+                let (enterprise, block) = match opt {
+                    v6::DhcpOption::VendorOpts(opts) => {
+                        (opts.num, opts.opts.to_vec().unwrap_or_default())
+                    }
+                    v6::DhcpOption::Unknown(opt)
+                        if opt.code() == v6::OptionCode::VendorOpts && opt.data().len() >= 4 =>
+                    {
+                        let (num, block) = opt.data().split_at(4);
+                        (
+                            u32::from_be_bytes([num[0], num[1], num[2], num[3]]),
+                            block.to_vec(),
+                        )
+                    }
+                    _ => continue,
+                };
+                insert_enterprise(&mut tree, enterprise, &block, 2);
+            }
+            if !tree.is_empty() {
+                let tree = Value::Object(tree);
+                info!(vendor = %tree, "vendor-specific info (opt 17)");
+            }
+        }
+    }
+}
+
+/// add the sub-options in `block` under `enterprise`, merging repeated blocks
+fn insert_enterprise(tree: &mut Map<String, Value>, enterprise: u32, block: &[u8], width: usize) {
+    let subopts = decode_subopts(block, width).unwrap_or_else(|| render(block));
+    match (tree.get_mut(&enterprise.to_string()), subopts) {
+        (Some(Value::Object(existing)), Value::Object(subopts)) => existing.extend(subopts),
+        (_, subopts) => {
+            tree.insert(enterprise.to_string(), subopts);
+        }
+    }
+}
+
+/// decode code/len/value sub-options where code & len are `width` bytes,
+/// `None` if `buf` isn't well formed
+fn decode_subopts(mut buf: &[u8], width: usize) -> Option<Value> {
+    let int = |b: &[u8]| b.iter().fold(0_usize, |acc, b| acc << 8 | *b as usize);
+    let mut opts = Map::new();
+    while !buf.is_empty() {
+        // v4 pad & end
+        if width == 1 && buf[0] == 0 {
+            buf = &buf[1..];
+            continue;
+        }
+        if width == 1 && buf[0] == 255 {
+            break;
+        }
+        let code = int(buf.get(..width)?);
+        let len = int(buf.get(width..width * 2)?);
+        let val = buf.get(width * 2..width * 2 + len)?;
+        opts.insert(code.to_string(), render(val));
+        buf = &buf[width * 2 + len..];
+    }
+    Some(Value::Object(opts))
+}
+
+/// printable ASCII as a string, anything else as 0x prefixed hex
+fn render(val: &[u8]) -> Value {
+    if !val.is_empty() && val.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        Value::String(String::from_utf8_lossy(val).into_owned())
+    } else {
+        Value::String(format!("0x{}", hex::encode(val)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(opt: &v4::DhcpOption) -> String {
+        match opt {
+            v4::DhcpOption::Unknown(opt) => hex::encode(opt.data()),
+            opt => panic!("{opt:?}"),
+        }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            parse_vi_class("4491:docsis3.0,x").unwrap(),
+            ViClass {
+                enterprise: 4491,
+                data: vec![b"docsis3.0".to_vec(), b"x".to_vec()]
+            }
+        );
+        assert_eq!(
+            parse_vi_opt("3561:1,str,AB").unwrap(),
+            ViOpt {
+                enterprise: 3561,
+                code: 1,
+                data: b"AB".to_vec()
+            }
+        );
+        assert_eq!(parse_vi_opt6("4491:300,u16,1").unwrap().code, 300);
+        assert!(parse_vi_opt("3561:300,str,AB").is_err());
+        assert!(parse_vi_opt("3561:1,str").is_err());
+        assert!(parse_vi_class("docsis3.0").is_err());
+        assert!(parse_vi_class(&format!("1:{}", "a".repeat(255))).is_err());
+    }
+
+    // RFC 3925 sections 3 & 4
+    #[test]
+    fn v4() {
+        let opts = v4_opts(
+            Some("PXEClient"),
+            &[parse_vi_class("4491:ab").unwrap()],
+            &[
+                parse_vi_opt("3561:1,str,A").unwrap(),
+                parse_vi_opt("9:2,u8,5").unwrap(),
+                parse_vi_opt("3561:3,hex,ff").unwrap(),
+            ],
+        );
+        assert_eq!(
+            opts[0],
+            v4::DhcpOption::ClassIdentifier(b"PXEClient".to_vec())
+        );
+        assert_eq!(data(&opts[1]), "0000118b03026162");
+        // sub-options are grouped by enterprise
+        assert_eq!(data(&opts[2]), "00000de9060101410301ff0000000903020105");
+    }
+
+    #[test]
+    fn v4_long_block() {
+        let big = format!("1:1,hex,{}", "00".repeat(200));
+        let opts = v4_opts(
+            None,
+            &[],
+            &[parse_vi_opt(&big).unwrap(), parse_vi_opt(&big).unwrap()],
+        );
+        let buf = match &opts[0] {
+            v4::DhcpOption::Unknown(opt) => opt.data().to_vec(),
+            opt => panic!("{opt:?}"),
+        };
+        // two blocks for enterprise 1, each with one sub-option
+        assert_eq!(buf.len(), 2 * (5 + 202));
+        assert_eq!(buf[..5], [0, 0, 0, 1, 202]);
+        assert_eq!(buf[207..212], [0, 0, 0, 1, 202]);
+    }
+
+    // RFC 8415 sections 21.16 & 21.17
+    #[test]
+    fn v6() {
+        let opts = v6_opts(
+            &[parse_vi_class("4491:ab").unwrap()],
+            &[parse_vi_opt6("4491:1,str,A").unwrap()],
+        );
+        let data = opts
+            .iter()
+            .map(|opt| match opt {
+                v6::DhcpOption::Unknown(opt) => (u16::from(opt.code()), hex::encode(opt.data())),
+                opt => panic!("{opt:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            data,
+            [
+                (16, "0000118b00026162".to_owned()),
+                (17, "0000118b0001000141".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(
+            decode_subopts(&[1, 2, b'h', b'i', 0, 2, 1, 0xff, 255, 9], 1),
+            Some(serde_json::json!({"1": "hi", "2": "0xff"}))
+        );
+        assert_eq!(
+            decode_subopts(&[0, 1, 0, 1, b'x'], 2),
+            Some(serde_json::json!({"1": "x"}))
+        );
+        assert_eq!(decode_subopts(&[1, 5, 0], 1), None);
+    }
+}